@@ -0,0 +1,24 @@
+--
+-- named credentials that index urls can reference with `?credential=name`
+--
+
+CREATE TABLE zdb.credentials
+(
+    name                  text NOT NULL PRIMARY KEY,
+    auth_type             text NOT NULL CHECK (auth_type IN ('basic', 'aws_iam', 'api_key', 'bearer')),
+    username              text,
+    password              text,
+    api_key               text,
+    token                 text,
+    aws_access_key_id     text,
+    aws_secret_access_key text,
+    aws_region            text,
+    aws_service_name      text,
+    aws_unsigned_payload  boolean NOT NULL DEFAULT false,
+    urls                  text[]
+);
+
+SELECT pg_catalog.pg_extension_config_dump('zdb.credentials', '');
+
+-- only superusers can see or change credentials.  ZomboDB reads them as the table's owner
+REVOKE ALL ON zdb.credentials FROM PUBLIC;
@@ -71,6 +71,12 @@ CREATE TABLE zdb.similarities
     definition jsonb
 );
 
+CREATE TABLE zdb.synonym_sets
+(
+    name  text   NOT NULL PRIMARY KEY,
+    rules text[] NOT NULL
+);
+
 SELECT pg_catalog.pg_extension_config_dump('zdb.filters', 'WHERE NOT is_default');
 SELECT pg_catalog.pg_extension_config_dump('zdb.char_filters', 'WHERE NOT is_default');
 SELECT pg_catalog.pg_extension_config_dump('zdb.analyzers', 'WHERE NOT is_default');
@@ -80,6 +86,7 @@ SELECT pg_catalog.pg_extension_config_dump('zdb.tokenizers', 'WHERE NOT is_defau
 SELECT pg_catalog.pg_extension_config_dump('zdb.type_mappings', 'WHERE NOT is_default');
 SELECT pg_catalog.pg_extension_config_dump('zdb.type_conversions', 'WHERE NOT is_default');
 SELECT pg_catalog.pg_extension_config_dump('zdb.similarities', '');
+SELECT pg_catalog.pg_extension_config_dump('zdb.synonym_sets', '');
 
 
 CREATE OR REPLACE FUNCTION zdb.define_filter(name text, definition json) RETURNS void
@@ -191,6 +198,17 @@ $$
     INSERT INTO zdb.similarities(name, definition) VALUES ($1, $2);
 $$;
 
+CREATE OR REPLACE FUNCTION zdb.define_synonym_set(name text, rules text[]) RETURNS void
+    LANGUAGE sql
+    VOLATILE STRICT AS
+$$
+DELETE
+FROM zdb.synonym_sets
+WHERE name = $1;
+INSERT INTO zdb.synonym_sets(name, rules)
+VALUES ($1, $2);
+$$;
+
 INSERT INTO zdb.filters(name, definition, is_default)
 VALUES ('zdb_truncate_to_fit', '{
   "type": "truncate",
@@ -539,6 +557,18 @@ CREATE DOMAIN zdb.turkish AS text;
 CREATE DOMAIN zdb.thai AS text;
 CREATE DOMAIN zdb.whitespace AS text;
 
+--
+-- search-as-you-type support
+--
+
+CREATE DOMAIN zdb.search_as_you_type AS text;
+
+INSERT INTO zdb.type_mappings(type_name, definition, is_default)
+VALUES ('zdb.search_as_you_type', '{
+  "type": "search_as_you_type",
+  "copy_to": "zdb_all"
+}', true) ON CONFLICT (type_name) DO UPDATE SET definition = excluded.definition;
+
 --
 -- emoji analyzer support
 --
@@ -576,6 +606,7 @@ GRANT ALL ON zdb.char_filters TO PUBLIC;
 GRANT ALL ON zdb.filters TO PUBLIC;
 GRANT ALL ON zdb.mappings TO PUBLIC;
 GRANT ALL ON zdb.similarities TO PUBLIC;
+GRANT ALL ON zdb.synonym_sets TO PUBLIC;
 GRANT ALL ON zdb.tokenizers TO PUBLIC;
 GRANT ALL ON zdb.type_mappings TO PUBLIC;
 GRANT ALL ON zdb.normalizers TO PUBLIC;
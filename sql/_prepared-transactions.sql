@@ -0,0 +1,14 @@
+--
+-- two-phase commit support
+--
+
+CREATE TABLE zdb.prepared_transactions
+(
+    gid        text   NOT NULL,
+    indexrelid oid    NOT NULL,
+    xid        bigint NOT NULL,
+    PRIMARY KEY (gid, indexrelid, xid)
+);
+
+-- only ZomboDB changes this, as the table's owner, on behalf of whoever prepares or commits a transaction
+REVOKE ALL ON zdb.prepared_transactions FROM PUBLIC;
@@ -0,0 +1,26 @@
+--
+-- saved query support
+--
+
+CREATE TABLE zdb.saved_queries
+(
+    name   text               NOT NULL PRIMARY KEY,
+    query  text               NOT NULL,
+    params jsonb DEFAULT '{}' NOT NULL,
+    CHECK (zdb.validate_saved_query(query, params))
+);
+
+SELECT pg_catalog.pg_extension_config_dump('zdb.saved_queries', '');
+
+CREATE OR REPLACE FUNCTION zdb.define_saved_query(name text, query text, params jsonb DEFAULT '{}') RETURNS void
+    LANGUAGE sql
+    VOLATILE STRICT AS
+$$
+DELETE
+FROM zdb.saved_queries
+WHERE name = $1;
+INSERT INTO zdb.saved_queries(name, query, params)
+VALUES ($1, $2, $3);
+$$;
+
+GRANT ALL ON zdb.saved_queries TO PUBLIC;
@@ -1,25 +1,53 @@
 use pgrx::*;
 use std::panic::AssertUnwindSafe;
 
+use crate::access_method::concurrent::{add_index_aliases, remember_concurrent_build};
 use crate::access_method::options::ZDBIndexOptions;
+use crate::access_method::parallel::{ParallelBuild, ParallelBuildWorker};
+use crate::access_method::progress::{report_phase, report_tuples, BuildPhase};
 use crate::access_method::triggers::create_triggers;
 use crate::elasticsearch::Elasticsearch;
 use crate::executor_manager::{get_executor_manager, BulkContext};
 use crate::gucs::ZDB_LOG_LEVEL;
 use crate::json::builder::JsonBuilder;
 use crate::mapping::{categorize_tupdesc, generate_default_mapping, CategorizedAttribute};
-use crate::utils::{count_non_shadow_zdb_indices, lookup_zdb_index_tupdesc};
+use crate::utils::{
+    find_zdb_partition_ancestors, lookup_zdb_index_tupdesc, other_non_shadow_zdb_indices,
+};
 
 struct BuildState<'a> {
     bulk: &'a mut BulkContext,
     memcxt: PgMemoryContexts,
+    progress: BuildProgress<'a>,
+}
+
+enum BuildProgress<'a> {
+    /// the backend running CREATE INDEX reports its own rows plus those of its workers
+    Leader(Option<&'a ParallelBuild>),
+
+    /// a parallel worker shares its counts with the leader
+    Worker(&'a ParallelBuildWorker),
 }
 
 impl<'a> BuildState<'a> {
-    fn new(bulk: &'a mut BulkContext) -> Self {
+    fn new(bulk: &'a mut BulkContext, progress: BuildProgress<'a>) -> Self {
         BuildState {
             bulk,
             memcxt: PgMemoryContexts::new("zombodb build context"),
+            progress,
+        }
+    }
+
+    fn report_progress(&self) {
+        let sent = self.bulk.es_bulk_request.totals().0;
+        match self.progress {
+            BuildProgress::Leader(parallel) => {
+                let (worker_sent, _) = parallel.map_or((0, 0), |parallel| parallel.progress());
+                report_tuples(None, sent + worker_sent);
+            }
+            BuildProgress::Worker(worker) => {
+                worker.report_progress(sent, self.bulk.es_bulk_request.acknowledged())
+            }
         }
     }
 }
@@ -38,30 +66,53 @@ pub extern "C" fn ambuild(
         return unsafe { PgBox::<pg_sys::IndexBuildResult>::alloc0().into_pg() };
     }
 
+    let is_concurrent = unsafe {
+        index_info
+            .as_ref()
+            .expect("index_info is null")
+            .ii_Concurrent
+    };
+
     unsafe {
-        if count_non_shadow_zdb_indices(&heap_relation, &index_relation) != 0 {
-            panic!("Relations can only have one non-shadow ZomboDB index");
-        } else if !index_info
+        if !index_info
             .as_ref()
             .expect("index_info is null")
             .ii_Predicate
             .is_null()
         {
             panic!("ZomboDB indices cannot contain WHERE clauses");
-        } else if index_info
-            .as_ref()
-            .expect("index_info is null")
-            .ii_Concurrent
-        {
-            panic!("ZomboDB indices cannot be created CONCURRENTLY");
         }
     }
 
     let elasticsearch = Elasticsearch::new(&index_relation);
+
+    // building the index starts by deleting its Elasticsearch index, which mustn't be one the
+    // table's other ZomboDB indices are still using
+    for other in other_non_shadow_zdb_indices(&heap_relation, &index_relation) {
+        if Elasticsearch::new(&other).index_name() == elasticsearch.index_name() {
+            panic!(
+                "another ZomboDB index already uses the Elasticsearch index {}",
+                elasticsearch.index_name()
+            );
+        }
+    }
+
     let tupdesc = lookup_zdb_index_tupdesc(&index_relation);
 
+    // partitioned indices tell their partitions' documents apart by Elasticsearch index name,
+    // which doesn't work for a rollover index's backing indices
+    if elasticsearch.is_rollover() && !find_zdb_partition_ancestors(&index_relation).is_empty() {
+        panic!("ZomboDB indices on partitions cannot use rollover");
+    }
+
     let mut mapping = generate_default_mapping(&heap_relation);
-    let _ = categorize_tupdesc(&tupdesc, &heap_relation, Some(&mut mapping));
+    let _ = categorize_tupdesc(&tupdesc, &index_relation, Some(&mut mapping));
+
+    if is_concurrent {
+        remember_concurrent_build(&heap_relation, &index_relation);
+    }
+
+    report_phase(BuildPhase::CreatingIndex);
 
     // delete any existing Elasticsearch index with the same name as this one we're about to create
     elasticsearch
@@ -70,36 +121,66 @@ pub extern "C" fn ambuild(
         .expect("failed to delete existing Elasticsearch index");
 
     // create the new index
+    let mapping = serde_json::to_value(&mapping).expect("failed to generate mapping");
     elasticsearch
-        .create_index(serde_json::to_value(&mapping).expect("failed to generate mapping"))
+        .create_index(mapping.clone())
         .execute()
         .expect("failed to create new Elasticsearch index");
 
+    // and the same on each of its mirror clusters
+    elasticsearch.for_each_mirror("create Elasticsearch index", |mirror| {
+        mirror.delete_index().execute()?;
+        mirror.create_index(mapping.clone()).execute()
+    });
+
     // register a callback to delete the newly-created ES index if our transaction aborts
-    let delete_on_abort = AssertUnwindSafe(elasticsearch.delete_index());
+    let delete_on_abort = AssertUnwindSafe(
+        std::iter::once(elasticsearch.clone())
+            .chain(elasticsearch.mirrors())
+            .map(|es| es.delete_index())
+            .collect::<Vec<_>>(),
+    );
     register_xact_callback(PgXactCallbackEvent::Abort, move || {
-        if let Err(e) = delete_on_abort.execute() {
-            // we can't panic here b/c we're already in the ABORT stage
-            warning!(
-                "failed to delete Elasticsearch index on transaction abort: {:?}",
-                e
-            )
+        for delete_index in delete_on_abort.iter() {
+            if let Err(e) = delete_index.execute() {
+                // we can't panic here b/c we're already in the ABORT stage
+                warning!(
+                    "failed to delete Elasticsearch index on transaction abort: {:?}",
+                    e
+                )
+            }
         }
     });
 
     let ntuples = do_heap_scan(index_info, &heap_relation, &index_relation, &elasticsearch);
 
+    report_phase(BuildPhase::Refreshing);
+    elasticsearch
+        .refresh_index()
+        .execute()
+        .expect("failed to refresh index after build");
+    elasticsearch.for_each_mirror("refresh Elasticsearch index", |mirror| {
+        mirror.refresh_index().execute()
+    });
+
     // update the index settings, such as refresh_interval and number of replicas
+    report_phase(BuildPhase::UpdatingSettings);
     elasticsearch
         .update_settings()
         .execute()
         .expect("failed to update index settings after build");
 
-    // ensure the index is added to its alias defined during CREATE INDEX
-    elasticsearch
-        .add_alias(elasticsearch.alias_name())
-        .execute()
-        .expect("failed to add index to alias during CREATE INDEX");
+    // mirrors get the same settings
+    elasticsearch.for_each_mirror("finish CREATE INDEX", |mirror| {
+        mirror.update_settings().execute()
+    });
+
+    // an index built CONCURRENTLY isn't searchable until Postgres has validated it, which is
+    // when it joins its aliases
+    if !is_concurrent {
+        report_phase(BuildPhase::AddingAlias);
+        add_index_aliases(&index_relation, &elasticsearch);
+    }
 
     // create the triggers we need on the table to which this index is attached
     if !heap_relation.is_matview() {
@@ -119,20 +200,63 @@ fn do_heap_scan<'a>(
     index_relation: &'a PgRelation,
     elasticsearch: &Elasticsearch,
 ) -> usize {
-    let bulk_context = get_executor_manager().checkout_bulk_context(index_relation.oid());
-    let mut state = BuildState::new(bulk_context);
+    let bulk = get_executor_manager().checkout_bulk_context(index_relation.oid());
 
-    unsafe {
-        pg_sys::IndexBuildHeapScan(
-            heap_relation.as_ptr(),
-            index_relation.as_ptr(),
-            index_info,
-            Some(build_callback),
-            &mut state,
-        );
+    report_phase(BuildPhase::BulkIndexing);
+    report_tuples(
+        heap_relation
+            .reltuples()
+            .filter(|reltuples| *reltuples >= 0.0)
+            .map(|reltuples| reltuples as usize),
+        0,
+    );
+
+    let parallel = ParallelBuild::begin(heap_relation, index_relation, index_info);
+    {
+        let mut state = BuildState::new(bulk, BuildProgress::Leader(parallel.as_ref()));
+        match &parallel {
+            Some(parallel) => parallel.scan(
+                heap_relation,
+                index_relation,
+                index_info,
+                Some(build_callback),
+                &mut state,
+            ),
+            None => unsafe {
+                pg_sys::IndexBuildHeapScan(
+                    heap_relation.as_ptr(),
+                    index_relation.as_ptr(),
+                    index_info,
+                    Some(build_callback),
+                    &mut state,
+                );
+            },
+        }
     }
 
-    let (ntuples, nrequests) = state.bulk.es_bulk_request.totals();
+    // wait for Elasticsearch to acknowledge everything now, rather than when the transaction
+    // commits, so the index can be refreshed before it's used
+    report_phase(BuildPhase::WaitingForElasticsearch);
+    let es_bulk_request =
+        std::mem::replace(&mut bulk.es_bulk_request, bulk.elasticsearch.start_bulk());
+    let (worker_sent, worker_acknowledged) = parallel
+        .as_ref()
+        .map_or((0, 0), |parallel| parallel.progress());
+    report_tuples(
+        Some(es_bulk_request.totals().0 + worker_sent),
+        es_bulk_request.acknowledged() + worker_acknowledged,
+    );
+
+    let (mut ntuples, mut nrequests) = es_bulk_request
+        .without_refresh()
+        .finish()
+        .unwrap_or_else(|e| panic!("{:?}", e));
+    if let Some(parallel) = parallel {
+        let (worker_ntuples, worker_nrequests) = parallel.finish();
+        ntuples += worker_ntuples;
+        nrequests += worker_nrequests;
+    }
+    report_tuples(None, ntuples);
 
     ZDB_LOG_LEVEL.get().log(&format!(
         "[zombodb] indexed {} rows to {} in {} requests",
@@ -144,6 +268,31 @@ fn do_heap_scan<'a>(
     ntuples
 }
 
+/// Entry point for the workers of a parallel build, which Postgres finds by name
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn zdb_parallel_build_main(
+    _seg: *mut pg_sys::dsm_segment,
+    toc: *mut pg_sys::shm_toc,
+) {
+    let worker = unsafe { ParallelBuildWorker::attach(toc) };
+
+    // workers aren't part of the leader's transaction as far as the executor manager is
+    // concerned, so each one has its own bulk context that it finishes itself
+    let mut bulk = BulkContext::new(&worker.index_relation);
+    let mut state = BuildState::new(&mut bulk, BuildProgress::Worker(&worker));
+    worker.scan(Some(build_callback), &mut state);
+    drop(state);
+
+    // the leader refreshes the index once every worker has finished
+    let (ntuples, nrequests) = bulk
+        .es_bulk_request
+        .without_refresh()
+        .finish()
+        .expect("failed to finish parallel build");
+    worker.finish(ntuples, nrequests);
+}
+
 #[pg_guard]
 pub extern "C" fn ambuildempty(_index_relation: pg_sys::Relation) {}
 
@@ -241,6 +390,7 @@ unsafe extern "C" fn build_callback_internal(
         .es_bulk_request
         .insert(ctid, cmin, cmax, xmin, xmax, builder)
         .expect("Unable to send tuple for insert");
+    state.report_progress();
 
     old_context.set_as_current();
     state.memcxt.reset();
@@ -280,17 +430,17 @@ unsafe fn decon_row(
         nulls.as_mut_ptr(),
     );
 
-    let mut drop_cnt = 0;
+    let mut skip_cnt = 0;
     (0..bulk.natts).map(move |idx| {
-        let is_dropped = *bulk.dropped.get(idx).unwrap();
+        let is_skipped = *bulk.skipped.get(idx).unwrap();
 
-        if is_dropped {
-            drop_cnt += 1;
+        if is_skipped {
+            skip_cnt += 1;
             None
         } else if nulls[idx] {
             None
         } else {
-            Some((&bulk.attributes[idx - drop_cnt], datums[idx]))
+            Some((&bulk.attributes[idx - skip_cnt], datums[idx]))
         }
     })
 }
@@ -0,0 +1,114 @@
+//! `CREATE INDEX CONCURRENTLY` and `REINDEX CONCURRENTLY` build and validate an index across
+//! several transactions, so what we learn while building it is kept for this backend until the
+//! statement finishes
+use crate::access_method::options::ZDBIndexOptions;
+use crate::elasticsearch::Elasticsearch;
+use crate::gucs::ZDB_LOG_LEVEL;
+use crate::utils::{find_zdb_partition_ancestors, other_non_shadow_zdb_indices};
+use pgrx::*;
+use std::cell::RefCell;
+
+struct ConcurrentBuild {
+    indexrelid: pg_sys::Oid,
+
+    /// the table's other ZomboDB indices, one of which REINDEX CONCURRENTLY drops once the new
+    /// index has replaced it
+    siblings: Vec<(pg_sys::Oid, Elasticsearch)>,
+}
+
+thread_local! {
+    static CONCURRENT_BUILDS: RefCell<Vec<ConcurrentBuild>> = RefCell::new(Vec::new());
+}
+
+/// Called before Postgres runs a top-level `CREATE INDEX` or `REINDEX` statement
+pub fn begin_index_statement() {
+    CONCURRENT_BUILDS.with(|builds| builds.borrow_mut().clear());
+}
+
+/// Remember an index `ambuild` is building CONCURRENTLY, which only joins its aliases once
+/// Postgres has validated it
+pub fn remember_concurrent_build(heap_relation: &PgRelation, index_relation: &PgRelation) {
+    let siblings = other_non_shadow_zdb_indices(heap_relation, index_relation)
+        .iter()
+        .map(|index| (index.oid(), Elasticsearch::new(index)))
+        .collect::<Vec<_>>();
+
+    CONCURRENT_BUILDS.with(|builds| {
+        builds.borrow_mut().push(ConcurrentBuild {
+            indexrelid: index_relation.oid(),
+            siblings,
+        })
+    });
+}
+
+/// Called after Postgres has finished a top-level `CREATE INDEX` or `REINDEX` statement.  Each
+/// index it built CONCURRENTLY is now valid, so it joins its aliases, and the Elasticsearch
+/// indices of those REINDEX CONCURRENTLY replaced are deleted
+pub fn finish_concurrent_builds() {
+    for build in CONCURRENT_BUILDS.with(|builds| builds.take()) {
+        if lookup_indisvalid(build.indexrelid) == Some(true) {
+            let index_relation = unsafe {
+                PgRelation::with_lock(
+                    build.indexrelid,
+                    pg_sys::AccessShareLock as pg_sys::LOCKMODE,
+                )
+            };
+            add_index_aliases(&index_relation, &Elasticsearch::new(&index_relation));
+        }
+
+        // REINDEX CONCURRENTLY drops the replaced index itself, without a DROP INDEX statement
+        for (oid, sibling) in build.siblings {
+            if lookup_indisvalid(oid).is_none() {
+                ZDB_LOG_LEVEL.get().log(&format!(
+                    "[zombodb] Deleting remote index replaced by REINDEX CONCURRENTLY: {}",
+                    sibling.base_url()
+                ));
+
+                if let Err(e) = sibling.delete_index().execute() {
+                    warning!("failed to delete replaced Elasticsearch index: {:?}", e)
+                }
+                for mirror in sibling.mirrors() {
+                    mirror.delete_index().execute().ok();
+                }
+            }
+        }
+    }
+}
+
+/// Add an index to the alias defined during CREATE INDEX and, if it's on a partition, to the
+/// aliases of the partitioned indices above it, which is how they're searched
+pub fn add_index_aliases(index_relation: &PgRelation, elasticsearch: &Elasticsearch) {
+    elasticsearch
+        .add_alias(elasticsearch.alias_name())
+        .execute()
+        .expect("failed to add index to alias during CREATE INDEX");
+
+    let ancestor_aliases = find_zdb_partition_ancestors(index_relation)
+        .iter()
+        .map(|ancestor| ZDBIndexOptions::from_relation(ancestor).alias().to_string())
+        .collect::<Vec<_>>();
+    for alias in &ancestor_aliases {
+        elasticsearch
+            .add_alias(alias)
+            .execute()
+            .expect("failed to add index to partitioned index alias during CREATE INDEX");
+    }
+
+    // mirrors get the same aliases
+    elasticsearch.for_each_mirror("add index to its aliases", |mirror| {
+        mirror.add_alias(mirror.alias_name()).execute()?;
+        for alias in &ancestor_aliases {
+            mirror.add_alias(alias).execute()?;
+        }
+        Ok(())
+    });
+}
+
+/// Returns `None` if the index no longer exists
+fn lookup_indisvalid(indexrelid: pg_sys::Oid) -> Option<bool> {
+    Spi::get_one_with_args(
+        "SELECT (SELECT indisvalid FROM pg_index WHERE indexrelid = $1)",
+        &[indexrelid.into()],
+    )
+    .expect("SPI failed")
+}
@@ -1,5 +1,9 @@
+use crate::zdbquery::ZDBQuery;
 use pgrx::*;
 
+/// Same as `disable_cost` in costsize.c, for paths we'd rather Postgres didn't choose
+const DISABLE_COST: f64 = 1.0e10;
+
 #[pg_guard(immutable, parallel_safe)]
 pub unsafe extern "C" fn amcostestimate(
     _root: *mut pg_sys::PlannerInfo,
@@ -45,6 +49,14 @@ pub unsafe extern "C" fn amcostestimate(
         if ri.norm_selec > 0f64 {
             *index_selectivity = ri.norm_selec.min(*index_selectivity);
         }
+
+        // steer Postgres towards the index a query was pointed at with `dsl.index()`
+        if let Some(target_index) = clause_target_index(ri.clause) {
+            if target_index != index_relation.oid() {
+                *index_startup_cost += DISABLE_COST;
+                *index_total_cost += DISABLE_COST;
+            }
+        }
     }
 
     let reltuples = heap_relation.reltuples().unwrap_or(1f32) as f64;
@@ -58,3 +70,23 @@ pub unsafe extern "C" fn amcostestimate(
     //  allowing Postgres to instead prefer to use our index for plans where it can actually use one
     *index_total_cost -= pg_sys::random_page_cost;
 }
+
+unsafe fn clause_target_index(clause: *mut pg_sys::Expr) -> Option<pg_sys::Oid> {
+    if !is_a(clause as *mut pg_sys::Node, pg_sys::NodeTag::T_OpExpr) {
+        return None;
+    }
+
+    let opexpr = PgBox::from_pg(clause as *mut pg_sys::OpExpr);
+    let args = PgList::<pg_sys::Node>::from_pg(opexpr.args);
+    let right = args.get_ptr(1)?;
+    if !is_a(right, pg_sys::NodeTag::T_Const) {
+        return None;
+    }
+
+    let rconst = PgBox::from_pg(right as *mut pg_sys::Const);
+    if pg_sys::type_is_array(rconst.consttype) {
+        return None;
+    }
+
+    ZDBQuery::from_datum(rconst.constvalue, rconst.constisnull)?.target_index()
+}
@@ -1,11 +1,14 @@
 use pgrx::*;
 
 mod build;
+pub mod concurrent;
 mod cost_estimate;
 pub mod options;
+mod parallel;
+mod progress;
 pub mod rewriter;
 mod scan;
-mod triggers;
+pub mod triggers;
 mod vacuum;
 
 #[pg_extern(sql = "
@@ -26,6 +29,7 @@ fn amhandler(_fcinfo: pg_sys::FunctionCallInfo) -> PgBox<pg_sys::IndexAmRoutine>
     amroutine.amvalidate = Some(amvalidate);
     amroutine.ambuild = Some(build::ambuild);
     amroutine.ambuildempty = Some(build::ambuildempty);
+    amroutine.ambuildphasename = Some(progress::ambuildphasename);
     amroutine.aminsert = Some(build::aminsert);
     amroutine.ambulkdelete = Some(vacuum::ambulkdelete);
     amroutine.amvacuumcleanup = Some(vacuum::amvacuumcleanup);
@@ -0,0 +1,370 @@
+//! Parallel `CREATE INDEX`.  Postgres only plans parallel builds for btree indices, so ZomboDB
+//! launches its own workers the same way btree does.  Each worker scans the heap blocks it's
+//! handed by a shared parallel heap scan and sends its rows to Elasticsearch through its own
+//! `_bulk` requests, while the leader does the same and then adds up everyone's counts
+use crate::gucs::ZDB_LOG_LEVEL;
+use pgrx::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const PARALLEL_KEY_ZDB_SHARED: u64 = 0xA000000000000001;
+const PARALLEL_KEY_TABLE_SCAN: u64 = 0xA000000000000002;
+const PARALLEL_KEY_WORKER_TOTALS: u64 = 0xA000000000000003;
+
+/// `BUFFERALIGN()` from c.h, which is how `shm_toc_allocate()` aligns each chunk
+const BUFFER_ALIGNMENT: usize = 32;
+
+#[repr(C)]
+struct ZDBShared {
+    heaprelid: pg_sys::Oid,
+    indexrelid: pg_sys::Oid,
+    is_concurrent: bool,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct WorkerTotals {
+    /// updated as the worker goes, so the leader can report the build's progress
+    sent: AtomicUsize,
+    acknowledged: AtomicUsize,
+
+    ntuples: usize,
+    nrequests: usize,
+}
+
+pub struct ParallelBuild {
+    pcxt: *mut pg_sys::ParallelContext,
+    snapshot: pg_sys::Snapshot,
+    is_concurrent: bool,
+    pscan: pg_sys::ParallelTableScanDesc,
+    totals: *mut WorkerTotals,
+}
+
+impl ParallelBuild {
+    /// Launch as many workers as `max_parallel_maintenance_workers` and the size of the table
+    /// allow.  Returns `None` if the index should be built by this backend alone
+    pub fn begin(
+        heap_relation: &PgRelation,
+        index_relation: &PgRelation,
+        index_info: *mut pg_sys::IndexInfo,
+    ) -> Option<ParallelBuild> {
+        unsafe {
+            if pg_sys::Mode != pg_sys::ProcessingMode::NormalProcessing {
+                return None;
+            }
+
+            let nworkers =
+                pg_sys::plan_create_index_workers(heap_relation.oid(), index_relation.oid());
+            if nworkers <= 0 {
+                return None;
+            }
+
+            let is_concurrent = index_info
+                .as_ref()
+                .expect("index_info is null")
+                .ii_Concurrent;
+
+            pg_sys::EnterParallelMode();
+            let pcxt = pg_sys::CreateParallelContext(
+                "zombodb".as_pg_cstr(),
+                "zdb_parallel_build_main".as_pg_cstr(),
+                nworkers,
+            );
+
+            // a CONCURRENTLY build only indexes what its MVCC snapshot sees, otherwise every
+            // tuple is examined, same as a serial build
+            let snapshot = if is_concurrent {
+                pg_sys::RegisterSnapshot(pg_sys::GetTransactionSnapshot())
+            } else {
+                std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData)
+            };
+
+            let scan_size = pg_sys::table_parallelscan_estimate(heap_relation.as_ptr(), snapshot);
+            let totals_size = std::mem::size_of::<WorkerTotals>() * nworkers as usize;
+            let estimator = &mut (*pcxt).estimator;
+            for size in [std::mem::size_of::<ZDBShared>(), scan_size, totals_size] {
+                estimator.space_for_chunks +=
+                    (size + BUFFER_ALIGNMENT - 1) & !(BUFFER_ALIGNMENT - 1);
+            }
+            estimator.number_of_keys += 3;
+
+            pg_sys::InitializeParallelDSM(pcxt);
+            if (*pcxt).seg.is_null() {
+                // no shared memory available, so we'll do it ourselves
+                if is_concurrent {
+                    pg_sys::UnregisterSnapshot(snapshot);
+                }
+                pg_sys::DestroyParallelContext(pcxt);
+                pg_sys::ExitParallelMode();
+                return None;
+            }
+
+            let toc = (*pcxt).toc;
+            let shared =
+                pg_sys::shm_toc_allocate(toc, std::mem::size_of::<ZDBShared>()) as *mut ZDBShared;
+            shared.write(ZDBShared {
+                heaprelid: heap_relation.oid(),
+                indexrelid: index_relation.oid(),
+                is_concurrent,
+            });
+
+            let pscan = pg_sys::shm_toc_allocate(toc, scan_size) as pg_sys::ParallelTableScanDesc;
+            pg_sys::table_parallelscan_initialize(heap_relation.as_ptr(), pscan, snapshot);
+
+            let totals = pg_sys::shm_toc_allocate(toc, totals_size) as *mut WorkerTotals;
+            for i in 0..nworkers as usize {
+                totals.add(i).write(WorkerTotals::default());
+            }
+
+            pg_sys::shm_toc_insert(toc, PARALLEL_KEY_ZDB_SHARED, shared as *mut _);
+            pg_sys::shm_toc_insert(toc, PARALLEL_KEY_TABLE_SCAN, pscan as *mut _);
+            pg_sys::shm_toc_insert(toc, PARALLEL_KEY_WORKER_TOTALS, totals as *mut _);
+
+            pg_sys::LaunchParallelWorkers(pcxt);
+
+            ZDB_LOG_LEVEL.get().log(&format!(
+                "[zombodb] building {} with {} parallel workers",
+                index_relation.name(),
+                (*pcxt).nworkers_launched
+            ));
+
+            Some(ParallelBuild {
+                pcxt,
+                snapshot,
+                is_concurrent,
+                pscan,
+                totals,
+            })
+        }
+    }
+
+    /// The leader takes its share of the heap scan too
+    pub fn scan<T>(
+        &self,
+        heap_relation: &PgRelation,
+        index_relation: &PgRelation,
+        index_info: *mut pg_sys::IndexInfo,
+        build_callback: pg_sys::IndexBuildCallback,
+        state: &mut T,
+    ) {
+        unsafe {
+            parallel_heap_scan(
+                heap_relation,
+                index_relation,
+                index_info,
+                self.pscan,
+                true,
+                build_callback,
+                state,
+            )
+        }
+    }
+
+    /// How many rows the workers have sent to Elasticsearch so far, and how many of those
+    /// Elasticsearch has acknowledged
+    pub fn progress(&self) -> (usize, usize) {
+        unsafe {
+            (0..(*self.pcxt).nworkers_launched as usize)
+                .map(|i| &*self.totals.add(i))
+                .fold((0, 0), |(sent, acknowledged), totals| {
+                    (
+                        sent + totals.sent.load(Ordering::Relaxed),
+                        acknowledged + totals.acknowledged.load(Ordering::Relaxed),
+                    )
+                })
+        }
+    }
+
+    /// Wait for the workers to finish and return how many rows they indexed, and in how many
+    /// `_bulk` requests
+    pub fn finish(self) -> (usize, usize) {
+        unsafe {
+            pg_sys::WaitForParallelWorkersToFinish(self.pcxt);
+
+            let (mut ntuples, mut nrequests) = (0, 0);
+            for i in 0..(*self.pcxt).nworkers_launched as usize {
+                let totals = &*self.totals.add(i);
+                ntuples += totals.ntuples;
+                nrequests += totals.nrequests;
+            }
+
+            if self.is_concurrent {
+                pg_sys::UnregisterSnapshot(self.snapshot);
+            }
+            pg_sys::DestroyParallelContext(self.pcxt);
+            pg_sys::ExitParallelMode();
+
+            (ntuples, nrequests)
+        }
+    }
+}
+
+/// What a parallel worker needs to take its share of the build
+pub struct ParallelBuildWorker {
+    pub heap_relation: PgRelation,
+    pub index_relation: PgRelation,
+    pub index_info: *mut pg_sys::IndexInfo,
+    pscan: pg_sys::ParallelTableScanDesc,
+    totals: *mut WorkerTotals,
+}
+
+impl ParallelBuildWorker {
+    pub unsafe fn attach(toc: *mut pg_sys::shm_toc) -> Self {
+        let shared = pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_ZDB_SHARED, false) as *mut ZDBShared;
+        let pscan = pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_TABLE_SCAN, false)
+            as pg_sys::ParallelTableScanDesc;
+        let totals =
+            pg_sys::shm_toc_lookup(toc, PARALLEL_KEY_WORKER_TOTALS, false) as *mut WorkerTotals;
+        let shared = shared.as_ref().expect("no shared build state");
+
+        // the same locks the leader holds, which are granted because we're in its lock group
+        let (heap_lockmode, index_lockmode) = if shared.is_concurrent {
+            (pg_sys::ShareUpdateExclusiveLock, pg_sys::RowExclusiveLock)
+        } else {
+            (pg_sys::ShareLock, pg_sys::AccessExclusiveLock)
+        };
+        let heap_relation =
+            PgRelation::with_lock(shared.heaprelid, heap_lockmode as pg_sys::LOCKMODE);
+        let index_relation =
+            PgRelation::with_lock(shared.indexrelid, index_lockmode as pg_sys::LOCKMODE);
+
+        let index_info = pg_sys::BuildIndexInfo(index_relation.as_ptr());
+        (*index_info).ii_Concurrent = shared.is_concurrent;
+
+        ParallelBuildWorker {
+            heap_relation,
+            index_relation,
+            index_info,
+            pscan,
+            totals,
+        }
+    }
+
+    pub fn scan<T>(&self, build_callback: pg_sys::IndexBuildCallback, state: &mut T) {
+        unsafe {
+            parallel_heap_scan(
+                &self.heap_relation,
+                &self.index_relation,
+                self.index_info,
+                self.pscan,
+                false,
+                build_callback,
+                state,
+            )
+        }
+    }
+
+    pub fn report_progress(&self, sent: usize, acknowledged: usize) {
+        let totals = unsafe { &*self.my_totals() };
+        totals.sent.store(sent, Ordering::Relaxed);
+        totals.acknowledged.store(acknowledged, Ordering::Relaxed);
+    }
+
+    /// Report this worker's counts back to the leader
+    pub fn finish(self, ntuples: usize, nrequests: usize) {
+        unsafe {
+            let totals = self.my_totals();
+            (*totals).ntuples = ntuples;
+            (*totals).nrequests = nrequests;
+        }
+    }
+
+    fn my_totals(&self) -> *mut WorkerTotals {
+        unsafe { self.totals.add(pg_sys::ParallelWorkerNumber as usize) }
+    }
+}
+
+/// `table_index_build_scan()` over the blocks the shared parallel scan hands to this process
+unsafe fn parallel_heap_scan<T>(
+    heap_relation: &PgRelation,
+    index_relation: &PgRelation,
+    index_info: *mut pg_sys::IndexInfo,
+    pscan: pg_sys::ParallelTableScanDesc,
+    progress: bool,
+    build_callback: pg_sys::IndexBuildCallback,
+    state: &mut T,
+) {
+    let scan = pg_sys::table_beginscan_parallel(heap_relation.as_ptr(), pscan);
+    let tableam = heap_relation
+        .rd_tableam
+        .as_ref()
+        .expect("relation has no table access method");
+
+    // the scan is ended for us once it's been consumed
+    tableam
+        .index_build_range_scan
+        .expect("table access method has no index_build_range_scan")(
+        heap_relation.as_ptr(),
+        index_relation.as_ptr(),
+        index_info,
+        true,
+        false,
+        progress,
+        0,
+        pg_sys::InvalidBlockNumber,
+        build_callback,
+        state as *mut T as *mut std::os::raw::c_void,
+        scan,
+    );
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+    use std::ffi::CStr;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static LAUNCHED_WORKERS: AtomicI32 = AtomicI32::new(-1);
+
+    /// Pick out how many workers the build said it launched
+    unsafe extern "C-unwind" fn capture_launched_workers(edata: *mut pg_sys::ErrorData) {
+        let message = match edata.as_ref() {
+            Some(edata) if !edata.message.is_null() => CStr::from_ptr(edata.message),
+            _ => return,
+        };
+        if let Some(nworkers) = message
+            .to_str()
+            .ok()
+            .and_then(|message| message.strip_prefix("[zombodb] building idxparallel_build with "))
+            .and_then(|rest| rest.strip_suffix(" parallel workers"))
+            .and_then(|nworkers| nworkers.parse().ok())
+        {
+            LAUNCHED_WORKERS.store(nworkers, Ordering::SeqCst);
+        }
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_parallel_build() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE parallel_build(id serial8, title text);
+             INSERT INTO parallel_build(title) SELECT repeat('parallel build ', 20) || i FROM generate_series(1, 10000) i;
+             ALTER TABLE parallel_build SET (parallel_workers = 2);
+             SET LOCAL max_parallel_maintenance_workers = 2;
+             SET LOCAL maintenance_work_mem = '256MB';
+             SET LOCAL zdb.log_level = 'info';",
+        )?;
+        let blocks = Spi::get_one::<i64>(
+            "SELECT pg_relation_size('parallel_build') / current_setting('block_size')::int8",
+        )?
+        .expect("blocks was NULL");
+        assert!(blocks > 1);
+
+        unsafe {
+            let prev_hook = pg_sys::emit_log_hook;
+            pg_sys::emit_log_hook = Some(capture_launched_workers);
+            let result = Spi::run(
+                "CREATE INDEX idxparallel_build ON parallel_build USING zombodb ((parallel_build.*));",
+            );
+            pg_sys::emit_log_hook = prev_hook;
+            result?;
+        }
+        assert_eq!(LAUNCHED_WORKERS.load(Ordering::SeqCst), 2);
+
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM parallel_build WHERE parallel_build ==> 'title:parallel'",
+        )?;
+        assert_eq!(count, Some(10000));
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Reports how far along a ZomboDB index build is through `pg_stat_progress_create_index`.  Its
+//! "phase" column reads "building index: <sub-phase>", where the sub-phase is one of ours
+use pgrx::*;
+
+// from commands/progress.h
+const PROGRESS_CREATEIDX_SUBPHASE: i32 = 10;
+const PROGRESS_CREATEIDX_TUPLES_TOTAL: i32 = 11;
+const PROGRESS_CREATEIDX_TUPLES_DONE: i32 = 12;
+
+/// Our sub-phases of `CREATE INDEX`.  They start at 2 because Postgres reserves 1 for
+/// "initializing"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildPhase {
+    CreatingIndex = 2,
+    BulkIndexing,
+    WaitingForElasticsearch,
+    Refreshing,
+    UpdatingSettings,
+    AddingAlias,
+}
+
+impl BuildPhase {
+    const ALL: [BuildPhase; 6] = [
+        BuildPhase::CreatingIndex,
+        BuildPhase::BulkIndexing,
+        BuildPhase::WaitingForElasticsearch,
+        BuildPhase::Refreshing,
+        BuildPhase::UpdatingSettings,
+        BuildPhase::AddingAlias,
+    ];
+
+    fn name(&self) -> &'static [u8] {
+        match self {
+            BuildPhase::CreatingIndex => b"creating Elasticsearch index\0",
+            BuildPhase::BulkIndexing => b"bulk indexing\0",
+            BuildPhase::WaitingForElasticsearch => b"waiting for Elasticsearch\0",
+            BuildPhase::Refreshing => b"refreshing\0",
+            BuildPhase::UpdatingSettings => b"updating settings\0",
+            BuildPhase::AddingAlias => b"adding alias\0",
+        }
+    }
+}
+
+pub fn report_phase(phase: BuildPhase) {
+    unsafe { pg_sys::pgstat_progress_update_param(PROGRESS_CREATEIDX_SUBPHASE, phase as i64) }
+}
+
+/// While bulk indexing, `tuples_total` is the planner's estimate of the table's rows and
+/// `tuples_done` the rows sent to Elasticsearch.  While waiting for Elasticsearch, they're the
+/// rows sent and the rows Elasticsearch has acknowledged
+pub fn report_tuples(total: Option<usize>, done: usize) {
+    unsafe {
+        if let Some(total) = total {
+            pg_sys::pgstat_progress_update_param(PROGRESS_CREATEIDX_TUPLES_TOTAL, total as i64);
+        }
+        pg_sys::pgstat_progress_update_param(PROGRESS_CREATEIDX_TUPLES_DONE, done as i64);
+    }
+}
+
+#[pg_guard]
+pub extern "C" fn ambuildphasename(phasenum: i64) -> *mut std::os::raw::c_char {
+    BuildPhase::ALL
+        .iter()
+        .find(|phase| **phase as i64 == phasenum)
+        .map_or(std::ptr::null_mut(), |phase| {
+            phase.name().as_ptr() as *mut std::os::raw::c_char
+        })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::access_method::progress::{ambuildphasename, BuildPhase};
+    use pgrx::*;
+    use std::ffi::CStr;
+
+    #[pg_test]
+    fn test_ambuildphasename() {
+        let name = unsafe { CStr::from_ptr(ambuildphasename(BuildPhase::BulkIndexing as i64)) };
+        assert_eq!(name.to_str().unwrap(), "bulk indexing");
+        assert!(ambuildphasename(1).is_null());
+        assert!(ambuildphasename(8).is_null());
+    }
+}
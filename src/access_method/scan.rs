@@ -1,6 +1,7 @@
 use crate::elasticsearch::search::SearchResponseIntoIter;
 use crate::elasticsearch::Elasticsearch;
 use crate::executor_manager::get_executor_manager;
+use crate::utils::{find_zdb_index, is_non_shadow_zdb_index};
 use crate::zdbquery::ZDBQuery;
 use pgrx::itemptr::{item_pointer_get_both, item_pointer_is_valid, u64_to_item_pointer};
 use pgrx::*;
@@ -24,7 +25,7 @@ pub extern "C" fn ambeginscan(
         ))
     };
     let state = ZDBScanState {
-        index_oid: unsafe { (*index_relation).rd_id },
+        index_oid: score_index_oid(&unsafe { PgRelation::from_pg(index_relation) }),
         iterator: std::ptr::null_mut(),
     };
 
@@ -60,6 +61,8 @@ pub extern "C" fn amrescan(
         });
     }
 
+    // the query may have been pointed at another of the table's ZomboDB indices
+    let indexrel = query.resolve_index(&indexrel);
     let elasticsearch = Elasticsearch::new(&indexrel);
 
     let response = elasticsearch
@@ -92,11 +95,9 @@ pub extern "C" fn amgettuple(
                 panic!("invalid item pointer: {:?}", item_pointer_get_both(*tid));
             }
 
-            // TODO:  the score/highlight values we stash away here relates to the index ctid, not
-            //        the heap ctid.  These could be different in the case of HOT-updated tuples
-            //        it's not clear how we can efficiently resolve the HOT chain here.
-            //        Likely side-effects of this will be that `zdb.score(ctid)` and `zdb.highlight(ctid...`
-            //        will end up returning NULL on the SQL-side of things.
+            // the score/highlight values we stash away here relate to the index ctid, which is the
+            // root of the HOT chain for HOT-updated tuples.  `QueryState` maps the visible heap
+            // ctid that `zdb.score(ctid)` and `zdb.highlight(ctid, ...)` are given back to it
             let (_, qstate) = get_executor_manager().peek_query_state().unwrap();
             qstate.add_score(state.index_oid, ctid, score);
             qstate.add_highlight(state.index_oid, ctid, highlights);
@@ -115,7 +116,6 @@ pub extern "C" fn amendscan(_scan: pg_sys::IndexScanDesc) {
 #[pg_guard]
 pub extern "C" fn ambitmapscan(scan: pg_sys::IndexScanDesc, tbm: *mut pg_sys::TIDBitmap) -> i64 {
     let scan = unsafe { PgBox::from_pg(scan) };
-    let index_relation = unsafe { PgRelation::from_pg(scan.indexRelation) };
     let state = unsafe { (scan.opaque as *mut ZDBScanState).as_mut() }.expect("no scandesc state");
     let (_query, qstate) = get_executor_manager().peek_query_state().unwrap();
 
@@ -129,10 +129,23 @@ pub extern "C" fn ambitmapscan(scan: pg_sys::IndexScanDesc, tbm: *mut pg_sys::TI
             pg_sys::tbm_add_tuples(tbm, &mut tid, 1, false);
         }
 
-        qstate.add_score(index_relation.oid(), ctid_u64, score);
-        qstate.add_highlight(index_relation.oid(), ctid_u64, highlights);
+        qstate.add_score(state.index_oid, ctid_u64, score);
+        qstate.add_highlight(state.index_oid, ctid_u64, highlights);
         cnt += 1;
     }
 
     cnt
 }
+
+/// `zdb.score()` and `zdb.highlight()` find a table's scores and highlights through its first
+/// ZomboDB index, so they're kept there no matter which of its indices was scanned
+fn score_index_oid(index_relation: &PgRelation) -> pg_sys::Oid {
+    if is_non_shadow_zdb_index(index_relation) {
+        if let Some(heap_relation) = index_relation.heap_relation() {
+            if let Ok((index, _)) = find_zdb_index(&heap_relation) {
+                return index.oid();
+            }
+        }
+    }
+    index_relation.oid()
+}
@@ -1,4 +1,7 @@
 use crate::executor_manager::get_executor_manager;
+use crate::utils::{
+    find_zdb_partition_indices, is_non_shadow_zdb_index, is_partitioned_zdb_index, is_zdb_index,
+};
 use pgrx::callconv::{BoxRet, FcInfo};
 use pgrx::datum::Datum;
 use pgrx::itemptr::{item_pointer_get_both, item_pointer_set_all};
@@ -7,7 +10,6 @@ use pgrx::pgrx_sql_entity_graph::metadata::{
     ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
 };
 use pgrx::*;
-use std::ffi::CStr;
 
 pub struct TriggerDatum(pub pg_sys::Datum);
 
@@ -54,25 +56,32 @@ fn zdb_update_trigger(fcinfo: pg_sys::FunctionCallInfo) -> TriggerDatum {
     }
 
     unsafe {
-        let args = std::slice::from_raw_parts(tg_trigger.tgargs, tg_trigger.tgnargs as usize);
-        let index_relid_str = CStr::from_ptr(args[0] as *const std::os::raw::c_char)
-            .to_str()
-            .unwrap();
-        let index_relid = str::parse::<u32>(index_relid_str).expect("malformed oid");
-        let index_relid = pg_sys::Oid::from(index_relid);
         let mut tid = (*trigdata.tg_trigtuple).t_self;
 
         maybe_find_hot_root(&trigdata, &mut tid);
 
-        let bulk = get_executor_manager().checkout_bulk_context(index_relid);
-        if !bulk.is_shadow {
-            bulk.es_bulk_request
-                .update(
-                    tid,
-                    pg_sys::GetCurrentCommandId(true),
-                    xid_to_64bit(pg_sys::GetCurrentTransactionId()),
-                )
-                .expect("failed to queue index update command");
+        let heap_relation = PgRelation::from_pg(trigdata.tg_relation);
+        for (index_relid, is_valid) in ready_zdb_indices(&heap_relation) {
+            let bulk = get_executor_manager().checkout_bulk_context(index_relid);
+            if bulk.is_shadow {
+                continue;
+            }
+
+            let cmax = pg_sys::GetCurrentCommandId(true);
+            let xmax = xid_to_64bit(pg_sys::GetCurrentTransactionId());
+            if is_valid {
+                bulk.es_bulk_request
+                    .update(tid, cmax, xmax)
+                    .expect("failed to queue index update command");
+            } else {
+                // an index being built CONCURRENTLY may not have this row yet.  It gets the new
+                // version from `aminsert` or when Postgres validates it, so here we only mark
+                // the old version as deleted, if it's there.  If the build indexes it afterwards,
+                // validation gives it this xmax
+                bulk.es_bulk_request
+                    .delete_if_exists(tid, cmax, xmax)
+                    .expect("failed to queue index update command");
+            }
         }
 
         TriggerDatum(trigdata.tg_newtuple.into())
@@ -106,31 +115,65 @@ fn zdb_delete_trigger(fcinfo: pg_sys::FunctionCallInfo) -> TriggerDatum {
     }
 
     unsafe {
-        let args = std::slice::from_raw_parts(tg_trigger.tgargs, tg_trigger.tgnargs as usize);
-        let index_relid_str = CStr::from_ptr(args[0] as *const std::os::raw::c_char)
-            .to_str()
-            .unwrap();
-        let index_relid = str::parse::<u32>(index_relid_str).expect("malformed oid");
-        let index_relid = pg_sys::Oid::from_u32_unchecked(index_relid);
         let mut tid = (*trigdata.tg_trigtuple).t_self;
 
         maybe_find_hot_root(&trigdata, &mut tid);
 
-        let bulk = get_executor_manager().checkout_bulk_context(index_relid);
-        if !bulk.is_shadow {
-            bulk.es_bulk_request
-                .delete(
-                    tid,
-                    pg_sys::GetCurrentCommandId(true),
-                    xid_to_64bit(pg_sys::GetCurrentTransactionId()),
-                )
-                .expect("failed to queue index delete command");
+        let heap_relation = PgRelation::from_pg(trigdata.tg_relation);
+        for (index_relid, is_valid) in ready_zdb_indices(&heap_relation) {
+            let bulk = get_executor_manager().checkout_bulk_context(index_relid);
+            if bulk.is_shadow {
+                continue;
+            }
+
+            let cmax = pg_sys::GetCurrentCommandId(true);
+            let xmax = xid_to_64bit(pg_sys::GetCurrentTransactionId());
+            if is_valid {
+                bulk.es_bulk_request
+                    .delete(tid, cmax, xmax)
+                    .expect("failed to queue index delete command");
+            } else {
+                // an index being built CONCURRENTLY may not have this row yet
+                bulk.es_bulk_request
+                    .delete_if_exists(tid, cmax, xmax)
+                    .expect("failed to queue index delete command");
+            }
         }
 
         TriggerDatum(trigdata.tg_trigtuple.into())
     }
 }
 
+/// The ZomboDB indices on a table that are ready for its changes, and whether each is valid.
+///
+/// The triggers are created for a table's first ZomboDB index and name it in their arguments,
+/// but fire for all of them:  an index being built CONCURRENTLY needs the changes too, and
+/// REINDEX CONCURRENTLY replaces the index the triggers name with a new one.  They're looked up
+/// once per statement
+fn ready_zdb_indices(heap_relation: &PgRelation) -> Vec<(pg_sys::Oid, bool)> {
+    match get_executor_manager().peek_query_state() {
+        Some((_, query_state)) => query_state.get_ready_zdb_indices(heap_relation.oid(), || {
+            lookup_ready_zdb_indices(heap_relation)
+        }),
+        None => lookup_ready_zdb_indices(heap_relation),
+    }
+}
+
+fn lookup_ready_zdb_indices(heap_relation: &PgRelation) -> Vec<(pg_sys::Oid, bool)> {
+    heap_relation
+        .indices(pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+        .filter(|index| is_zdb_index(index))
+        .filter_map(|index| {
+            let rd_index = unsafe { index.rd_index.as_ref() }?;
+            if rd_index.indisready {
+                Some((index.oid(), rd_index.indisvalid))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
 #[inline]
 unsafe fn maybe_find_hot_root(
     trigdata: &PgBox<pg_sys::TriggerData>,
@@ -174,6 +217,42 @@ unsafe fn maybe_find_hot_root(
     }
 }
 
+/// The tables whose triggers belong to a ZomboDB index, or to the indices on its partitions
+pub fn tables_with_triggers(index_relation: &PgRelation) -> Vec<pg_sys::Oid> {
+    if is_partitioned_zdb_index(index_relation) {
+        find_zdb_partition_indices(index_relation, true)
+            .iter()
+            .filter_map(|partition| partition.heap_relation())
+            .map(|heap_relation| heap_relation.oid())
+            .collect()
+    } else if is_non_shadow_zdb_index(index_relation) {
+        index_relation
+            .heap_relation()
+            .map(|heap_relation| heap_relation.oid())
+            .into_iter()
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The triggers are dropped along with the index they were created for, so if the table still
+/// has other ZomboDB indices, they're created again for the first of those
+pub fn restore_triggers(heap_oid: pg_sys::Oid) {
+    let heap_relation =
+        unsafe { PgRelation::with_lock(heap_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE) };
+    if heap_relation.is_matview() {
+        return;
+    }
+
+    if let Some(index_relation) = heap_relation
+        .indices(pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+        .find(is_non_shadow_zdb_index)
+    {
+        create_triggers(&index_relation);
+    }
+}
+
 pub fn create_triggers(index_relation: &PgRelation) {
     if !trigger_exists(index_relation, "zdb_update_trigger") {
         let trigger_oid = create_update_trigger(index_relation);
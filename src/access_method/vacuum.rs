@@ -5,6 +5,7 @@ use crate::query_dsl::bool::dsl::{and_vec, noteq};
 use crate::query_dsl::range::dsl::range_numeric;
 use crate::query_dsl::terms_lookup::dsl::terms_lookup;
 use crate::zdbquery::ZDBQuery;
+use pgrx::itemptr::u64_to_item_pointer;
 use pgrx::*;
 use serde::*;
 
@@ -12,8 +13,8 @@ use serde::*;
 pub extern "C" fn ambulkdelete(
     info: *mut pg_sys::IndexVacuumInfo,
     stats: *mut pg_sys::IndexBulkDeleteResult,
-    _callback: pg_sys::IndexBulkDeleteCallback,
-    _callback_state: *mut ::std::os::raw::c_void,
+    callback: pg_sys::IndexBulkDeleteCallback,
+    callback_state: *mut ::std::os::raw::c_void,
 ) -> *mut pg_sys::IndexBulkDeleteResult {
     let info = unsafe { PgBox::from_pg(info) };
     let index_relation = unsafe { PgRelation::from_pg(info.index) };
@@ -56,44 +57,88 @@ pub extern "C" fn ambulkdelete(
         .execute()
         .expect("failed to refresh index");
 
-    // Find all rows with what we think is an *aborted* xmin
-    //
-    // These rows can be deleted
-    let mut bulk = elasticsearch.start_bulk();
-    let by_xmin = delete_by_xmin(
-        &index_relation,
-        &elasticsearch,
-        es_index_name,
-        oldest_xmin,
-        &mut bulk,
-    );
-    bulk.finish().expect("failed to finish delete_by_xmin");
+    // an index that isn't valid is being built CONCURRENTLY and Postgres wants to know which rows
+    // it already has, so it can add the others.  Otherwise it's left over from a concurrent build
+    // that failed, and isn't used until it's rebuilt
+    let is_valid =
+        unsafe { index_relation.rd_index.as_ref() }.is_some_and(|index| index.indisvalid);
+    if !is_valid {
+        if let Some(callback) = callback {
+            // validation never asks for docs to be deleted.  But a row updated or deleted while
+            // the index was being built may have been indexed by the build after the trigger
+            // found no doc to give its xmax to, so it's given that xmax now
+            let heap_relation = index_relation
+                .heap_relation()
+                .expect("index has no heap relation");
+            let mut bulk = elasticsearch.start_bulk();
+            let mut deleted = 0;
+            let reported = report_indexed_tids(&index_relation, &elasticsearch, |tid| unsafe {
+                callback(tid, callback_state);
+
+                if let Some(xmax) = heap_tuple_xmax(&heap_relation, tid) {
+                    // only the transaction that changed the row looks at cmax, and all of its
+                    // later commands come after the change
+                    bulk.delete(*tid, 0, xid_to_64bit(xmax))
+                        .expect("failed to queue validate xmax command");
+                    deleted += 1;
+                }
+            });
+            bulk.finish().expect("failed to finish validate");
+
+            ZDB_LOG_LEVEL.get().log(&format!(
+                "[zombodb] validate:  index={}, docs={}, xmax_applied={}",
+                elasticsearch.base_url(),
+                reported,
+                deleted
+            ));
+        }
+        return stats;
+    }
 
-    // Find all rows with what we think is a *committed* xmax
-    //
-    // These rows can be deleted
-    let mut bulk = elasticsearch.start_bulk();
-    let by_xmax = delete_by_xmax(
-        &index_relation,
-        &elasticsearch,
-        es_index_name,
-        oldest_xmin,
-        &mut bulk,
-    );
-    bulk.finish().expect("failed to finish delete_by_xmax");
+    let (by_xmin, by_xmax, vacuumed) = if elasticsearch.is_rollover() {
+        vacuum_rollover(&elasticsearch, oldest_xmin)
+    } else {
+        // Find all rows with what we think is an *aborted* xmin
+        //
+        // These rows can be deleted
+        let mut bulk = elasticsearch.start_bulk();
+        let by_xmin = delete_by_xmin(
+            &index_relation,
+            &elasticsearch,
+            es_index_name,
+            oldest_xmin,
+            &mut bulk,
+        );
+        bulk.finish().expect("failed to finish delete_by_xmin");
+
+        // Find all rows with what we think is a *committed* xmax
+        //
+        // These rows can be deleted
+        let mut bulk = elasticsearch.start_bulk();
+        let by_xmax = delete_by_xmax(
+            &index_relation,
+            &elasticsearch,
+            es_index_name,
+            oldest_xmin,
+            &mut bulk,
+        );
+        bulk.finish().expect("failed to finish delete_by_xmax");
+
+        // Find all rows with what we think is an *aborted* xmax
+        //
+        // These rows can have their xmax reset to null because they're still live
+        let mut bulk = elasticsearch.start_bulk();
+        let vacuumed = vacuum_xmax(
+            &index_relation,
+            &elasticsearch,
+            es_index_name,
+            oldest_xmin,
+            &mut bulk,
+        );
+        bulk.finish().expect("failed to finish vacuum_xmax");
 
-    // Find all rows with what we think is an *aborted* xmax
-    //
-    // These rows can have their xmax reset to null because they're still live
-    let mut bulk = elasticsearch.start_bulk();
-    let vacuumed = vacuum_xmax(
-        &index_relation,
-        &elasticsearch,
-        es_index_name,
-        oldest_xmin,
-        &mut bulk,
-    );
-    bulk.finish().expect("failed to finish vacuum_xmax");
+        (by_xmin, by_xmax, vacuumed)
+    };
 
     // Finally, any "zdb_aborted_xid" value we have can be removed if it's
     // known to be aborted and no longer referenced anywhere in the index
@@ -146,6 +191,95 @@ pub extern "C" fn amvacuumcleanup(
     stats
 }
 
+/// Pass the ctid of every doc in the index to `report`
+fn report_indexed_tids<F: FnMut(pg_sys::ItemPointer)>(
+    index: &PgRelation,
+    elasticsearch: &Elasticsearch,
+    mut report: F,
+) -> usize {
+    let mut cnt = 0;
+    let docs = elasticsearch
+        .open_search(
+            ZDBQuery::new_with_query_dsl(serde_json::json! { { "match_all": {} } })
+                .prepare(index, None)
+                .0,
+        )
+        .execute()
+        .expect("failed to search for indexed docs");
+    for (_, ctid, _, _) in docs.into_iter() {
+        check_for_interrupts!();
+
+        let mut tid = pg_sys::ItemPointerData::default();
+        u64_to_item_pointer(ctid, &mut tid);
+        report(&mut tid);
+        cnt += 1;
+    }
+
+    cnt
+}
+
+/// The transaction that updated or deleted the heap tuple at `tid`, if one has and it committed
+/// or is still in progress.  A row that's only been locked, or HOT-updated and so still known to
+/// Elasticsearch by this tid, has none
+unsafe fn heap_tuple_xmax(
+    heap_relation: &PgRelation,
+    tid: pg_sys::ItemPointer,
+) -> Option<pg_sys::TransactionId> {
+    let mut tuple = pg_sys::HeapTupleData {
+        t_self: *tid,
+        ..Default::default()
+    };
+    let mut buf = 0 as pg_sys::Buffer;
+
+    #[cfg(any(feature = "pg13", feature = "pg14"))]
+    let found_tuple = pg_sys::heap_fetch(
+        heap_relation.as_ptr(),
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        &mut tuple,
+        &mut buf,
+    );
+
+    #[cfg(any(feature = "pg15"))]
+    let found_tuple = pg_sys::heap_fetch(
+        heap_relation.as_ptr(),
+        std::ptr::addr_of_mut!(pg_sys::SnapshotAnyData),
+        &mut tuple,
+        &mut buf,
+        false,
+    );
+
+    if !found_tuple {
+        return None;
+    }
+
+    pg_sys::LockBuffer(buf, pg_sys::BUFFER_LOCK_SHARE as i32);
+    let header = tuple.t_data;
+    let infomask = (*header).t_infomask as u32;
+    let infomask2 = (*header).t_infomask2 as u32;
+    let locked_only = infomask & pg_sys::HEAP_XMAX_LOCK_ONLY != 0
+        || infomask & (pg_sys::HEAP_XMAX_IS_MULTI | pg_sys::HEAP_LOCK_MASK)
+            == pg_sys::HEAP_XMAX_EXCL_LOCK;
+    let xmax = if infomask & pg_sys::HEAP_XMAX_INVALID != 0
+        || infomask2 & pg_sys::HEAP_HOT_UPDATED != 0
+        || locked_only
+    {
+        None
+    } else if infomask & pg_sys::HEAP_XMAX_IS_MULTI != 0 {
+        Some(pg_sys::HeapTupleGetUpdateXid(header))
+    } else {
+        Some((*header).t_choice.t_heap.t_xmax)
+    };
+    pg_sys::LockBuffer(buf, pg_sys::BUFFER_LOCK_UNLOCK as i32);
+    pg_sys::ReleaseBuffer(buf);
+
+    // a transaction that rolled back didn't change the row, and it may have done so before the
+    // index was ready, so its xid wouldn't be one of the index's aborted xids either
+    xmax.filter(|xmax| {
+        *xmax != pg_sys::InvalidTransactionId
+            && (pg_sys::TransactionIdIsInProgress(*xmax) || pg_sys::TransactionIdDidCommit(*xmax))
+    })
+}
+
 fn remove_aborted_xids(
     index: &PgRelation,
     elasticsearch: &Elasticsearch,
@@ -220,6 +354,79 @@ fn remove_aborted_xids(
     cnt
 }
 
+/// Vacuum a rollover index.  Its docs could be in any of its backing indices, so rather than
+/// changing them one by one they're deleted or changed by query, which also skips the backing
+/// indices ILM has already deleted.  Returns the same counts as the other vacuum steps
+fn vacuum_rollover(elasticsearch: &Elasticsearch, oldest_xmin: u32) -> (usize, usize, usize) {
+    // every xid here is either aborted or still in progress
+    let known_xids = elasticsearch
+        .get_document::<serde_json::Value>("zdb_aborted_xids", false)
+        .execute()
+        .expect("failed to get the zdb_aborted_xids doc")
+        .and_then(|doc| doc["_source"]["zdb_aborted_xids"].as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|xid| xid.as_u64())
+        .collect::<Vec<_>>();
+    let aborted_xids = known_xids
+        .iter()
+        .copied()
+        .filter(|xid64| {
+            let xid = *xid64 as pg_sys::TransactionId;
+            unsafe {
+                pg_sys::TransactionIdPrecedes(xid, oldest_xmin)
+                    && pg_sys::TransactionIdDidAbort(xid)
+                    && !pg_sys::TransactionIdDidCommit(xid)
+                    && !pg_sys::TransactionIdIsInProgress(xid)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // docs inserted by aborted transactions can be deleted
+    let by_xmin = if aborted_xids.is_empty() {
+        0
+    } else {
+        elasticsearch
+            .delete_by_query(serde_json::json! { { "terms": { "zdb_xmin": aborted_xids } } })
+            .execute()
+            .expect("failed to delete docs with aborted xmins") as usize
+    };
+
+    // as can docs whose rows were deleted by a transaction that committed before any running one
+    // started
+    let by_xmax = elasticsearch
+        .delete_by_query(serde_json::json! {
+            {
+                "bool": {
+                    "filter": [{ "range": { "zdb_xmax": { "lt": xid_to_64bit(oldest_xmin) } } }],
+                    "must_not": [{ "terms": { "zdb_xmax": known_xids } }]
+                }
+            }
+        })
+        .execute()
+        .expect("failed to delete docs with committed xmaxes") as usize;
+
+    // and docs whose rows' UPDATE or DELETE aborted are still live
+    let vacuumed = if aborted_xids.is_empty() {
+        0
+    } else {
+        elasticsearch
+            .update_by_query(
+                serde_json::json! { { "terms": { "zdb_xmax": aborted_xids } } },
+                serde_json::json! {
+                    {
+                        "source": "ctx._source.remove('zdb_cmax');ctx._source.remove('zdb_xmax');",
+                        "lang": "painless"
+                    }
+                },
+            )
+            .execute()
+            .expect("failed to reset aborted xmaxes") as usize
+    };
+
+    (by_xmin, by_xmax, vacuumed)
+}
+
 fn vacuum_xmax(
     index: &PgRelation,
     elasticsearch: &Elasticsearch,
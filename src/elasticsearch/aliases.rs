@@ -27,12 +27,18 @@ impl ElasticsearchAliasRequest {
     }
 
     pub fn execute(self) -> std::result::Result<(), ElasticsearchError> {
+        // a rollover index's aliases also cover its backing indices
+        let mut indices = vec![self.elasticsearch.index_name().to_owned()];
+        if self.elasticsearch.is_rollover() {
+            indices.push(self.elasticsearch.backing_index_pattern());
+        }
+
         let json_body = match &self.command {
             AliasCommand::Add(alias_name) => {
                 json! {
                     {
                        "actions": [
-                            {"add": { "index": self.elasticsearch.index_name(), "alias": alias_name } }
+                            {"add": { "indices": indices, "alias": alias_name } }
                         ]
                     }
                 }
@@ -42,7 +48,7 @@ impl ElasticsearchAliasRequest {
                 json! {
                     {
                        "actions": [
-                            {"remove": { "index": self.elasticsearch.index_name(), "alias": alias_name } }
+                            {"remove": { "indices": indices, "alias": alias_name } }
                         ]
                     }
                 }
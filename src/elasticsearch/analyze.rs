@@ -9,7 +9,7 @@ pub struct AnalyzedData {
     pub tokens: Vec<Token>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Token {
     #[serde(rename = "type")]
     pub type_: String,
@@ -91,6 +91,26 @@ impl ElasticsearchAnalyzerRequest {
         }
     }
 
+    /// Analyzes each of `analyze_texts` against `analyze_field` in a single request.  Elasticsearch
+    /// treats them like the values of a multi-valued field, so the returned token offsets continue
+    /// from one text to the next
+    pub fn new_with_field_texts(
+        elasticsearch: &Elasticsearch,
+        analyze_field: &str,
+        analyze_texts: &[&str],
+    ) -> ElasticsearchAnalyzerRequest {
+        ElasticsearchAnalyzerRequest {
+            elasticsearch: elasticsearch.clone(),
+            analyze_json: json!(
+               {
+                "field": analyze_field,
+                "text": analyze_texts
+                }
+            ),
+            url: format!("{}/_analyze", elasticsearch.base_url()),
+        }
+    }
+
     pub fn new_custom<'a>(
         elasticsearch: &Elasticsearch,
         field: Option<&'a str>,
@@ -0,0 +1,83 @@
+use aws_config::meta::credentials::CredentialsProviderChain;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_credential_types::Credentials;
+use lazy_static::*;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Credentials are refreshed when they're this close to expiring
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct CredentialsCache {
+    provider: Option<CredentialsProviderChain>,
+    credentials: Option<Credentials>,
+}
+
+lazy_static! {
+    /// The one runtime this backend uses to drive the AWS SDK's async credential providers
+    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create tokio runtime");
+    static ref CACHE: Mutex<CredentialsCache> = Mutex::new(CredentialsCache::default());
+}
+
+/// Credentials from the default AWS credentials provider chain.  They're shared by every request
+/// this backend makes, until they're about to expire
+pub fn default_credentials() -> Credentials {
+    // nothing we do while holding the lock leaves the cache half-updated, so it's fine to use
+    // even if a panic poisoned it
+    let mut guard = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cache = &mut *guard;
+
+    if let Some(credentials) = &cache.credentials {
+        let fresh = credentials
+            .expiry()
+            .map_or(true, |expiry| expiry > SystemTime::now() + EXPIRY_MARGIN);
+        if fresh {
+            return credentials.clone();
+        }
+    }
+
+    let result = RUNTIME.block_on(async {
+        if cache.provider.is_none() {
+            cache.provider = Some(CredentialsProviderChain::default_provider().await);
+        }
+        cache.provider.as_ref().unwrap().provide_credentials().await
+    });
+    match result {
+        Ok(credentials) => {
+            cache.credentials = Some(credentials.clone());
+            credentials
+        }
+        Err(e) => {
+            // don't panic while holding the lock
+            drop(guard);
+            panic!("unable to retrieve credentials: {}", e)
+        }
+    }
+}
+
+/// Is `url` a `_bulk` request?  Their bodies are the ones large enough to be worth not hashing
+pub fn is_bulk_request(url: &str) -> bool {
+    url.split('?').next().unwrap_or(url).ends_with("/_bulk")
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::elasticsearch::aws::is_bulk_request;
+    use pgrx::*;
+
+    #[pg_test]
+    fn test_is_bulk_request() {
+        assert!(is_bulk_request(
+            "https://search.example.com/abc/_bulk?format=json&filter_path=errors"
+        ));
+        assert!(!is_bulk_request("https://search.example.com/abc/_search"));
+        assert!(!is_bulk_request(
+            "https://search.example.com/abc/_doc/1?q=_bulk"
+        ));
+    }
+}
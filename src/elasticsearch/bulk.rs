@@ -1,10 +1,11 @@
-use crate::access_method::options::RefreshInterval;
+use crate::access_method::options::{MirrorPolicy, RefreshInterval};
 use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
 use crate::executor_manager::get_executor_manager;
 use crate::gucs::ZDB_LOG_LEVEL;
 use crate::json::builder::JsonBuilder;
 use crossbeam::channel::{RecvTimeoutError, SendTimeoutError};
 use dashmap::DashSet;
+use indexmap::IndexMap;
 use pgrx::itemptr::item_pointer_to_u64;
 use pgrx::pg_sys::elog::interrupt_pending;
 use pgrx::*;
@@ -36,6 +37,7 @@ pub enum BulkRequestCommand {
         ctid: u64,
         cmax: pg_sys::CommandId,
         xmax: u64,
+        if_exists: bool,
     },
     TransactionInProgress {
         xid: u64,
@@ -75,6 +77,10 @@ pub struct ElasticsearchBulkRequest {
     concurrency: usize,
     batch_size: usize,
     error_receiver: crossbeam::channel::Receiver<BulkRequestError>,
+
+    /// The `(ctid, cmax, xmax, if_exists)` of each row UPDATEd or DELETEd in a rollover index,
+    /// whose docs are found across its backing indices once everything before them has been sent
+    rollover_changes: Vec<(u64, pg_sys::CommandId, u64, bool)>,
 }
 
 impl Clone for ElasticsearchBulkRequest {
@@ -112,11 +118,22 @@ impl ElasticsearchBulkRequest {
             concurrency,
             batch_size,
             error_receiver: erx,
+            rollover_changes: Vec::new(),
         }
     }
 
+    /// Leave refreshing the index to the caller once this request has finished
+    pub fn without_refresh(mut self) -> Self {
+        self.do_refresh = false;
+        self
+    }
+
     pub fn finish(mut self) -> Result<(usize, usize), BulkRequestError> {
         self.handler.check_for_error();
+        let rollover_changes = std::mem::take(&mut self.rollover_changes);
+        self.handler
+            .flush_pending()
+            .expect("failed to queue pending inserts");
 
         // do we have any deferred commands we need to process again?
         let deferred_commands = std::mem::take(&mut self.handler.deferred);
@@ -128,12 +145,22 @@ impl ElasticsearchBulkRequest {
         // need to clone the successful_requests counter so we can get an accurate
         // count after we've called .wait_for_completion()
         let successful_requests = self.handler.successful_requests.clone();
+        let mirror_failures = self.handler.mirror_failures.clone();
         let elasticsearch = self.handler.elasticsearch.clone();
 
         // wait for the bulk requests to finish
         let mut total_docs = self.handler.wait_for_completion()?;
         let mut nrequests = successful_requests.load(Ordering::SeqCst);
 
+        let mirror_failures = mirror_failures.load(Ordering::SeqCst);
+        if mirror_failures > 0 {
+            warning!(
+                "[zombodb] {} _bulk requests failed against mirror clusters of {}.  See zdb.mirror_status()",
+                mirror_failures,
+                elasticsearch.base_url()
+            );
+        }
+
         // requeue any deferred commands
         if deferred_request.is_some() {
             ZDB_LOG_LEVEL.get().log(&format!(
@@ -154,6 +181,11 @@ impl ElasticsearchBulkRequest {
             nrequests += nr;
         }
 
+        if !rollover_changes.is_empty() {
+            total_docs +=
+                ElasticsearchBulkRequest::apply_rollover_changes(&elasticsearch, rollover_changes)?;
+        }
+
         // now refresh the index if we actually modified it
         if self.do_refresh && total_docs != 0 && nrequests != 0 {
             match elasticsearch.options.refresh_interval() {
@@ -174,12 +206,97 @@ impl ElasticsearchBulkRequest {
         Ok((total_docs, nrequests))
     }
 
+    /// Set the xmax of the docs of rows UPDATEd or DELETEd in a rollover index, in whichever of
+    /// its backing indices they are.  Returns how many docs were changed
+    fn apply_rollover_changes(
+        elasticsearch: &Elasticsearch,
+        changes: Vec<(u64, pg_sys::CommandId, u64, bool)>,
+    ) -> Result<usize, BulkRequestError> {
+        // the docs of rows this transaction inserted have to be searchable to be found
+        ElasticsearchBulkRequest::refresh_index(elasticsearch.clone())?;
+
+        let mut updated = 0;
+        for chunk in changes.chunks(ROLLOVER_CHANGES_PER_REQUEST) {
+            let ctids = chunk.iter().map(|(ctid, ..)| *ctid).collect::<Vec<_>>();
+            let changes = chunk
+                .iter()
+                .map(|(ctid, cmax, xmax, _)| (ctid.to_string(), json!([cmax, xmax])))
+                .collect::<serde_json::Map<_, _>>();
+
+            // every doc has to be found, except those of rows an index being built CONCURRENTLY
+            // might not have yet
+            let expected = chunk
+                .iter()
+                .filter(|(.., if_exists)| !if_exists)
+                .map(|(ctid, ..)| *ctid)
+                .collect::<HashSet<_>>()
+                .len();
+            let query = json! { { "terms": { "zdb_ctid": ctids } } };
+            let script = json! {
+                {
+                    "source": "def change = params.CHANGES[String.valueOf(ctx._source.zdb_ctid)];ctx._source.zdb_cmax=change[0];ctx._source.zdb_xmax=change[1];",
+                    "lang": "painless",
+                    "params": {
+                        "CHANGES": changes
+                    }
+                }
+            };
+
+            let chunk_updated = elasticsearch
+                .update_by_query(query.clone(), script.clone())
+                .execute()
+                .map_err(|e| BulkRequestError::IndexingError(e.message().to_string()))?
+                as usize;
+            if chunk_updated < expected {
+                return Err(BulkRequestError::IndexingError(format!(
+                    "only found {} of the {} docs of UPDATEd or DELETEd rows",
+                    chunk_updated, expected
+                )));
+            }
+            updated += chunk_updated;
+
+            for mirror in elasticsearch.mirrors() {
+                if let Err(e) = mirror
+                    .update_by_query(query.clone(), script.clone())
+                    .execute()
+                {
+                    if elasticsearch.mirror_policy() == MirrorPolicy::All {
+                        return Err(BulkRequestError::IndexingError(format!(
+                            "mirror {}: {}",
+                            mirror.url(),
+                            e.message()
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Does finishing this request still have UPDATEs or DELETEs of rollover index rows to make?
+    pub fn has_rollover_changes(&self) -> bool {
+        !self.rollover_changes.is_empty()
+    }
+
     fn refresh_index(elasticsearch: Elasticsearch) -> Result<(), BulkRequestError> {
         if let Err(e) = elasticsearch.refresh_index().execute() {
-            Err(BulkRequestError::RefreshError(e.message().to_string()))
-        } else {
-            Ok(())
+            return Err(BulkRequestError::RefreshError(e.message().to_string()));
         }
+
+        for mirror in elasticsearch.mirrors() {
+            if let Err(e) = mirror.refresh_index().execute() {
+                if elasticsearch.mirror_policy() == MirrorPolicy::All {
+                    return Err(BulkRequestError::RefreshError(format!(
+                        "mirror {}: {}",
+                        mirror.url(),
+                        e.message()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn terminate(
@@ -202,6 +319,11 @@ impl ElasticsearchBulkRequest {
         )
     }
 
+    /// How many of the docs sent so far Elasticsearch has acknowledged
+    pub fn acknowledged(&self) -> usize {
+        self.handler.acknowledged_docs.load(Ordering::SeqCst)
+    }
+
     pub fn insert(
         &mut self,
         ctid: pg_sys::ItemPointerData,
@@ -213,8 +335,25 @@ impl ElasticsearchBulkRequest {
     ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
         self.handler.check_for_error();
 
-        let prior_update = self.handler.prior_update.take();
-        self.handler.queue_command(BulkRequestCommand::Insert {
+        // the UPDATE that queued a prior update always runs `aminsert` in the same command.  If
+        // this insert is from a later command, that UPDATE was a HOT update and there's nothing
+        // to send for it
+        let mut prior_update = self.handler.prior_update.take().filter(|prior_update| {
+            matches!(prior_update, BulkRequestCommand::Update { cmax, .. } if *cmax == cmin)
+        });
+        if self.elasticsearch.is_rollover() {
+            // the old doc is in whichever backing index, not necessarily the one this is written to
+            if let Some(BulkRequestCommand::Update {
+                ctid,
+                cmax,
+                xmax,
+                if_exists,
+            }) = prior_update.take()
+            {
+                self.queue_update(ctid, cmax, xmax, if_exists)?;
+            }
+        }
+        let command = BulkRequestCommand::Insert {
             prior_update: prior_update.map(|c| Box::new(c)),
             ctid: item_pointer_to_u64(ctid),
             cmin,
@@ -222,7 +361,15 @@ impl ElasticsearchBulkRequest {
             xmin,
             xmax,
             builder,
-        })
+        };
+
+        // only a row this transaction wrote can be changed again before we're finished
+        let current_xid = unsafe { pg_sys::GetCurrentTransactionIdIfAny() };
+        if current_xid != pg_sys::InvalidTransactionId && xmin == xid_to_64bit(current_xid) {
+            self.handler.queue_pending_insert(command)
+        } else {
+            self.handler.queue_command(command)
+        }
     }
 
     pub fn update(
@@ -233,31 +380,78 @@ impl ElasticsearchBulkRequest {
     ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
         self.handler.check_for_error();
 
-        if self.handler.prior_update.is_some() {
-            panic!("Bulk Handler already has a queued prior update tuple")
-        }
-
-        // hold onto this, we'll use it during self.insert()
+        // hold onto this, we'll use it during self.insert().  If we're still holding one from an
+        // earlier UPDATE, that UPDATE was a HOT update, which doesn't call `aminsert`.  Its row is
+        // still known to Elasticsearch by the HOT chain's root, whose indexed values didn't change,
+        // so there's nothing to send for it.  See also `forget_prior_update()`
         self.handler.prior_update = Some(BulkRequestCommand::Update {
             ctid: item_pointer_to_u64(ctid),
             cmax,
             xmax,
+            if_exists: false,
         });
 
         Ok(())
     }
 
+    /// Drop the prior update left behind by a HOT update, which never reaches `aminsert`, so
+    /// that it can't be attached to an unrelated insert
+    pub fn forget_prior_update(&mut self) {
+        self.handler.prior_update.take();
+    }
+
     pub fn delete(
         &mut self,
         ctid: pg_sys::ItemPointerData,
         cmax: pg_sys::CommandId,
         xmax: u64,
+    ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        self.queue_delete(ctid, cmax, xmax, false)
+    }
+
+    /// Same as `delete()`, but the doc might not exist, as in an index being built CONCURRENTLY
+    pub fn delete_if_exists(
+        &mut self,
+        ctid: pg_sys::ItemPointerData,
+        cmax: pg_sys::CommandId,
+        xmax: u64,
+    ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        self.queue_delete(ctid, cmax, xmax, true)
+    }
+
+    fn queue_delete(
+        &mut self,
+        ctid: pg_sys::ItemPointerData,
+        cmax: pg_sys::CommandId,
+        xmax: u64,
+        if_exists: bool,
     ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
         self.handler.check_for_error();
+        self.queue_update(item_pointer_to_u64(ctid), cmax, xmax, if_exists)
+    }
 
-        let ctid = item_pointer_to_u64(ctid);
-        let command = BulkRequestCommand::Update { ctid, cmax, xmax };
-        if self.handler.in_flight.contains(&ctid) {
+    /// Queue the change of a doc's xmax on its own, rather than along with an insert
+    fn queue_update(
+        &mut self,
+        ctid: u64,
+        cmax: pg_sys::CommandId,
+        xmax: u64,
+        if_exists: bool,
+    ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        let command = BulkRequestCommand::Update {
+            ctid,
+            cmax,
+            xmax,
+            if_exists,
+        };
+        if self.handler.fold_into_pending(&command) {
+            Ok(())
+        } else if self.elasticsearch.is_rollover() {
+            // the doc could be in any of a rollover index's backing indices, so it can't be
+            // changed by its _id.  `finish()` finds it instead
+            self.rollover_changes.push((ctid, cmax, xmax, if_exists));
+            Ok(())
+        } else if self.handler.in_flight.contains(&ctid) {
             self.handler.deferred.push(command);
             Ok(())
         } else {
@@ -280,8 +474,18 @@ impl ElasticsearchBulkRequest {
     pub fn transaction_committed(
         &mut self,
         xid: pg_sys::TransactionId,
+    ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        self.transaction_committed_xid64(xid_to_64bit(xid))
+    }
+
+    /// Same as `transaction_committed()`, for a transaction that may be from an earlier xid epoch,
+    /// such as a prepared transaction
+    pub fn transaction_committed_xid64(
+        &mut self,
+        xid: u64,
     ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
         self.handler.check_for_error();
+        self.handler.flush_pending()?;
 
         // the transaction committed command needs to be the last command we send
         // so we busy-loop wait until there's only 1 (or zero) active threads
@@ -292,9 +496,7 @@ impl ElasticsearchBulkRequest {
         }
 
         self.handler
-            .queue_command(BulkRequestCommand::TransactionCommitted {
-                xid: xid_to_64bit(xid),
-            })
+            .queue_command(BulkRequestCommand::TransactionCommitted { xid })
     }
 
     pub fn delete_by_xmin(
@@ -348,17 +550,28 @@ impl ElasticsearchBulkRequest {
 
 const BULK_FILTER_PATH: &str = "errors,items.*.error";
 
+/// How many rollover index docs are changed by each `_update_by_query`
+const ROLLOVER_CHANGES_PER_REQUEST: usize = 10_000;
+
 pub(crate) struct Handler {
     when_started: Instant,
     terminated: Arc<AtomicBool>,
     threads: Vec<Option<JoinHandle<usize>>>,
     prior_update: Option<BulkRequestCommand>,
+
+    /// Inserts of rows this transaction wrote, held back until there's a batch of them so that a
+    /// later UPDATE or DELETE of one of those rows, such as from `INSERT ... ON CONFLICT DO UPDATE`
+    /// or `MERGE`, becomes part of its `index` action rather than another action on the same doc
+    pending: IndexMap<u64, BulkRequestCommand, BuildHasherDefault<FxHasher>>,
     in_flight: Arc<DashSet<u64, BuildHasherDefault<FxHasher>>>,
     deferred: Vec<BulkRequestCommand>,
     total_docs: usize,
     active_threads: Arc<AtomicUsize>,
     successful_requests: Arc<AtomicUsize>,
+    acknowledged_docs: Arc<AtomicUsize>,
+    mirror_failures: Arc<AtomicUsize>,
     elasticsearch: Elasticsearch,
+    mirrors: Vec<Elasticsearch>,
     concurrency: usize,
     batch_size: usize,
     queue_size: usize,
@@ -380,6 +593,10 @@ struct BulkReceiver {
     buffer_offset: usize,
     batch_size: usize,
     queue_size: usize,
+    copy: Option<Vec<u8>>,
+
+    /// A rollover index's documents are written through its write alias
+    write_index: Option<String>,
 }
 
 impl std::io::Read for BulkReceiver {
@@ -425,6 +642,11 @@ impl std::io::Read for BulkReceiver {
                 // serialize this comment (into `self.buffer`)
                 self.serialize_command(command);
 
+                // and keep a copy of it if we need to send the same request to mirror clusters
+                if let Some(copy) = self.copy.as_mut() {
+                    copy.extend_from_slice(&self.buffer);
+                }
+
                 // and start to write out what we can
                 // our buffer_offset should be zero here as the prior contents
                 // of the buffer should have been written above
@@ -469,13 +691,16 @@ impl BulkReceiver {
                     self.serialize_command(*prior_update.unwrap());
                 }
 
-                serde_json::to_writer(
-                    &mut self.buffer,
-                    &json! {
+                let action = match &self.write_index {
+                    Some(write_index) => json! {
+                        {"index": {"_id": ctid, "_index": write_index } }
+                    },
+                    None => json! {
                         {"index": {"_id": ctid } }
                     },
-                )
-                .expect("failed to serialize index line");
+                };
+                serde_json::to_writer(&mut self.buffer, &action)
+                    .expect("failed to serialize index line");
                 self.buffer.push(b'\n');
 
                 doc.add_u64("\"zdb_ctid\"".into(), ctid);
@@ -491,7 +716,12 @@ impl BulkReceiver {
                 doc.build(&mut self.buffer);
                 self.buffer.push(b'\n');
             }
-            BulkRequestCommand::Update { ctid, cmax, xmax } => {
+            BulkRequestCommand::Update {
+                ctid,
+                cmax,
+                xmax,
+                if_exists,
+            } => {
                 serde_json::to_writer(
                     &mut self.buffer,
                     &json! {
@@ -506,9 +736,25 @@ impl BulkReceiver {
                 .expect("failed to serialize update line");
                 self.buffer.push(b'\n');
 
-                serde_json::to_writer(
-                    &mut self.buffer,
-                    &json! {
+                let command = if if_exists {
+                    // a scripted upsert runs the script even when the doc is missing, in which
+                    // case it does nothing
+                    json! {
+                        {
+                            "script": {
+                                "source": "if (ctx._source.zdb_ctid == null) { ctx.op='none'; } else { ctx._source.zdb_cmax=params.CMAX;ctx._source.zdb_xmax=params.XMAX; }",
+                                "lang": "painless",
+                                "params": {
+                                    "CMAX": cmax,
+                                    "XMAX": xmax
+                                }
+                            },
+                            "scripted_upsert": true,
+                            "upsert": {}
+                        }
+                    }
+                } else {
+                    json! {
                         {
                             "script": {
                                 "source": "ctx._source.zdb_cmax=params.CMAX;ctx._source.zdb_xmax=params.XMAX;",
@@ -519,9 +765,10 @@ impl BulkReceiver {
                                 }
                             }
                         }
-                    },
-                )
-                .expect("failed to serialize update command");
+                    }
+                };
+                serde_json::to_writer(&mut self.buffer, &command)
+                    .expect("failed to serialize update command");
                 self.buffer.push(b'\n');
             }
             BulkRequestCommand::TransactionInProgress { xid } => {
@@ -706,11 +953,15 @@ impl Handler {
             terminated: Arc::new(AtomicBool::new(false)),
             threads: Vec::new(),
             prior_update: None,
+            pending: Default::default(),
             in_flight: Arc::new(DashSet::default()),
             deferred: Default::default(),
             total_docs: 0,
             active_threads: Arc::new(AtomicUsize::new(0)),
             successful_requests: Arc::new(AtomicUsize::new(0)),
+            acknowledged_docs: Arc::new(AtomicUsize::new(0)),
+            mirror_failures: Arc::new(AtomicUsize::new(0)),
+            mirrors: elasticsearch.mirrors(),
             elasticsearch,
             batch_size,
             queue_size,
@@ -730,19 +981,68 @@ impl Handler {
         self.queue_command_ex(command, false)
     }
 
+    fn queue_pending_insert(
+        &mut self,
+        mut command: BulkRequestCommand,
+    ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        if let BulkRequestCommand::Insert { prior_update, .. } = &mut command {
+            if prior_update
+                .as_ref()
+                .is_some_and(|update| self.fold_into_pending(update))
+            {
+                prior_update.take();
+            }
+        }
+
+        self.remember_current_xid();
+        if let BulkRequestCommand::Insert { ctid, .. } = &command {
+            self.pending.insert(*ctid, command);
+        }
+
+        if self.pending.len() >= self.queue_size {
+            self.flush_pending()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// If `update` changes a row whose insert we're still holding back, apply it to the insert
+    /// instead and return true
+    fn fold_into_pending(&mut self, update: &BulkRequestCommand) -> bool {
+        if let BulkRequestCommand::Update {
+            ctid, cmax, xmax, ..
+        } = update
+        {
+            if let Some(BulkRequestCommand::Insert {
+                cmax: pending_cmax,
+                xmax: pending_xmax,
+                ..
+            }) = self.pending.get_mut(ctid)
+            {
+                *pending_cmax = *cmax;
+                *pending_xmax = *xmax;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn flush_pending(&mut self) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
+        for (_, command) in std::mem::take(&mut self.pending) {
+            self.queue_command(command)?;
+        }
+        Ok(())
+    }
+
     pub fn queue_command_ex(
         &mut self,
         mut command: BulkRequestCommand,
         is_deferred: bool,
     ) -> Result<(), crossbeam::channel::SendError<BulkRequestCommand>> {
-        if !is_deferred && self.current_xid.is_none() {
+        if !is_deferred {
             match &command {
                 BulkRequestCommand::Insert { .. } | BulkRequestCommand::Update { .. } => {
-                    let current_xid = unsafe { pg_sys::GetCurrentTransactionId() };
-                    if current_xid != pg_sys::InvalidTransactionId {
-                        get_executor_manager().push_xid(current_xid);
-                    }
-                    self.current_xid.replace(current_xid);
+                    self.remember_current_xid()
                 }
                 _ => {}
             }
@@ -838,6 +1138,16 @@ impl Handler {
         Ok(())
     }
 
+    fn remember_current_xid(&mut self) {
+        if self.current_xid.is_none() {
+            let current_xid = unsafe { pg_sys::GetCurrentTransactionId() };
+            if current_xid != pg_sys::InvalidTransactionId {
+                get_executor_manager().push_xid(current_xid);
+            }
+            self.current_xid.replace(current_xid);
+        }
+    }
+
     fn create_thread(&self, _thread_id: usize) -> JoinHandle<usize> {
         let base_url = self.elasticsearch.base_url();
         let bulk_receiver = self.bulk_receiver.clone();
@@ -848,7 +1158,19 @@ impl Handler {
         let queue_size = self.queue_size;
         let active_threads = self.active_threads.clone();
         let successful_requests = self.successful_requests.clone();
+        let acknowledged_docs = self.acknowledged_docs.clone();
         let client = self.elasticsearch.client();
+        let write_index = self
+            .elasticsearch
+            .is_rollover()
+            .then(|| self.elasticsearch.write_alias());
+        let mirror_policy = self.elasticsearch.mirror_policy();
+        let mirror_failures = self.mirror_failures.clone();
+        let mirrors = self
+            .mirrors
+            .iter()
+            .map(|mirror| (mirror.base_url(), mirror.client()))
+            .collect::<Vec<_>>();
 
         self.active_threads.fetch_add(1, Ordering::SeqCst);
         std::thread::spawn(move || {
@@ -890,6 +1212,12 @@ impl Handler {
                         docs_out: 0,
                         buffer: Vec::with_capacity(16384),
                         buffer_offset: 0,
+                        copy: if mirrors.is_empty() {
+                            None
+                        } else {
+                            Some(Vec::new())
+                        },
+                        write_index: write_index.clone(),
                     };
 
                     let url = format!(
@@ -902,47 +1230,9 @@ impl Handler {
                             client.post(&url).set("content-type", "application/json"),
                             &mut reader,
                             |body| {
-                                #[derive(Serialize, Deserialize, Debug)]
-                                struct ErrorObject {
-                                    reason: String,
-                                }
-
-                                #[derive(Serialize, Deserialize, Debug)]
-                                struct BulkResponse {
-                                    error: Option<ErrorObject>,
-                                    errors: Option<bool>,
-                                    items: Option<Vec<Value>>,
-                                }
-
-                                // NB:  this is stupid that ES forces us to parse the response for requests
-                                // that contain an error, but here we are
-                                let result: serde_json::Result<BulkResponse> =
-                                    serde_json::de::from_reader(body);
-                                match result {
-                                    // result deserialized okay, lets see if it's what we need
-                                    Ok(response) => {
-                                        if !response.errors.unwrap_or(false)
-                                            && response.error.is_none()
-                                        {
-                                            successful_requests.fetch_add(1, Ordering::SeqCst);
-                                            Ok(())
-                                        } else {
-                                            // yup, the response contains an error
-                                            Err(ElasticsearchError(
-                                                Some(200), // but it was given to us as a 200 OK, otherwise we wouldn't be here at all
-                                                match serde_json::to_string(&response) {
-                                                    Ok(s) => s,
-                                                    Err(e) => format!("{:?}", e),
-                                                },
-                                            ))
-                                        }
-                                    }
-
-                                    // couldn't deserialize the result
-                                    Err(e) => {
-                                        Err(ElasticsearchError(Some(200), format!("{:?}", e)))
-                                    }
-                                }
+                                parse_bulk_response(body)?;
+                                successful_requests.fetch_add(1, Ordering::SeqCst);
+                                Ok(())
                             },
                         )
                     }));
@@ -971,6 +1261,50 @@ impl Handler {
                         Handler::send_error(error, e.status(), e.message());
                         break;
                     }
+                    acknowledged_docs.fetch_add(reader.docs_out, Ordering::SeqCst);
+
+                    // send the exact same request to each mirror cluster
+                    if let Some(copy) = reader.copy.take() {
+                        let mut mirror_error = None;
+                        for (mirror_url, mirror_client) in &mirrors {
+                            let url = format!(
+                                "{}/_bulk?format=json&filter_path={}",
+                                mirror_url, BULK_FILTER_PATH
+                            );
+
+                            let response = catch_unwind(AssertUnwindSafe(|| {
+                                Elasticsearch::execute_request(
+                                    mirror_client
+                                        .post(&url)
+                                        .set("content-type", "application/json"),
+                                    copy.as_slice(),
+                                    parse_bulk_response,
+                                )
+                            }));
+
+                            let (code, message) = match response {
+                                Ok(Ok(())) => continue,
+                                Ok(Err(e)) => (e.status(), e.message().to_string()),
+                                Err(e) => (None, downcast_err(e)),
+                            };
+
+                            if mirror_policy == MirrorPolicy::All {
+                                mirror_error =
+                                    Some((code, format!("mirror {}: {}", mirror_url, message)));
+                                break;
+                            } else {
+                                // the mirror is now behind, which zdb.mirror_status() will show
+                                mirror_failures.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+
+                        if let Some((code, message)) = mirror_error {
+                            drop(bulk_receiver);
+                            terminated.store(true, Ordering::SeqCst);
+                            Handler::send_error(error, code, &message);
+                            break;
+                        }
+                    }
 
                     // remove from our set of "in flight" ctids those that we consumed
                     // during this request
@@ -1068,6 +1402,44 @@ impl Handler {
     }
 }
 
+fn parse_bulk_response(body: &mut (dyn std::io::Read + Send)) -> Result<(), ElasticsearchError> {
+    #[derive(Serialize, Deserialize, Debug)]
+    struct ErrorObject {
+        reason: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct BulkResponse {
+        error: Option<ErrorObject>,
+        errors: Option<bool>,
+        items: Option<Vec<Value>>,
+    }
+
+    // NB:  this is stupid that ES forces us to parse the response for requests
+    // that contain an error, but here we are
+    let result: serde_json::Result<BulkResponse> = serde_json::de::from_reader(body);
+    match result {
+        // result deserialized okay, lets see if it's what we need
+        Ok(response) => {
+            if !response.errors.unwrap_or(false) && response.error.is_none() {
+                Ok(())
+            } else {
+                // yup, the response contains an error
+                Err(ElasticsearchError(
+                    Some(200), // but it was given to us as a 200 OK, otherwise we wouldn't be here at all
+                    match serde_json::to_string(&response) {
+                        Ok(s) => s,
+                        Err(e) => format!("{:?}", e),
+                    },
+                ))
+            }
+        }
+
+        // couldn't deserialize the result
+        Err(e) => Err(ElasticsearchError(Some(200), format!("{:?}", e))),
+    }
+}
+
 fn downcast_err(e: Box<dyn Any + Send>) -> String {
     if let Some(s) = e.downcast_ref::<&str>() {
         (*s).to_string()
@@ -1078,3 +1450,84 @@ fn downcast_err(e: Box<dyn Any + Send>) -> String {
         "Box<Any>".to_string()
     }
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    fn count(table: &str, query: &str) -> i64 {
+        Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM {table} WHERE {table} ==> '{query}';"
+        ))
+        .expect("SPI failed")
+        .expect("count was NULL")
+    }
+
+    /// How many docs `index` has for rows, and the highest `_version` of any of them, which is
+    /// how many bulk actions Elasticsearch applied to that doc
+    fn doc_versions(index: &str) -> (i64, i64) {
+        let (docs, max_version) = Spi::get_two::<i64, i64>(&format!(
+            r#"SELECT count(*), max((hit->>'_version')::int8)
+                 FROM jsonb_array_elements((zdb.request('{index}', '_search?version=true&size=100&filter_path=hits.hits._version', 'POST',
+                      '{{"query": {{"exists": {{"field": "zdb_ctid"}}}}}}'))::jsonb->'hits'->'hits') hit"#
+        ))
+        .expect("SPI failed");
+        (
+            docs.expect("docs was NULL"),
+            max_version.expect("max_version was NULL"),
+        )
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_on_conflict_upserts() {
+        Spi::run(
+            "CREATE TABLE test_upserts (id int8 PRIMARY KEY, title text);
+             CREATE INDEX idxtest_upserts ON test_upserts USING zombodb ((test_upserts.*));
+             INSERT INTO test_upserts VALUES (1, 'one'), (2, 'two');
+             INSERT INTO test_upserts VALUES (1, 'uno'), (3, 'three')
+                 ON CONFLICT (id) DO UPDATE SET title = excluded.title;
+             INSERT INTO test_upserts VALUES (1, 'eins'), (3, 'drei')
+                 ON CONFLICT (id) DO UPDATE SET title = excluded.title;",
+        )
+        .expect("SPI failed");
+
+        assert_eq!(count("test_upserts", "title:one"), 0);
+        assert_eq!(count("test_upserts", "title:uno"), 0);
+        assert_eq!(count("test_upserts", "title:eins"), 1);
+        assert_eq!(count("test_upserts", "title:drei"), 1);
+        assert_eq!(count("test_upserts", "id:*"), 3);
+
+        // one doc for each of the six row versions, each sent in a single `index` action that
+        // already carried the xmax of the rows that were updated
+        assert_eq!(doc_versions("idxtest_upserts"), (6, 1));
+    }
+
+    #[cfg(feature = "pg15")]
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_merge_upserts() {
+        Spi::run(
+            "CREATE TABLE test_merge (id int8 PRIMARY KEY, title text);
+             CREATE INDEX idxtest_merge ON test_merge USING zombodb ((test_merge.*));
+             INSERT INTO test_merge VALUES (1, 'one'), (2, 'two');
+             MERGE INTO test_merge t USING (VALUES (1, 'uno'), (3, 'three')) AS s(id, title) ON t.id = s.id
+                 WHEN MATCHED THEN UPDATE SET title = s.title
+                 WHEN NOT MATCHED THEN INSERT VALUES (s.id, s.title);
+             MERGE INTO test_merge t USING (VALUES (1, 'eins'), (3, NULL)) AS s(id, title) ON t.id = s.id
+                 WHEN MATCHED AND s.title IS NULL THEN DELETE
+                 WHEN MATCHED THEN UPDATE SET title = s.title;",
+        )
+        .expect("SPI failed");
+
+        assert_eq!(count("test_merge", "title:uno"), 0);
+        assert_eq!(count("test_merge", "title:eins"), 1);
+        assert_eq!(count("test_merge", "title:three"), 0);
+        assert_eq!(count("test_merge", "id:*"), 2);
+
+        // one doc for each of the five row versions, each sent in a single `index` action that
+        // already carried the xmax of the rows that were updated or deleted
+        assert_eq!(doc_versions("idxtest_merge"), (5, 1));
+    }
+}
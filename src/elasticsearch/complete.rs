@@ -0,0 +1,369 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use crate::utils::{lookup_es_field_type, lookup_es_subfield_type};
+use crate::zdbquery::mvcc::apply_visibility_clause;
+use crate::zdbquery::ZDBQuery;
+use pgrx::itemptr::u64_to_item_pointer;
+use pgrx::prelude::*;
+use pgrx::*;
+use serde::*;
+use serde_json::*;
+use std::collections::HashSet;
+
+/// How many more suggestions than asked for we ask a completion suggester for, as it can't apply
+/// our visibility rules and some of what it suggests may come from rows we can't see
+const COMPLETION_OVERFETCH: u32 = 4;
+
+pub enum CompletionField {
+    /// A `search_as_you_type` field, searched with a `bool_prefix` query
+    SearchAsYouType(String),
+
+    /// A `completion` field, asked for suggestions by the completion suggester
+    Completion(String),
+}
+
+pub struct ElasticsearchCompleteRequest {
+    elasticsearch: Elasticsearch,
+    field: CompletionField,
+    prefix: String,
+    size: u32,
+    contexts: Option<Value>,
+}
+
+impl ElasticsearchCompleteRequest {
+    pub fn new(
+        elasticsearch: &Elasticsearch,
+        field: CompletionField,
+        prefix: String,
+        size: u32,
+        contexts: Option<Value>,
+    ) -> Self {
+        ElasticsearchCompleteRequest {
+            elasticsearch: elasticsearch.clone(),
+            field,
+            prefix,
+            size,
+            contexts,
+        }
+    }
+
+    /// Returns `(suggestion, score, ctid)` for up to `size` visible rows
+    pub fn execute(self) -> std::result::Result<Vec<(String, f64, u64)>, ElasticsearchError> {
+        match &self.field {
+            CompletionField::SearchAsYouType(field) => self.search_as_you_type(field),
+            CompletionField::Completion(field) => self.completion(field),
+        }
+    }
+
+    fn search_as_you_type(
+        &self,
+        field: &str,
+    ) -> std::result::Result<Vec<(String, f64, u64)>, ElasticsearchError> {
+        let query = ZDBQuery::new_with_query_dsl(json! {
+            {
+                "multi_match": {
+                    "query": self.prefix,
+                    "type": "bool_prefix",
+                    "fields": [field, format!("{}._2gram", field), format!("{}._3gram", field)]
+                }
+            }
+        });
+        let (query, _) = query.prepare(&self.elasticsearch.index_relation(), None);
+        let body = json! {
+            {
+                "size": self.size,
+                "_source": [field],
+                "query": apply_visibility_clause(&self.elasticsearch, query, false)
+            }
+        };
+
+        Elasticsearch::execute_json_request(
+            self.elasticsearch
+                .client()
+                .post(&format!("{}/_search", self.elasticsearch.alias_url())),
+            Some(body),
+            |body| {
+                #[derive(Deserialize)]
+                struct Hit {
+                    _id: String,
+                    _score: f64,
+                    _source: Option<Value>,
+                }
+
+                #[derive(Deserialize)]
+                struct Hits {
+                    hits: Vec<Hit>,
+                }
+
+                #[derive(Deserialize)]
+                struct SearchResponse {
+                    hits: Hits,
+                }
+
+                let response: SearchResponse = serde_json::from_reader(body)
+                    .expect("failed to deserialize search_as_you_type response");
+                Ok(response
+                    .hits
+                    .hits
+                    .into_iter()
+                    .filter_map(|hit| {
+                        let ctid = hit._id.parse::<u64>().ok()?;
+                        let suggestion = source_value(hit._source.as_ref()?, field)?;
+                        Some((suggestion, hit._score, ctid))
+                    })
+                    .collect())
+            },
+        )
+    }
+
+    fn completion(
+        &self,
+        field: &str,
+    ) -> std::result::Result<Vec<(String, f64, u64)>, ElasticsearchError> {
+        let mut completion = json! {
+            {
+                "field": field,
+                "size": self.size.saturating_mul(COMPLETION_OVERFETCH)
+            }
+        };
+        if let Some(contexts) = &self.contexts {
+            completion["contexts"] = contexts.clone();
+        }
+        let body = json! {
+            {
+                "_source": false,
+                "suggest": {
+                    "completion": {
+                        "prefix": self.prefix,
+                        "completion": completion
+                    }
+                }
+            }
+        };
+
+        let suggestions = Elasticsearch::execute_json_request(
+            self.elasticsearch
+                .client()
+                .post(&format!("{}/_search", self.elasticsearch.alias_url())),
+            Some(body),
+            |body| {
+                #[derive(Deserialize)]
+                struct CompletionOption {
+                    text: String,
+                    _id: String,
+                    _score: f64,
+                }
+
+                #[derive(Deserialize)]
+                struct CompletionEntry {
+                    options: Vec<CompletionOption>,
+                }
+
+                #[derive(Deserialize)]
+                struct Suggest {
+                    completion: Vec<CompletionEntry>,
+                }
+
+                #[derive(Deserialize)]
+                struct WholeResponse {
+                    suggest: Suggest,
+                }
+
+                let response: WholeResponse = serde_json::from_reader(body)
+                    .expect("failed to deserialize completion suggester response");
+                Ok(response
+                    .suggest
+                    .completion
+                    .into_iter()
+                    .flat_map(|entry| entry.options)
+                    .filter_map(|option| {
+                        Some((option.text, option._score, option._id.parse::<u64>().ok()?))
+                    })
+                    .collect::<Vec<_>>())
+            },
+        )?;
+
+        let visible = self.visible_ctids(suggestions.iter().map(|(_, _, ctid)| *ctid))?;
+        Ok(suggestions
+            .into_iter()
+            .filter(|(_, _, ctid)| visible.contains(ctid))
+            .take(self.size as usize)
+            .collect())
+    }
+
+    /// Which of `ctids` belong to rows that are visible to our transaction
+    fn visible_ctids<I: Iterator<Item = u64>>(
+        &self,
+        ctids: I,
+    ) -> std::result::Result<HashSet<u64>, ElasticsearchError> {
+        let ids = ctids.map(|ctid| ctid.to_string()).collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let size = ids.len();
+        let query = ZDBQuery::new_with_query_dsl(json! { { "ids": { "values": ids } } });
+        let (query, _) = query.prepare(&self.elasticsearch.index_relation(), None);
+        let body = json! {
+            {
+                "size": size,
+                "_source": false,
+                "query": apply_visibility_clause(&self.elasticsearch, query, false)
+            }
+        };
+
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().post(&format!(
+                "{}/_search?filter_path=hits.hits._id",
+                self.elasticsearch.alias_url()
+            )),
+            Some(body),
+            |body| {
+                #[derive(Deserialize)]
+                struct Hit {
+                    _id: String,
+                }
+
+                #[derive(Deserialize, Default)]
+                struct Hits {
+                    hits: Vec<Hit>,
+                }
+
+                #[derive(Deserialize)]
+                struct SearchResponse {
+                    #[serde(default)]
+                    hits: Hits,
+                }
+
+                let response: SearchResponse = serde_json::from_reader(body)
+                    .expect("failed to deserialize visible ids response");
+                Ok(response
+                    .hits
+                    .hits
+                    .into_iter()
+                    .filter_map(|hit| hit._id.parse::<u64>().ok())
+                    .collect())
+            },
+        )
+    }
+}
+
+/// The string at the dotted `path` of a document's `_source`, or the first string if it's an array
+fn source_value(source: &Value, path: &str) -> Option<String> {
+    let value = path
+        .split('.')
+        .try_fold(source, |value, part| value.get(part))?;
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(values) => values.iter().find_map(|v| v.as_str().map(str::to_owned)),
+        _ => None,
+    }
+}
+
+/// Type-ahead suggestions for `prefix` from `field`, which is either a `zdb.search_as_you_type`
+/// column, a `completion` field, or a column named by the index's `completion_columns` option.
+/// Only suggestions from rows visible to the current transaction are returned
+#[pg_extern(immutable, parallel_safe)]
+fn complete(
+    index: PgRelation,
+    field: String,
+    prefix: String,
+    size: default!(i32, 10),
+    contexts: default!(Option<JsonB>, NULL),
+) -> TableIterator<
+    'static,
+    (
+        name!(suggestion, String),
+        name!(score, f64),
+        name!(ctid, pg_sys::ItemPointerData),
+    ),
+> {
+    if size < 1 {
+        panic!("size must be at least 1")
+    }
+
+    let completion_field = match lookup_es_field_type(&index, &field).as_str() {
+        "search_as_you_type" => {
+            if contexts.is_some() {
+                panic!("contexts only apply to completion fields")
+            }
+            CompletionField::SearchAsYouType(field)
+        }
+        "completion" => CompletionField::Completion(field),
+        _ if lookup_es_subfield_type(&index, &format!("{}.completion", field)) == "completion" => {
+            CompletionField::Completion(format!("{}.completion", field))
+        }
+        _ => panic!("{} is not a search_as_you_type or completion field", field),
+    };
+
+    let elasticsearch = Elasticsearch::new(&index);
+    let results = elasticsearch
+        .complete(
+            completion_field,
+            prefix,
+            size as u32,
+            contexts.map(|contexts| contexts.0),
+        )
+        .execute()
+        .expect("failed to complete");
+
+    TableIterator::new(
+        results
+            .into_iter()
+            .map(|(suggestion, score, ctid)| {
+                let mut ipd = pg_sys::ItemPointerData::default();
+                u64_to_item_pointer(ctid, &mut ipd);
+                (suggestion, score, ipd)
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_complete_search_as_you_type() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE complete_sayt(id serial8, title zdb.search_as_you_type);
+             CREATE INDEX idxcomplete_sayt ON complete_sayt USING zombodb ((complete_sayt.*));
+             INSERT INTO complete_sayt(title) VALUES ('quick brown fox'), ('quick red fox'), ('slow turtle');
+             DELETE FROM complete_sayt WHERE title = 'quick red fox';",
+        )?;
+
+        let suggestions = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(suggestion) FROM zdb.complete('idxcomplete_sayt', 'title', 'quick br')",
+        )?;
+        assert_eq!(suggestions, Some(vec!["quick brown fox".to_string()]));
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_complete_completion_columns() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE complete_cc(id serial8, title text);
+             CREATE INDEX idxcomplete_cc ON complete_cc USING zombodb ((complete_cc.*)) WITH (completion_columns='title');
+             INSERT INTO complete_cc(title) VALUES ('apple'), ('apricot'), ('banana');
+             DELETE FROM complete_cc WHERE title = 'apricot';",
+        )?;
+
+        let suggestions = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(suggestion ORDER BY suggestion) FROM zdb.complete('idxcomplete_cc', 'title', 'ap')",
+        )?;
+        assert_eq!(suggestions, Some(vec!["apple".to_string()]));
+        Ok(())
+    }
+
+    #[pg_test(error = "id is not a search_as_you_type or completion field")]
+    #[initialize(es = true)]
+    fn test_complete_wrong_field() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE complete_wrong(id serial8, title text);
+             CREATE INDEX idxcomplete_wrong ON complete_wrong USING zombodb ((complete_wrong.*));
+             SELECT * FROM zdb.complete('idxcomplete_wrong', 'id', '1');",
+        )
+    }
+}
@@ -1,5 +1,6 @@
+use crate::elasticsearch::reload_search_analyzers::ElasticsearchReloadSearchAnalyzersRequest;
 use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
-use crate::mapping::lookup_analysis_thing;
+use crate::mapping::{lookup_analysis_thing, lookup_filters};
 use serde_json::*;
 
 pub struct ElasticsearchCreateIndexRequest {
@@ -16,6 +17,14 @@ impl ElasticsearchCreateIndexRequest {
     }
 
     pub fn execute(self) -> std::result::Result<(), ElasticsearchError> {
+        // the index's synonym filters load their rules from these
+        ElasticsearchReloadSearchAnalyzersRequest::put_synonym_sets(&self.elasticsearch)?;
+
+        if self.elasticsearch.is_rollover() {
+            self.put_rollover_policy()?;
+            self.put_rollover_template()?;
+        }
+
         Elasticsearch::execute_json_request(
             self.elasticsearch
                 .client()
@@ -24,6 +33,23 @@ impl ElasticsearchCreateIndexRequest {
             |_| Ok(()),
         )?;
 
+        if self.elasticsearch.is_rollover() {
+            // the first backing index gets its settings and mapping from the template
+            Elasticsearch::execute_json_request(
+                self.elasticsearch
+                    .client()
+                    .put(&format!("{}-000001", self.elasticsearch.base_url())),
+                Some(json! {
+                    {
+                        "aliases": {
+                            self.elasticsearch.write_alias(): { "is_write_index": true }
+                        }
+                    }
+                }),
+                |_| Ok(()),
+            )?;
+        }
+
         let url = format!(
             "{}_cluster/health/{}?wait_for_active_shards={}&timeout=5m&master_timeout=5m",
             self.elasticsearch.url(),
@@ -53,6 +79,81 @@ impl ElasticsearchCreateIndexRequest {
         }
     }
 
+    /// The ILM policy that rolls a rollover index over to new backing indices, and deletes them
+    /// once they're past their retention
+    fn put_rollover_policy(&self) -> std::result::Result<(), ElasticsearchError> {
+        let conditions = self
+            .elasticsearch
+            .options
+            .rollover()
+            .expect("index does not use rollover")
+            .into_iter()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect::<Map<String, Value>>();
+
+        let mut phases = json! {
+            {
+                "hot": {
+                    "actions": {
+                        "rollover": conditions
+                    }
+                }
+            }
+        };
+        if let Some(retention) = self.elasticsearch.options.rollover_retention() {
+            phases["delete"] = json! {
+                {
+                    "min_age": retention,
+                    "actions": { "delete": {} }
+                }
+            };
+        }
+
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().put(&format!(
+                "{}_ilm/policy/{}",
+                self.elasticsearch.url(),
+                self.elasticsearch.index_name()
+            )),
+            Some(json! { { "policy": { "phases": phases } } }),
+            |_| Ok(()),
+        )
+    }
+
+    /// The template Elasticsearch creates each of a rollover index's backing indices from.  They
+    /// get the index's current settings rather than the ones ZomboDB creates indices with and
+    /// then changes once they're built
+    pub fn put_rollover_template(&self) -> std::result::Result<(), ElasticsearchError> {
+        let options = &self.elasticsearch.options;
+        let mut template = self.create_request_body();
+        let index_block = &mut template["settings"]["index"];
+        index_block["number_of_replicas"] = json!(options.replicas());
+        index_block["refresh_interval"] = json!(options.refresh_interval().as_str());
+        index_block["translog.durability"] = json!(options.translog_durability());
+        index_block["lifecycle"] = json! {
+            {
+                "name": self.elasticsearch.index_name(),
+                "rollover_alias": self.elasticsearch.write_alias()
+            }
+        };
+
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().put(&format!(
+                "{}_index_template/{}",
+                self.elasticsearch.url(),
+                self.elasticsearch.index_name()
+            )),
+            Some(json! {
+                {
+                    "index_patterns": [self.elasticsearch.backing_index_pattern()],
+                    "priority": 500,
+                    "template": template
+                }
+            }),
+            |_| Ok(()),
+        )
+    }
+
     fn create_request_body(&self) -> Value {
         // hacky way to see if the mapping contains a nested field
         let has_nested_field = serde_json::to_string(&self.mapping)
@@ -93,10 +194,14 @@ impl ElasticsearchCreateIndexRequest {
             } }
         };
 
-        let source = if self.elasticsearch.options.include_source() {
+        // the MVCC fields are always kept in _source as VACUUM's update scripts modify them
+        let unstored_columns = self.elasticsearch.options.unstored_columns();
+        let source = if !self.elasticsearch.options.include_source() {
+            json! { { "includes": [ "zdb_ctid", "zdb_cmin", "zdb_cmax", "zdb_xmin", "zdb_xmax", "zdb_aborted_xids"] } }
+        } else if unstored_columns.is_empty() {
             json! { { "enabled": true } }
         } else {
-            json! { { "includes": [ "zdb_aborted_xids"] } }
+            json! { { "excludes": unstored_columns } }
         };
 
         json! {
@@ -104,7 +209,7 @@ impl ElasticsearchCreateIndexRequest {
                "settings": {
                   "index": index_block,
                   "analysis": {
-                     "filter": lookup_analysis_thing("filters"),
+                     "filter": lookup_filters(),
                      "char_filter" : lookup_analysis_thing("char_filters"),
                      "tokenizer" : lookup_analysis_thing("tokenizers"),
                      "analyzer": lookup_analysis_thing("analyzers"),
@@ -156,3 +261,106 @@ impl ElasticsearchCreateIndexRequest {
         }
     }
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_rollover_index() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE rollover_test(id serial8, title text);
+             CREATE INDEX idxrollover_test ON rollover_test USING zombodb ((rollover_test.*))
+                    WITH (rollover='max_docs=1', rollover_retention='30d');
+             INSERT INTO rollover_test(title) VALUES ('one'), ('two');",
+        )?;
+
+        let count = || {
+            Spi::get_one::<i64>(
+                "SELECT count(*) FROM rollover_test WHERE rollover_test ==> 'title:(one, two, three, uno)'",
+            )
+        };
+        assert_eq!(count()?, Some(2));
+
+        // ILM only checks the conditions every so often, so roll over now.  The next row is
+        // written through the write alias to the new backing index
+        Spi::run(
+            "SELECT zdb.request('idxrollover_test', '/' || zdb.index_name('idxrollover_test') || '-write/_rollover', 'POST');
+             INSERT INTO rollover_test(title) VALUES ('three');",
+        )?;
+        assert_eq!(count()?, Some(3));
+
+        let backing_docs = Spi::get_one::<JsonB>(
+            "SELECT jsonb_object_agg(i->>'index', (i->>'docs.count')::int8)
+               FROM jsonb_array_elements(zdb.request('idxrollover_test', '/_cat/indices/' || zdb.index_name('idxrollover_test') || '-0*?format=json&h=index,docs.count')::jsonb) i",
+        )?
+        .expect("no backing indices")
+        .0;
+        let index_name = Spi::get_one::<String>("SELECT zdb.index_name('idxrollover_test')")?
+            .expect("no index name");
+        assert_eq!(
+            backing_docs,
+            serde_json::json! {
+                {
+                    format!("{}-000001", index_name): 2,
+                    format!("{}-000002", index_name): 1
+                }
+            }
+        );
+
+        // rows can be changed whichever backing index holds their docs
+        Spi::run(
+            "UPDATE rollover_test SET title = 'uno' WHERE title = 'one';
+             DELETE FROM rollover_test WHERE title = 'three';",
+        )?;
+        assert_eq!(count()?, Some(2));
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM rollover_test WHERE rollover_test ==> 'title:(one, three)'",
+        )?;
+        assert_eq!(count, Some(0));
+
+        let count = Spi::get_one::<i64>("SELECT zdb.count('idxrollover_test', '')")?;
+        assert_eq!(count, Some(2));
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_exclude_and_unstored_columns() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE private_test(id serial8, title text, ssn text, blob bytea);
+             CREATE INDEX idxprivate_test ON private_test USING zombodb ((private_test.*))
+                    WITH (exclude_columns='blob', unstored_columns='ssn');
+             INSERT INTO private_test(title, ssn, blob) VALUES ('one', '123-45-6789', 'abc');",
+        )?;
+
+        // the unstored column is still searchable
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM private_test WHERE private_test ==> 'ssn:\"123-45-6789\"'",
+        )?;
+        assert_eq!(count, Some(1));
+
+        let source = Spi::get_one::<JsonB>(
+            "SELECT (zdb.request('idxprivate_test', '_search')::jsonb)->'hits'->'hits'->0->'_source'",
+        )?
+        .expect("no document was returned")
+        .0;
+        assert_eq!(source["title"], "one");
+        assert!(source.get("ssn").is_none());
+        assert!(source.get("blob").is_none());
+        assert!(source.get("zdb_xmin").is_some());
+        Ok(())
+    }
+
+    #[pg_test(error = "the exclude_columns option names column nope which does not exist")]
+    #[initialize(es = true)]
+    fn test_exclude_unknown_column() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE private_test(id serial8, title text);
+             CREATE INDEX idxprivate_test ON private_test USING zombodb ((private_test.*))
+                    WITH (exclude_columns='nope');",
+        )
+    }
+}
@@ -0,0 +1,162 @@
+use crate::access_method::options::{Auth, AwsIamAuthDetails, Secret};
+use lazy_static::*;
+use pgrx::prelude::*;
+use pgrx::spi;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone)]
+struct Credential {
+    auth: Auth,
+    urls: Option<Vec<String>>,
+}
+
+lazy_static! {
+    /// The credentials this backend has resolved, for when it needs to connect somewhere it can't
+    /// read `zdb.credentials`, such as a transaction abort callback
+    static ref RESOLVED: Mutex<HashMap<String, Credential>> = Mutex::new(HashMap::new());
+}
+
+/// Resolve the `zdb.credentials` row called `name` into the [`Auth`] to connect to the cluster
+/// at `nodes` with.  A credential that lists `urls` can only be used with those urls, so that
+/// an index can't send it to a cluster of the index creator's choosing
+pub fn resolve(name: &str, nodes: &[String]) -> Auth {
+    let credential = if unsafe { pg_sys::IsTransactionState() } {
+        let credential =
+            lookup(name).unwrap_or_else(|| panic!("credential \"{}\" does not exist", name));
+        RESOLVED
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), credential.clone());
+        credential
+    } else {
+        RESOLVED
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| panic!("credential \"{}\" cannot be resolved here", name))
+    };
+
+    if let Some(urls) = &credential.urls {
+        let urls = urls.iter().map(|url| normalize(url)).collect::<Vec<_>>();
+        for node in nodes {
+            if !urls.contains(&normalize(node)) {
+                panic!("credential \"{}\" cannot be used with {}", name, node)
+            }
+        }
+    }
+
+    credential.auth
+}
+
+fn normalize(url: &str) -> String {
+    url::Url::parse(url)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+fn lookup(name: &str) -> Option<Credential> {
+    // only superusers can read zdb.credentials, so we read it as its owner
+    let owner = Spi::get_one::<pg_sys::Oid>(
+        "SELECT relowner FROM pg_class WHERE oid = 'zdb.credentials'::regclass",
+    )
+    .expect("SPI failed")
+    .expect("zdb.credentials has no owner");
+
+    let mut save_userid = pg_sys::InvalidOid;
+    let mut save_sec_context = 0;
+    unsafe {
+        pg_sys::GetUserIdAndSecContext(&mut save_userid, &mut save_sec_context);
+        pg_sys::SetUserIdAndSecContext(
+            owner,
+            save_sec_context | pg_sys::SECURITY_LOCAL_USERID_CHANGE as i32,
+        );
+    }
+
+    // an ERROR here restores the user when the transaction aborts
+    let credential = Spi::connect(|client| {
+        let mut table = client.select(
+            "SELECT auth_type, username, password, api_key, token, aws_access_key_id,
+                    aws_secret_access_key, aws_region, aws_service_name, aws_unsigned_payload, urls
+               FROM zdb.credentials
+              WHERE name = $1",
+            None,
+            &[name.into()],
+        )?;
+
+        let Some(row) = table.next() else {
+            return Ok(None);
+        };
+        let auth_type = row.get::<String>(1)?.expect("auth_type is NULL");
+        let required = |ordinal: usize, column: &str| -> Result<String, spi::Error> {
+            Ok(row
+                .get::<String>(ordinal)?
+                .unwrap_or_else(|| panic!("credential \"{}\" has no {}", name, column)))
+        };
+
+        let auth = match auth_type.as_str() {
+            "basic" => Auth::Basic((required(2, "username")?, required(3, "password")?)),
+            "api_key" => Auth::ApiKey(Secret::Value(required(4, "api_key")?)),
+            "bearer" => Auth::Bearer(Secret::Value(required(5, "token")?)),
+            "aws_iam" => Auth::AwsIam(AwsIamAuthDetails::new(
+                row.get::<String>(6)?,
+                row.get::<String>(7)?,
+                row.get::<String>(8)?,
+                row.get::<String>(9)?,
+                row.get::<bool>(10)?.unwrap_or_default(),
+            )),
+            other => panic!("unrecognized auth_type: {}", other),
+        };
+
+        Ok(Some(Credential {
+            auth,
+            urls: row.get::<Vec<String>>(11)?,
+        }))
+    })
+    .expect("SPI failed");
+
+    unsafe {
+        pg_sys::SetUserIdAndSecContext(save_userid, save_sec_context);
+    }
+
+    credential
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::access_method::options::{Auth, Secret};
+    use crate::elasticsearch::credentials::resolve;
+    use pgrx::*;
+
+    #[pg_test]
+    fn test_resolve_credential() -> spi::Result<()> {
+        Spi::run(
+            "INSERT INTO zdb.credentials(name, auth_type, api_key, urls)
+                  VALUES ('test', 'api_key', 'secret', ARRAY['http://es1:9200/'])",
+        )?;
+
+        match resolve("test", &["http://es1:9200/".to_string()]) {
+            Auth::ApiKey(secret) => assert_eq!(secret, Secret::Value("secret".into())),
+            _ => panic!("expected api_key auth"),
+        }
+        Ok(())
+    }
+
+    #[pg_test(error = "credential \"test\" cannot be used with http://elsewhere:9200/")]
+    fn test_resolve_credential_wrong_url() -> spi::Result<()> {
+        Spi::run(
+            "INSERT INTO zdb.credentials(name, auth_type, token, urls)
+                  VALUES ('test', 'bearer', 'secret', ARRAY['http://es1:9200/'])",
+        )?;
+
+        resolve("test", &["http://elsewhere:9200/".to_string()]);
+        Ok(())
+    }
+
+    #[pg_test(error = "credential \"missing\" does not exist")]
+    fn test_resolve_missing_credential() {
+        resolve("missing", &["http://es1:9200/".to_string()]);
+    }
+}
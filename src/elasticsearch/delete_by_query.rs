@@ -0,0 +1,34 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use serde_json::*;
+
+pub struct ElasticsearchDeleteByQueryRequest {
+    elasticsearch: Elasticsearch,
+    query: Value,
+}
+
+impl ElasticsearchDeleteByQueryRequest {
+    pub fn new(elasticsearch: &Elasticsearch, query: Value) -> Self {
+        ElasticsearchDeleteByQueryRequest {
+            elasticsearch: elasticsearch.clone(),
+            query,
+        }
+    }
+
+    /// Delete the documents matching the query from the index's backing indices, returning how
+    /// many were deleted
+    pub fn execute(self) -> std::result::Result<u64, ElasticsearchError> {
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().post(&format!(
+                "{}{}/_delete_by_query?conflicts=proceed&refresh=true",
+                self.elasticsearch.url(),
+                self.elasticsearch.backing_index_pattern()
+            )),
+            Some(json! { { "query": self.query } }),
+            |body| {
+                let response: Value = serde_json::from_reader(body)
+                    .expect("failed to parse _delete_by_query response");
+                Ok(response["deleted"].as_u64().unwrap_or_default())
+            },
+        )
+    }
+}
@@ -1,4 +1,6 @@
+use crate::elasticsearch::nodes::NodeRequest;
 use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use serde_json::Value;
 
 pub struct ElasticsearchDeleteIndexRequest(Elasticsearch);
 
@@ -8,11 +10,54 @@ impl ElasticsearchDeleteIndexRequest {
     }
 
     pub fn execute(&self) -> Result<(), ElasticsearchError> {
-        match Elasticsearch::execute_json_request(
-            self.0.client().delete(&self.0.base_url()),
+        if self.0.is_rollover() {
+            self.delete_rollover()?;
+        }
+
+        Self::delete(self.0.client().delete(&self.0.base_url()))
+    }
+
+    /// A rollover index also has backing indices, a template and an ILM policy to delete
+    fn delete_rollover(&self) -> Result<(), ElasticsearchError> {
+        // deleting by wildcard is usually disallowed, so we find the backing indices by name
+        let backing_indices = Elasticsearch::execute_json_request(
+            self.0.client().get(&format!(
+                "{}_cat/indices/{}?h=index&format=json",
+                self.0.url(),
+                self.0.backing_index_pattern()
+            )),
             None,
-            |_| Ok(()),
-        ) {
+            |body| {
+                let indices: Vec<Value> =
+                    serde_json::from_reader(body).expect("failed to parse _cat/indices response");
+                Ok(indices
+                    .iter()
+                    .filter_map(|index| index["index"].as_str().map(|s| s.to_string()))
+                    .collect::<Vec<_>>())
+            },
+        )?;
+        if !backing_indices.is_empty() {
+            Self::delete(self.0.client().delete(&format!(
+                "{}{}",
+                self.0.url(),
+                backing_indices.join(",")
+            )))?;
+        }
+
+        Self::delete(self.0.client().delete(&format!(
+            "{}_index_template/{}",
+            self.0.url(),
+            self.0.index_name()
+        )))?;
+        Self::delete(self.0.client().delete(&format!(
+            "{}_ilm/policy/{}",
+            self.0.url(),
+            self.0.index_name()
+        )))
+    }
+
+    fn delete(request: NodeRequest) -> Result<(), ElasticsearchError> {
+        match Elasticsearch::execute_json_request(request, None, |_| Ok(())) {
             // 404 NOT FOUND is okay for us
             Err(e) if e.is_404() => Ok(()),
 
@@ -11,7 +11,7 @@ impl ElasticsearchExpungeDeletesRequest {
         Elasticsearch::execute_json_request(
             self.0.client().post(&format!(
                 "{}/_forcemerge?only_expunge_deletes=true&flush=false",
-                self.0.base_url()
+                self.0.search_url()
             )),
             None,
             |_| Ok(()),
@@ -8,6 +8,23 @@ impl ElasticsearchGetMappingRequest {
     }
 
     pub fn execute(self) -> Result<serde_json::Value, ElasticsearchError> {
+        if self.0.is_partitioned() {
+            // a partitioned index has no Elasticsearch index of its own, but its partitions all
+            // share the same mapping, so we return the first one as if it were ours
+            let mapping: serde_json::Value = Elasticsearch::execute_json_request(
+                self.0
+                    .client()
+                    .get(&format!("{}/_mapping", self.0.alias_url())),
+                None,
+                |body| Ok(serde_json::from_reader(body).expect("failed to read json response")),
+            )?;
+            let partition_mapping = mapping
+                .as_object()
+                .and_then(|indices| indices.values().next().cloned())
+                .unwrap_or_else(|| serde_json::json!({ "mappings": {} }));
+            return Ok(serde_json::json!({ self.0.index_name(): partition_mapping }));
+        }
+
         Elasticsearch::execute_json_request(
             self.0
                 .client()
@@ -0,0 +1,160 @@
+use crate::elasticsearch::pg_catalog::ArbitraryRequestType;
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use pgrx::prelude::*;
+use pgrx::PgRelation;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+struct ClusterState {
+    docs: i64,
+    aborted_xids: BTreeSet<u64>,
+}
+
+type MirrorStatusRow = (
+    String,
+    String,
+    bool,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<bool>,
+    Option<String>,
+);
+
+fn cluster_state(elasticsearch: &Elasticsearch) -> Result<ClusterState, ElasticsearchError> {
+    let count: Value = serde_json::from_str(&elasticsearch.arbitrary_request(
+        ArbitraryRequestType::GET,
+        "_count",
+        None,
+    )?)
+    .expect("failed to parse _count response");
+    let docs = count["count"].as_i64().unwrap_or_default();
+
+    let aborted_xids = elasticsearch
+        .get_document::<Value>("zdb_aborted_xids", true)
+        .execute()?
+        .and_then(|doc| doc["_source"]["zdb_aborted_xids"].as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|xid| xid.as_u64())
+        .collect();
+
+    Ok(ClusterState { docs, aborted_xids })
+}
+
+fn status_row(
+    elasticsearch: &Elasticsearch,
+    role: &str,
+    state: &Result<ClusterState, ElasticsearchError>,
+    primary: Option<&ClusterState>,
+) -> MirrorStatusRow {
+    match state {
+        Ok(state) => (
+            elasticsearch.url(),
+            role.to_string(),
+            true,
+            Some(state.docs),
+            primary.map(|primary| primary.docs - state.docs),
+            Some(state.aborted_xids.len() as i64),
+            primary.map(|primary| {
+                primary.docs != state.docs || primary.aborted_xids != state.aborted_xids
+            }),
+            None,
+        ),
+
+        // any HTTP response means the cluster is there, even if the index isn't
+        Err(e) => (
+            elasticsearch.url(),
+            role.to_string(),
+            e.status().is_some(),
+            None,
+            None,
+            None,
+            None,
+            Some(e.to_string()),
+        ),
+    }
+}
+
+/// Compare the index on its primary cluster with each of its mirrors.  A mirror's `lag` is how
+/// many fewer docs it has than the primary, and it has `diverged` if its docs or aborted
+/// transactions differ from the primary's
+#[pg_extern(volatile, parallel_safe)]
+fn mirror_status(
+    index: PgRelation,
+) -> TableIterator<
+    'static,
+    (
+        name!(cluster, String),
+        name!(role, String),
+        name!(reachable, bool),
+        name!(docs, Option<i64>),
+        name!(lag, Option<i64>),
+        name!(aborted_xids, Option<i64>),
+        name!(diverged, Option<bool>),
+        name!(error, Option<String>),
+    ),
+> {
+    let elasticsearch = Elasticsearch::new(&index);
+    let primary = cluster_state(&elasticsearch);
+
+    let mut rows = vec![status_row(
+        &elasticsearch,
+        "primary",
+        &primary,
+        primary.as_ref().ok(),
+    )];
+    for mirror in elasticsearch.mirrors() {
+        let state = cluster_state(&mirror);
+        rows.push(status_row(&mirror, "mirror", &state, primary.as_ref().ok()));
+    }
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_mirror_status_without_mirrors() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE mirror_test(id serial8, title text);
+             CREATE INDEX idxmirror_test ON mirror_test USING zombodb ((mirror_test.*));
+             INSERT INTO mirror_test(title) VALUES ('one'), ('two');",
+        )?;
+
+        let (role, diverged) = Spi::get_two::<String, bool>(
+            "SELECT role, diverged FROM zdb.mirror_status('idxmirror_test')",
+        )?;
+        assert_eq!(role, Some("primary".to_string()));
+        assert_eq!(diverged, Some(false));
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_unreachable_mirror() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE mirror_test(id serial8, title text);
+             CREATE INDEX idxmirror_test ON mirror_test USING zombodb ((mirror_test.*))
+                    WITH (mirror_urls='http://localhost:1/', mirror_policy='primary');
+             INSERT INTO mirror_test(title) VALUES ('one'), ('two');",
+        )?;
+
+        // the primary still has everything
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM mirror_test WHERE mirror_test ==> 'title:(one, two)'",
+        )?;
+        assert_eq!(count, Some(2));
+
+        let (reachable, docs) = Spi::get_two::<bool, i64>(
+            "SELECT reachable, docs FROM zdb.mirror_status('idxmirror_test') WHERE role = 'mirror'",
+        )?;
+        assert_eq!(reachable, Some(false));
+        assert_eq!(docs, None);
+        Ok(())
+    }
+}
@@ -3,50 +3,67 @@
 pub mod aggregates;
 mod aliases;
 pub(crate) mod analyze;
+mod aws;
 mod bulk;
 mod cat;
+mod complete;
 mod count;
 mod create_index;
+mod credentials;
+mod delete_by_query;
 mod delete_index;
 mod expunge_deletes;
 mod get_document;
 mod get_mapping;
 mod get_settings;
+mod mirrors;
+mod nodes;
 mod profile_query;
 mod put_mapping;
 mod refresh_index;
+mod reload_search_analyzers;
+mod suggest_phrase;
 mod suggest_term;
+mod update_by_query;
 mod update_settings;
+mod validate_query;
 
 pub mod aggregate_search;
 pub mod search;
 
-use crate::access_method::options::{Auth, ZDBIndexOptions};
+use crate::access_method::options::{Auth, MirrorPolicy, ZDBIndexOptions};
 use crate::elasticsearch::aggregate_search::ElasticsearchAggregateSearchRequest;
 use crate::elasticsearch::aliases::ElasticsearchAliasRequest;
 use crate::elasticsearch::analyze::ElasticsearchAnalyzerRequest;
 use crate::elasticsearch::cat::ElasticsearchCatRequest;
+use crate::elasticsearch::complete::{CompletionField, ElasticsearchCompleteRequest};
 use crate::elasticsearch::count::ElasticsearchCountRequest;
+use crate::elasticsearch::delete_by_query::ElasticsearchDeleteByQueryRequest;
 use crate::elasticsearch::delete_index::ElasticsearchDeleteIndexRequest;
 use crate::elasticsearch::expunge_deletes::ElasticsearchExpungeDeletesRequest;
 use crate::elasticsearch::get_document::ElasticsearchGetDocumentRequest;
 use crate::elasticsearch::get_mapping::ElasticsearchGetMappingRequest;
 use crate::elasticsearch::get_settings::ElasticsearchGetSettingsRequest;
+use crate::elasticsearch::nodes::{NodeClient, NodeRequest};
 use crate::elasticsearch::pg_catalog::ArbitraryRequestType;
 use crate::elasticsearch::profile_query::ElasticsearchProfileQueryRequest;
 use crate::elasticsearch::put_mapping::ElasticsearchPutMappingRequest;
 use crate::elasticsearch::refresh_index::ElasticsearchRefreshIndexRequest;
+use crate::elasticsearch::reload_search_analyzers::ElasticsearchReloadSearchAnalyzersRequest;
 use crate::elasticsearch::search::ElasticsearchSearchRequest;
+use crate::elasticsearch::suggest_phrase::ElasticsearchSuggestPhraseRequest;
 use crate::elasticsearch::suggest_term::ElasticsearchSuggestTermRequest;
+use crate::elasticsearch::update_by_query::ElasticsearchUpdateByQueryRequest;
 use crate::elasticsearch::update_settings::ElasticsearchUpdateSettingsRequest;
+use crate::elasticsearch::validate_query::ElasticsearchValidateQueryRequest;
 use crate::executor_manager::get_executor_manager;
 use crate::gucs::ZDB_LOG_LEVEL;
-use crate::utils::is_nested_field;
+use crate::utils::{is_nested_field, is_partitioned_zdb_index};
 use crate::zdbquery::ZDBPreparedQuery;
-use aws_config::meta::credentials::CredentialsProviderChain;
-use aws_credential_types::provider::ProvideCredentials;
 use aws_credential_types::Credentials;
-use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::http_request::{
+    sign, PayloadChecksumKind, SignableBody, SignableRequest, SigningSettings,
+};
 use aws_sigv4::sign::v4;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
@@ -150,12 +167,85 @@ impl Elasticsearch {
         self.options.is_shadow_index()
     }
 
-    pub fn client(&self) -> Arc<ureq::Agent> {
-        self.agent.clone()
+    pub fn is_partitioned(&self) -> bool {
+        is_partitioned_zdb_index(&self.options.index_relation())
+    }
+
+    pub fn client(&self) -> NodeClient {
+        NodeClient::new(self.agent.clone())
+    }
+
+    /// This index on each of its mirror clusters
+    pub fn mirrors(&self) -> Vec<Elasticsearch> {
+        self.options
+            .mirrors()
+            .into_iter()
+            .map(Elasticsearch::from_options)
+            .collect()
+    }
+
+    pub fn mirror_policy(&self) -> MirrorPolicy {
+        self.options.mirror_policy()
+    }
+
+    /// Apply `f` to each of this index's mirrors.  A failure raises an ERROR if the index's
+    /// `mirror_policy` is 'all', otherwise it's only a WARNING as the mirror will just diverge
+    pub fn for_each_mirror<F: Fn(&Elasticsearch) -> Result<(), ElasticsearchError>>(
+        &self,
+        description: &str,
+        f: F,
+    ) {
+        for mirror in self.mirrors() {
+            if let Err(e) = f(&mirror) {
+                match self.mirror_policy() {
+                    MirrorPolicy::All => {
+                        panic!(
+                            "failed to {} on mirror {}: {}",
+                            description,
+                            mirror.url(),
+                            e
+                        )
+                    }
+                    MirrorPolicy::Primary => {
+                        warning!(
+                            "failed to {} on mirror {}: {}",
+                            description,
+                            mirror.url(),
+                            e
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    /// The cluster searches should be sent to.  That's always the primary cluster, unless the index
+    /// has `mirror_failover` enabled and every node of the primary has recently failed to respond,
+    /// in which case it's the first mirror that still has a node that hasn't
+    pub fn search_target(&self) -> Elasticsearch {
+        if self.options.mirror_failover() && !nodes::has_healthy_node(&self.options) {
+            if let Some(mirror) = self
+                .mirrors()
+                .into_iter()
+                .find(|m| nodes::has_healthy_node(&m.options))
+            {
+                ZDB_LOG_LEVEL.get().log(&format!(
+                    "[zombodb] primary cluster {} is unreachable, searching mirror {}",
+                    self.url(),
+                    mirror.url()
+                ));
+                return mirror;
+            }
+        }
+
+        self.clone()
     }
 
     fn create_client(options: &ZDBIndexOptions) -> ureq::Agent {
-        let auth = options.auth().clone();
+        let auth = match options.auth() {
+            Auth::Credential(name) => credentials::resolve(name, options.nodes()),
+            auth => auth.clone(),
+        };
         let agent_builder = ureq::AgentBuilder::new().middleware(
             move |mut request: Request, mut payload: Payload, next: MiddlewareNext| {
                 match &auth {
@@ -166,34 +256,41 @@ impl Elasticsearch {
                             &format!("Basic {}", BASE64_STANDARD.encode(user_pass)),
                         );
                     }
+                    Auth::ApiKey(secret) => {
+                        // Elasticsearch wants the key's "id:api_key" base64 encoded, which is
+                        // also how Elastic Cloud hands it out
+                        let mut api_key = secret.get();
+                        if api_key.contains(':') {
+                            api_key = BASE64_STANDARD.encode(api_key);
+                        }
+                        request = request.set("Authorization", &format!("ApiKey {}", api_key));
+                    }
+                    Auth::Bearer(secret) => {
+                        request = request.set("Authorization", &format!("Bearer {}", secret.get()));
+                    }
                     Auth::AwsIam(details) => {
-                        let identity = if details.access_key_id.is_some()
-                            && details.secret_access_key.is_some()
-                        {
-                            Credentials::new(
-                                details.access_key_id.clone().unwrap(),
-                                details.secret_access_key.clone().unwrap(),
+                        let identity = match (&details.access_key_id, &details.secret_access_key) {
+                            (Some(access_key_id), Some(secret_access_key)) => Credentials::new(
+                                access_key_id.clone(),
+                                secret_access_key.clone(),
                                 None,
                                 None,
                                 "hardcoded-credentials",
-                            )
-                            .into()
-                        } else {
-                            let rt = tokio::runtime::Builder::new_current_thread()
-                                .enable_all()
-                                .build()
-                                .unwrap();
-                            rt.block_on(async {
-                                CredentialsProviderChain::default_provider()
-                                    .await
-                                    .provide_credentials()
-                                    .await
-                                    .expect("unable to retrieve credentials")
-                                    .into()
-                            })
-                        };
+                            ),
+                            _ => aws::default_credentials(),
+                        }
+                        .into();
+
+                        // _bulk bodies can be large, so rather than read them into memory to
+                        // hash them, they can be streamed as an UNSIGNED-PAYLOAD
+                        let unsigned_payload =
+                            details.unsigned_payload && aws::is_bulk_request(request.url());
 
-                        let signing_settings = SigningSettings::default();
+                        let mut signing_settings = SigningSettings::default();
+                        if unsigned_payload {
+                            signing_settings.payload_checksum_kind =
+                                PayloadChecksumKind::XAmzSha256;
+                        }
                         let signing_params = v4::SigningParams::builder()
                             .identity(&identity)
                             .region(details.region.as_str())
@@ -204,16 +301,27 @@ impl Elasticsearch {
                             .unwrap()
                             .into();
 
-                        let mut read = payload.into_read();
-                        let mut buf = vec![];
-                        read.reader.read_to_end(&mut buf).expect("cannot read body");
+                        let body = if unsigned_payload {
+                            None
+                        } else {
+                            let mut buf = vec![];
+                            std::mem::replace(&mut payload, Payload::Empty)
+                                .into_read()
+                                .reader
+                                .read_to_end(&mut buf)
+                                .expect("cannot read body");
+                            Some(buf)
+                        };
 
                         // Convert the HTTP request into a signable request
                         let signable_request = SignableRequest::new(
                             request.method(),
                             request.url(),
                             std::iter::empty(),
-                            SignableBody::Bytes(buf.as_slice()),
+                            match &body {
+                                Some(buf) => SignableBody::Bytes(buf.as_slice()),
+                                None => SignableBody::UnsignedPayload,
+                            },
                         )
                         .expect("signable request");
 
@@ -229,7 +337,9 @@ impl Elasticsearch {
                             request = request.query(param, value);
                         }
 
-                        payload = Payload::Reader(Box::new(Cursor::new(buf)));
+                        if let Some(buf) = body {
+                            payload = Payload::Reader(Box::new(Cursor::new(buf)));
+                        }
                     }
                     _ => { /* Do nothing */ }
                 }
@@ -394,17 +504,17 @@ impl Elasticsearch {
             // as self.url() is required to have a trailing slash
             endpoint = &endpoint[1..];
         } else {
-            url.push_str(&self.base_url());
+            url.push_str(&self.search_url());
             url.push('/');
         }
 
         url.push_str(endpoint);
 
         let request = match method {
-            ArbitraryRequestType::GET => self.agent.get(&url),
-            ArbitraryRequestType::POST => self.agent.post(&url),
-            ArbitraryRequestType::PUT => self.agent.put(&url),
-            ArbitraryRequestType::DELETE => self.agent.delete(&url),
+            ArbitraryRequestType::GET => self.client().get(&url),
+            ArbitraryRequestType::POST => self.client().post(&url),
+            ArbitraryRequestType::PUT => self.client().put(&url),
+            ArbitraryRequestType::DELETE => self.client().delete(&url),
         };
 
         Elasticsearch::execute_json_request(request, post_data, |body| {
@@ -423,6 +533,14 @@ impl Elasticsearch {
         ElasticsearchAnalyzerRequest::new_with_field(self, field, text)
     }
 
+    pub fn analyze_with_field_texts(
+        &self,
+        field: &str,
+        texts: &[&str],
+    ) -> ElasticsearchAnalyzerRequest {
+        ElasticsearchAnalyzerRequest::new_with_field_texts(self, field, texts)
+    }
+
     pub fn analyze_custom<'a>(
         &self,
         field: Option<default!(&'a str, NULL)>,
@@ -451,10 +569,32 @@ impl Elasticsearch {
         ElasticsearchDeleteIndexRequest::new(self)
     }
 
+    pub fn delete_by_query(&self, query: Value) -> ElasticsearchDeleteByQueryRequest {
+        ElasticsearchDeleteByQueryRequest::new(self, query)
+    }
+
+    pub fn update_by_query(
+        &self,
+        query: Value,
+        script: Value,
+    ) -> ElasticsearchUpdateByQueryRequest {
+        ElasticsearchUpdateByQueryRequest::new(self, query, script)
+    }
+
+    /// Bring a rollover index's template up to date with its settings and `mapping`, so that
+    /// the backing indices Elasticsearch creates from now on get them too
+    pub fn update_rollover_template(&self, mapping: Value) -> Result<(), ElasticsearchError> {
+        ElasticsearchCreateIndexRequest::new(self, mapping).put_rollover_template()
+    }
+
     pub fn refresh_index(&self) -> ElasticsearchRefreshIndexRequest {
         ElasticsearchRefreshIndexRequest::new(self)
     }
 
+    pub fn reload_search_analyzers(&self) -> ElasticsearchReloadSearchAnalyzersRequest {
+        ElasticsearchReloadSearchAnalyzersRequest::new(self)
+    }
+
     pub fn add_alias(&self, alias_name: &str) -> ElasticsearchAliasRequest {
         ElasticsearchAliasRequest::add(self, alias_name)
     }
@@ -483,6 +623,10 @@ impl Elasticsearch {
         ElasticsearchProfileQueryRequest::new(self, query)
     }
 
+    pub fn validate_query(&self, query: ZDBPreparedQuery) -> ElasticsearchValidateQueryRequest {
+        ElasticsearchValidateQueryRequest::new(self, query)
+    }
+
     pub fn start_bulk(&self) -> ElasticsearchBulkRequest {
         let concurrency = (self.options.shards() as usize)
             .min(NUM_CPUS.min(self.options.bulk_concurrency() as usize));
@@ -496,7 +640,7 @@ impl Elasticsearch {
 
     pub fn open_search(&self, query: ZDBPreparedQuery) -> ElasticsearchSearchRequest {
         get_executor_manager().wait_for_completion();
-        ElasticsearchSearchRequest::new(self, query)
+        ElasticsearchSearchRequest::new(&self.search_target(), query)
     }
 
     pub fn arbitrary_aggregate<T: DeserializeOwned>(
@@ -582,7 +726,7 @@ impl Elasticsearch {
             })
             .collect::<HashMap<String, Value>>();
 
-        ElasticsearchAggregateSearchRequest::new(self, query, aggs)
+        ElasticsearchAggregateSearchRequest::new(&self.search_target(), query, aggs)
     }
 
     pub fn raw_json_aggregate<T: DeserializeOwned>(
@@ -590,17 +734,17 @@ impl Elasticsearch {
         agg_request: serde_json::Value,
     ) -> ElasticsearchAggregateSearchRequest<T> {
         get_executor_manager().wait_for_completion();
-        ElasticsearchAggregateSearchRequest::from_raw(self, agg_request)
+        ElasticsearchAggregateSearchRequest::from_raw(&self.search_target(), agg_request)
     }
 
     pub fn count(&self, query: ZDBPreparedQuery) -> ElasticsearchCountRequest {
         get_executor_manager().wait_for_completion();
-        ElasticsearchCountRequest::new(self, query, false)
+        ElasticsearchCountRequest::new(&self.search_target(), query, false)
     }
 
     pub fn raw_count<'a>(&self, query: ZDBPreparedQuery) -> ElasticsearchCountRequest {
         get_executor_manager().wait_for_completion();
-        ElasticsearchCountRequest::new(self, query, true)
+        ElasticsearchCountRequest::new(&self.search_target(), query, true)
     }
 
     pub fn suggest_terms(
@@ -610,7 +754,39 @@ impl Elasticsearch {
         suggest: String,
     ) -> ElasticsearchSuggestTermRequest {
         get_executor_manager().wait_for_completion();
-        ElasticsearchSuggestTermRequest::new(self, query, fieldname, suggest)
+        ElasticsearchSuggestTermRequest::new(&self.search_target(), query, fieldname, suggest)
+    }
+
+    pub fn suggest_phrase(
+        &self,
+        collate: ZDBPreparedQuery,
+        fieldname: String,
+        text: String,
+        size: u32,
+        max_errors: Option<f32>,
+        confidence: Option<f32>,
+    ) -> ElasticsearchSuggestPhraseRequest {
+        get_executor_manager().wait_for_completion();
+        ElasticsearchSuggestPhraseRequest::new(
+            &self.search_target(),
+            collate,
+            fieldname,
+            text,
+            size,
+            max_errors,
+            confidence,
+        )
+    }
+
+    pub fn complete(
+        &self,
+        field: CompletionField,
+        prefix: String,
+        size: u32,
+        contexts: Option<Value>,
+    ) -> ElasticsearchCompleteRequest {
+        get_executor_manager().wait_for_completion();
+        ElasticsearchCompleteRequest::new(&self.search_target(), field, prefix, size, contexts)
     }
 
     pub fn get_document<'a, T: DeserializeOwned>(
@@ -629,16 +805,49 @@ impl Elasticsearch {
         ElasticsearchGetSettingsRequest::new(self)
     }
 
+    /// The url of the cluster node the next request should go to
     pub fn url(&self) -> String {
-        self.options.url()
+        nodes::select_node(&self.options, &self.agent)
     }
 
     pub fn base_url(&self) -> String {
-        format!("{}{}", self.options.url(), self.options.index_name())
+        format!("{}{}", self.url(), self.options.index_name())
+    }
+
+    /// Does the index roll over to new backing indices?  If so, documents are written through
+    /// its write alias and spread across its backing indices, while the index named by
+    /// [`Elasticsearch::index_name`] only holds ZomboDB's own bookkeeping, such as the aborted xids
+    pub fn is_rollover(&self) -> bool {
+        self.options.rollover().is_some()
+    }
+
+    /// The alias through which documents are written to a rollover index's current backing index
+    pub fn write_alias(&self) -> String {
+        format!("{}-write", self.options.index_name())
+    }
+
+    /// Matches every backing index of a rollover index, even if Elasticsearch has deleted all of
+    /// them
+    pub fn backing_index_pattern(&self) -> String {
+        format!("{}-0*", self.options.index_name())
+    }
+
+    /// The url through which every document in the index can be reached
+    pub fn search_url(&self) -> String {
+        if self.is_rollover() {
+            format!(
+                "{}{},{}",
+                self.url(),
+                self.options.index_name(),
+                self.backing_index_pattern()
+            )
+        } else {
+            self.base_url()
+        }
     }
 
     pub fn alias_url(&self) -> String {
-        format!("{}{}", self.options.url(), self.options.alias())
+        format!("{}{}", self.url(), self.options.alias())
     }
 
     pub fn index_name(&self) -> &str {
@@ -653,45 +862,90 @@ impl Elasticsearch {
         self.options.type_name()
     }
 
+    /// Send `request` with a body read from `post_data`, such as a `_bulk` request.  The body
+    /// can't be read a second time, so the request is only sent to another node if the one it
+    /// was for couldn't be connected to
     pub fn execute_request<F, R, Reader: std::io::Read>(
-        request: ureq::Request,
-        post_data: Reader,
+        request: NodeRequest,
+        mut post_data: Reader,
         response_parser: F,
     ) -> std::result::Result<R, ElasticsearchError>
     where
         F: FnOnce(&mut (dyn std::io::Read + Send)) -> std::result::Result<R, ElasticsearchError>,
     {
-        Elasticsearch::handle_response(response_parser, request.send(post_data))
+        let mut request = request;
+        loop {
+            let url = request.url().to_string();
+            let response = request.clone().into_request().send(&mut post_data);
+            Elasticsearch::report_response(&url, &response);
+
+            match Elasticsearch::failover(&request, &response, false) {
+                Some(next) => request = next,
+                None => return Elasticsearch::handle_response(response_parser, response),
+            }
+        }
     }
 
+    /// Send `request`, again to the next healthy node if the one it was for can't be reached and
+    /// it's safe to do so
     pub fn execute_json_request<F, R>(
-        request: ureq::Request,
+        request: NodeRequest,
         post_data: Option<serde_json::Value>,
         response_parser: F,
     ) -> std::result::Result<R, ElasticsearchError>
     where
         F: FnOnce(&mut (dyn std::io::Read + Send)) -> std::result::Result<R, ElasticsearchError>,
     {
-        let response = if post_data.is_some() {
-            let post_data = post_data.unwrap();
-
-            if ZDB_LOG_LEVEL.get().log_level() == PgLogLevel::DEBUG1 {
-                pgrx::debug1!(
+        if ZDB_LOG_LEVEL.get().log_level() == PgLogLevel::DEBUG1 {
+            match &post_data {
+                Some(post_data) => pgrx::debug1!(
                     "{}\n{}",
                     request.url(),
-                    serde_json::to_string_pretty(&post_data).unwrap()
-                );
+                    serde_json::to_string_pretty(post_data).unwrap()
+                ),
+                None => pgrx::debug1!("{}", request.url()),
             }
+        }
 
-            request.send_json(post_data)
-        } else {
-            if ZDB_LOG_LEVEL.get().log_level() == PgLogLevel::DEBUG1 {
-                pgrx::debug1!("{}", request.url());
+        let mut request = request;
+        loop {
+            let url = request.url().to_string();
+            let response = match &post_data {
+                Some(post_data) => request.clone().into_request().send_json(post_data),
+                None => request.clone().into_request().call(),
+            };
+            Elasticsearch::report_response(&url, &response);
+
+            match Elasticsearch::failover(&request, &response, true) {
+                Some(next) => request = next,
+                None => return Elasticsearch::handle_response(response_parser, response),
             }
-            request.call()
-        };
+        }
+    }
 
-        Elasticsearch::handle_response(response_parser, response)
+    /// let the node pool know if the node the request went to is still there
+    fn report_response(url: &str, response: &Result<ureq::Response, ureq::Error>) {
+        match response {
+            Err(ureq::Error::Transport(e)) => nodes::report_response(url, Some(e.to_string())),
+            _ => nodes::report_response(url, None),
+        }
+    }
+
+    /// The request to send next if `request` couldn't reach its node, and it can be sent to another.
+    ///
+    /// This runs on the bulk and scroll threads too, which can't talk to Postgres, so the failure
+    /// isn't logged.  `report_response()` has already recorded it for `zdb.connection_status()`
+    fn failover(
+        request: &NodeRequest,
+        response: &Result<ureq::Response, ureq::Error>,
+        body_resendable: bool,
+    ) -> Option<NodeRequest> {
+        match response {
+            Err(ureq::Error::Transport(e)) if request.can_failover(e, body_resendable) => {
+                request.failover()
+            }
+            _ => None,
+        }
     }
 
     fn handle_response<F, R>(
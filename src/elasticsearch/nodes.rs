@@ -0,0 +1,483 @@
+use crate::access_method::options::ZDBIndexOptions;
+use lazy_static::*;
+use pgrx::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SNIFF_INTERVAL: Duration = Duration::from_secs(300);
+
+struct Node {
+    url: String,
+    sniffed: bool,
+    down_until: Option<Instant>,
+    requests: u64,
+    failures: u64,
+    last_error: Option<String>,
+}
+
+impl Node {
+    fn new(url: String, sniffed: bool) -> Self {
+        Node {
+            url,
+            sniffed,
+            down_until: None,
+            requests: 0,
+            failures: 0,
+            last_error: None,
+        }
+    }
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.down_until.map_or(true, |until| now >= until)
+    }
+}
+
+struct NodePool {
+    nodes: Vec<Node>,
+    next: usize,
+    cooldown: Duration,
+    sniffed_at: Option<Instant>,
+}
+
+impl NodePool {
+    fn new(seeds: &[String], cooldown: Duration) -> Self {
+        NodePool {
+            nodes: seeds
+                .iter()
+                .map(|url| Node::new(url.clone(), false))
+                .collect(),
+            next: 0,
+            cooldown,
+            sniffed_at: None,
+        }
+    }
+
+    /// Replace our nodes with those discovered by sniffing, keeping what we know about the ones
+    /// we already had
+    fn apply_sniffed(&mut self, sniffed: Vec<String>) {
+        let mut existing = self
+            .nodes
+            .drain(..)
+            .map(|node| (node.url.clone(), node))
+            .collect::<HashMap<_, _>>();
+        self.nodes = sniffed
+            .into_iter()
+            .map(|url| {
+                existing
+                    .remove(&url)
+                    .unwrap_or_else(|| Node::new(url, true))
+            })
+            .collect();
+        self.next = 0;
+    }
+
+    fn next_node(&mut self, now: Instant) -> String {
+        if let Some(url) = self.next_healthy_node(now) {
+            return url;
+        }
+
+        // every node is cooling down, so rather than fail outright, try the one that'll be back soonest
+        self.nodes
+            .iter()
+            .min_by_key(|node| node.down_until)
+            .map(|node| node.url.clone())
+            .expect("cluster has no nodes")
+    }
+
+    fn next_healthy_node(&mut self, now: Instant) -> Option<String> {
+        let len = self.nodes.len();
+        for i in 0..len {
+            let idx = (self.next + i) % len;
+            if self.nodes[idx].is_healthy(now) {
+                self.next = idx + 1;
+                return Some(self.nodes[idx].url.clone());
+            }
+        }
+        None
+    }
+}
+
+lazy_static! {
+    /// The nodes of each cluster this backend has used, keyed by the cluster's configured urls
+    static ref NODE_POOLS: Mutex<HashMap<Vec<String>, NodePool>> = Mutex::new(HashMap::new());
+}
+
+/// Pick the node the next request to the index's cluster should go to, round-robin across its
+/// healthy nodes
+pub fn select_node(options: &ZDBIndexOptions, agent: &ureq::Agent) -> String {
+    let seeds = options.nodes();
+    let needs_sniff = options.sniff()
+        && NODE_POOLS
+            .lock()
+            .unwrap()
+            .get(seeds)
+            .and_then(|pool| pool.sniffed_at)
+            .map_or(true, |sniffed_at| sniffed_at.elapsed() >= SNIFF_INTERVAL);
+
+    // don't hold the lock while talking to the cluster
+    let sniffed = if needs_sniff {
+        Some(sniff(seeds, agent))
+    } else {
+        None
+    };
+
+    let mut pools = NODE_POOLS.lock().unwrap();
+    let pool = pools
+        .entry(seeds.to_vec())
+        .or_insert_with(|| NodePool::new(seeds, Duration::from_secs(options.node_cooldown())));
+    if let Some(sniffed) = sniffed {
+        // if sniffing failed we'll keep what we have and try again later
+        pool.sniffed_at = Some(Instant::now());
+        if let Some(sniffed) = sniffed {
+            pool.apply_sniffed(sniffed);
+        }
+    }
+    pool.next_node(Instant::now())
+}
+
+/// Does the index's cluster have a node that hasn't recently failed to respond?  A cluster we
+/// haven't sent anything to yet is assumed to
+pub fn has_healthy_node(options: &ZDBIndexOptions) -> bool {
+    let now = Instant::now();
+    NODE_POOLS
+        .lock()
+        .unwrap()
+        .get(options.nodes())
+        .map_or(true, |pool| {
+            pool.nodes.iter().any(|node| node.is_healthy(now))
+        })
+}
+
+/// Record the outcome of a request to whichever node `request_url` was sent to.  A node that
+/// couldn't be reached is avoided until its cooldown has passed
+pub fn report_response(request_url: &str, error: Option<String>) {
+    let mut pools = NODE_POOLS.lock().unwrap();
+    for pool in pools.values_mut() {
+        let cooldown = pool.cooldown;
+        for node in pool
+            .nodes
+            .iter_mut()
+            .filter(|node| request_url.starts_with(&node.url))
+        {
+            node.requests += 1;
+            match &error {
+                Some(error) => {
+                    node.failures += 1;
+                    node.last_error = Some(error.clone());
+                    node.down_until = Some(Instant::now() + cooldown);
+                }
+                None => node.down_until = None,
+            }
+        }
+    }
+}
+
+/// `request_url` on the next healthy node of the cluster it was sent to, if there's one other
+/// than the node it was sent to
+fn failover_url(request_url: &str) -> Option<String> {
+    let now = Instant::now();
+    let mut pools = NODE_POOLS.lock().unwrap();
+    for pool in pools.values_mut() {
+        let path = pool
+            .nodes
+            .iter()
+            .find(|node| request_url.starts_with(&node.url))
+            .map(|node| &request_url[node.url.len()..]);
+        if let Some(path) = path {
+            let path = path.to_string();
+            return pool
+                .next_healthy_node(now)
+                .filter(|url| !request_url.starts_with(url))
+                .map(|url| format!("{}{}", url, path));
+        }
+    }
+    None
+}
+
+/// The agent requests to one index's cluster are made with.  Its requests remember the agent
+/// so that they can be sent again to another node if the one they went to can't be reached
+#[derive(Clone)]
+pub struct NodeClient(Arc<ureq::Agent>);
+
+impl NodeClient {
+    pub fn new(agent: Arc<ureq::Agent>) -> Self {
+        NodeClient(agent)
+    }
+
+    pub fn get(&self, url: &str) -> NodeRequest {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: &str) -> NodeRequest {
+        self.request("POST", url)
+    }
+
+    pub fn put(&self, url: &str) -> NodeRequest {
+        self.request("PUT", url)
+    }
+
+    pub fn delete(&self, url: &str) -> NodeRequest {
+        self.request("DELETE", url)
+    }
+
+    pub fn request(&self, method: &str, url: &str) -> NodeRequest {
+        NodeRequest {
+            agent: self.0.clone(),
+            request: self.0.request(method, url),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NodeRequest {
+    agent: Arc<ureq::Agent>,
+    request: ureq::Request,
+}
+
+impl NodeRequest {
+    pub fn set(mut self, header: &str, value: &str) -> Self {
+        self.request = self.request.set(header, value);
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        self.request.url()
+    }
+
+    pub fn into_request(self) -> ureq::Request {
+        self.request
+    }
+
+    /// Does sending this request more than once have the same effect as sending it once?
+    /// Searches, counts and the like are POSTed, but only read from the index
+    fn is_idempotent(&self) -> bool {
+        const READ_ONLY_ENDPOINTS: [&str; 6] = [
+            "_search",
+            "_count",
+            "_analyze",
+            "query",
+            "_refresh",
+            "_reload_search_analyzers",
+        ];
+
+        match self.request.method() {
+            "GET" | "HEAD" | "PUT" | "DELETE" => true,
+            _ => {
+                let path = self.url().split('?').next().unwrap_or_default();
+                READ_ONLY_ENDPOINTS
+                    .iter()
+                    .any(|endpoint| path.ends_with(&format!("/{}", endpoint)))
+            }
+        }
+    }
+
+    /// Can this request be sent to another node after failing with `error`?  If its node couldn't
+    /// be connected to at all, Elasticsearch never saw it, so it can be, as long as its body can
+    /// be sent again.  Otherwise it might have been applied, so only a request that's safe to
+    /// repeat can be
+    pub fn can_failover(&self, error: &ureq::Transport, body_resendable: bool) -> bool {
+        match error.kind() {
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed => true,
+            ureq::ErrorKind::Io => body_resendable && self.is_idempotent(),
+            _ => false,
+        }
+    }
+
+    /// This same request, to the next healthy node of its cluster
+    pub fn failover(&self) -> Option<NodeRequest> {
+        let url = failover_url(self.url())?;
+        let mut request = self.agent.request(self.request.method(), &url);
+        for name in self.request.header_names() {
+            if let Some(value) = self.request.header(&name) {
+                request = request.set(&name, value);
+            }
+        }
+
+        Some(NodeRequest {
+            agent: self.agent.clone(),
+            request,
+        })
+    }
+}
+
+fn sniff(seeds: &[String], agent: &ureq::Agent) -> Option<Vec<String>> {
+    for seed in seeds {
+        let response = agent
+            .get(&format!(
+                "{}_nodes/http?filter_path=nodes.*.http.publish_address",
+                seed
+            ))
+            .timeout(Duration::from_secs(5))
+            .call();
+
+        if let Ok(response) = response {
+            if let Ok(value) = serde_json::from_reader::<_, Value>(response.into_reader()) {
+                let scheme = url::Url::parse(seed)
+                    .map(|url| url.scheme().to_string())
+                    .unwrap_or_else(|_| "http".to_string());
+                let nodes = value["nodes"]
+                    .as_object()
+                    .map(|nodes| {
+                        nodes
+                            .values()
+                            .filter_map(|node| node["http"]["publish_address"].as_str())
+                            .map(|address| format!("{}://{}/", scheme, publish_host(address)))
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+
+                if !nodes.is_empty() {
+                    return Some(nodes);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// A node's `publish_address` is either `ip:port` or, when it has a hostname, `hostname/ip:port`,
+/// in which case we'd rather use the hostname
+fn publish_host(address: &str) -> String {
+    match address.split_once('/') {
+        Some((host, ip_port)) if !host.is_empty() => match ip_port.rsplit_once(':') {
+            Some((_, port)) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        },
+        Some((_, ip_port)) => ip_port.to_string(),
+        None => address.to_string(),
+    }
+}
+
+/// The state of each Elasticsearch node this backend knows about
+#[pg_extern(volatile, parallel_safe)]
+fn connection_status() -> TableIterator<
+    'static,
+    (
+        name!(cluster, String),
+        name!(node, String),
+        name!(sniffed, bool),
+        name!(healthy, bool),
+        name!(cooldown_remaining, Option<f64>),
+        name!(requests, i64),
+        name!(failures, i64),
+        name!(last_error, Option<String>),
+    ),
+> {
+    let now = Instant::now();
+    let pools = NODE_POOLS.lock().unwrap();
+    let mut rows = Vec::new();
+    for (seeds, pool) in pools.iter() {
+        for node in &pool.nodes {
+            rows.push((
+                seeds.join(","),
+                node.url.clone(),
+                node.sniffed,
+                node.is_healthy(now),
+                node.down_until
+                    .filter(|until| *until > now)
+                    .map(|until| (until - now).as_secs_f64()),
+                node.requests as i64,
+                node.failures as i64,
+                node.last_error.clone(),
+            ));
+        }
+    }
+    rows.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    TableIterator::new(rows)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::access_method::options::ZDBIndexOptions;
+    use crate::elasticsearch::nodes::{has_healthy_node, publish_host, report_response};
+    use pgrx::*;
+
+    #[pg_test]
+    fn test_publish_host() {
+        assert_eq!(publish_host("10.0.0.1:9200"), "10.0.0.1:9200");
+        assert_eq!(publish_host("es1.local/10.0.0.1:9200"), "es1.local:9200");
+        assert_eq!(publish_host("/10.0.0.1:9200"), "10.0.0.1:9200");
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_connection_status() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE nodes_test(id serial8, title text);
+             CREATE INDEX idxnodes_test ON nodes_test USING zombodb ((nodes_test.*));
+             INSERT INTO nodes_test(title) VALUES ('one');",
+        )?;
+
+        let (healthy, requests) = Spi::get_two::<bool, i64>(
+            "SELECT healthy, requests FROM zdb.connection_status() WHERE node = zdb.index_url('idxnodes_test')",
+        )?;
+        assert_eq!(healthy, Some(true));
+        assert!(requests.unwrap_or_default() > 0);
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_failover_to_healthy_node() -> spi::Result<()> {
+        // nothing listens on port 1, so every request that's sent there has to be sent again to
+        // the real node
+        Spi::run(
+            "DO $$ BEGIN
+                 EXECUTE format('CREATE TABLE failover_test(id serial8, title text);
+                                 CREATE INDEX idxfailover_test ON failover_test USING zombodb ((failover_test.*)) WITH (url=%L);',
+                                'http://localhost:1/,' || current_setting('zdb.default_elasticsearch_url'));
+             END $$;
+             INSERT INTO failover_test(title) VALUES ('one'), ('two');",
+        )?;
+
+        for _ in 0..3 {
+            assert_eq!(
+                Spi::get_one::<i64>(
+                    "SELECT count(*) FROM failover_test WHERE failover_test ==> 'title:one'"
+                )?,
+                Some(1)
+            );
+        }
+
+        let (healthy, failures) = Spi::get_two::<bool, i64>(
+            "SELECT healthy, failures FROM zdb.connection_status() WHERE node = 'http://localhost:1/'",
+        )?;
+        assert_eq!(healthy, Some(false));
+        assert!(failures.unwrap_or_default() > 0);
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_has_healthy_node() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE healthy_test(id serial8, title text);
+             CREATE INDEX idxhealthy_test ON healthy_test USING zombodb ((healthy_test.*));
+             INSERT INTO healthy_test(title) VALUES ('one');",
+        )?;
+
+        let index = unsafe { PgRelation::open_with_name("idxhealthy_test").unwrap() };
+        let options = ZDBIndexOptions::from_relation(&index);
+        assert!(has_healthy_node(&options));
+
+        // what the node pool learns from failed requests is all `search_target()` goes by
+        for node in options.nodes() {
+            report_response(
+                &format!("{}_search", node),
+                Some("connection refused".into()),
+            );
+        }
+        assert!(!has_healthy_node(&options));
+
+        for node in options.nodes() {
+            report_response(&format!("{}_search", node), None);
+        }
+        assert!(has_healthy_node(&options));
+        Ok(())
+    }
+}
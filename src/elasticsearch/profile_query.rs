@@ -25,7 +25,7 @@ impl ElasticsearchProfileQueryRequest {
         };
 
         let mut url = String::new();
-        url.push_str(&self.elasticsearch.base_url());
+        url.push_str(&self.elasticsearch.search_url());
         url.push_str("/_search");
         url.push_str("?size=0");
         url.push_str("&filter_path=profile");
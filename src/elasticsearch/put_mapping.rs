@@ -21,7 +21,7 @@ impl ElasticsearchPutMappingRequest {
             }
         };
 
-        let mut url = self.elasticsearch.base_url();
+        let mut url = self.elasticsearch.search_url();
         url.push_str("/_mapping");
         Elasticsearch::execute_json_request(
             self.elasticsearch.client().post(&url),
@@ -11,7 +11,7 @@ impl ElasticsearchRefreshIndexRequest {
         Elasticsearch::execute_json_request(
             self.0
                 .client()
-                .post(&format!("{}/_refresh", self.0.base_url())),
+                .post(&format!("{}/_refresh", self.0.search_url())),
             None,
             |_| Ok(()),
         )
@@ -0,0 +1,170 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use crate::mapping::lookup_synonym_sets;
+use pgrx::prelude::*;
+use pgrx::PgRelation;
+use serde::*;
+use serde_json::*;
+
+pub struct ElasticsearchReloadSearchAnalyzersRequest(Elasticsearch);
+
+#[derive(Deserialize)]
+pub struct ReloadDetails {
+    pub index: String,
+    pub reloaded_analyzers: Vec<String>,
+}
+
+impl ElasticsearchReloadSearchAnalyzersRequest {
+    pub fn new(elasticsearch: &Elasticsearch) -> Self {
+        ElasticsearchReloadSearchAnalyzersRequest(elasticsearch.clone())
+    }
+
+    /// Push the current `zdb.synonym_sets` into the cluster's synonyms sets and have
+    /// Elasticsearch reload the search analyzers that use them.  The index stays open, and
+    /// searchable, the whole time
+    pub fn execute(self) -> std::result::Result<Vec<ReloadDetails>, ElasticsearchError> {
+        ElasticsearchReloadSearchAnalyzersRequest::put_synonym_sets(&self.0)?;
+
+        Elasticsearch::execute_json_request(
+            self.0
+                .client()
+                .post(&format!("{}/_reload_search_analyzers", self.0.search_url())),
+            None,
+            |body| {
+                #[derive(Deserialize)]
+                struct Reloaded {
+                    reload_details: Vec<ReloadDetails>,
+                }
+
+                let reloaded: Reloaded = serde_json::from_reader(body)
+                    .expect("failed to deserialize _reload_search_analyzers response");
+                Ok(reloaded.reload_details)
+            },
+        )
+    }
+
+    /// Create or replace the cluster's synonyms set for each of `zdb.synonym_sets`.  These need
+    /// to exist before an index whose analysis settings refer to them can be created
+    pub fn put_synonym_sets(
+        elasticsearch: &Elasticsearch,
+    ) -> std::result::Result<(), ElasticsearchError> {
+        if let Value::Object(synonym_sets) = lookup_synonym_sets() {
+            for (name, rules) in synonym_sets {
+                let rules = rules
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|rule| json! { { "synonyms": rule } })
+                    .collect::<Vec<_>>();
+
+                Elasticsearch::execute_json_request(
+                    elasticsearch.client().put(&format!(
+                        "{}_synonyms/{}",
+                        elasticsearch.url(),
+                        name
+                    )),
+                    Some(json! { { "synonyms_set": rules } }),
+                    |_| Ok(()),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Bring the index's synonyms up to date with `zdb.synonym_sets` without reindexing, and return
+/// the search analyzers Elasticsearch reloaded for each of its indices
+#[pg_extern(volatile, parallel_safe)]
+fn reload_search_analyzers(
+    index: PgRelation,
+) -> TableIterator<
+    'static,
+    (
+        name!(index_name, String),
+        name!(reloaded_analyzers, Vec<String>),
+    ),
+> {
+    let elasticsearch = Elasticsearch::new(&index);
+    let reloaded = elasticsearch
+        .reload_search_analyzers()
+        .execute()
+        .expect("failed to reload search analyzers");
+    elasticsearch.for_each_mirror("reload search analyzers", |mirror| {
+        mirror.reload_search_analyzers().execute().map(|_| ())
+    });
+
+    TableIterator::new(
+        reloaded
+            .into_iter()
+            .map(|details| (details.index, details.reloaded_analyzers)),
+    )
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::elasticsearch::Elasticsearch;
+    use pgrx::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_reload_search_analyzers() -> spi::Result<()> {
+        Spi::run(
+            r#"SELECT zdb.define_synonym_set('test_synonyms', ARRAY['sofa, couch']);
+               SELECT zdb.define_analyzer('test_synonym_search', '{"type": "custom", "tokenizer": "standard", "filter": ["lowercase", "test_synonyms"]}');
+               CREATE TABLE synonym_test(id serial8, title text);
+               SELECT zdb.define_field_mapping('synonym_test', 'title', '{"type": "text", "analyzer": "standard", "search_analyzer": "test_synonym_search"}');
+               INSERT INTO synonym_test(title) VALUES ('red sofa'), ('green settee');
+               CREATE INDEX idxsynonym_test ON synonym_test USING zombodb ((synonym_test.*));"#,
+        )?;
+
+        let count = || {
+            Spi::get_one::<i64>(
+                "SELECT count(*) FROM synonym_test WHERE synonym_test ==> 'title:couch'",
+            )
+        };
+        assert_eq!(count()?, Some(1));
+
+        // search the index directly from another thread for as long as the reload takes, none of
+        // which should fail as the index stays open
+        let index = unsafe { PgRelation::open_with_name("idxsynonym_test").unwrap() };
+        let elasticsearch = Elasticsearch::new(&index);
+        let client = elasticsearch.client();
+        let url = format!("{}/_search", elasticsearch.base_url());
+        let reloading = Arc::new(AtomicBool::new(true));
+        let searcher = {
+            let reloading = reloading.clone();
+            std::thread::spawn(move || {
+                let mut searches = 0;
+                while searches == 0 || reloading.load(Ordering::SeqCst) {
+                    client
+                        .post(&url)
+                        .into_request()
+                        .send_json(json! { { "query": { "match": { "title": "couch" } } } })
+                        .map_err(|e| e.to_string())?;
+                    searches += 1;
+                }
+                Ok::<_, String>(searches)
+            })
+        };
+
+        Spi::run("SELECT zdb.define_synonym_set('test_synonyms', ARRAY['sofa, couch, settee']);")?;
+        let reloaded = Spi::get_one::<Vec<String>>(
+            "SELECT reloaded_analyzers FROM zdb.reload_search_analyzers('idxsynonym_test')",
+        )?
+        .expect("no reload details");
+        reloading.store(false, Ordering::SeqCst);
+
+        let searches = searcher
+            .join()
+            .expect("searcher thread panicked")
+            .expect("search failed during reload");
+        assert!(searches > 0);
+        assert!(reloaded.contains(&"test_synonym_search".to_string()));
+        assert_eq!(count()?, Some(2));
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
 use crate::gucs::ZDB_ACCELERATOR;
 use crate::utils::read_vlong;
+use crate::zdbquery::hybrid::{Hit, Hybrid};
 use crate::zdbquery::mvcc::apply_visibility_clause;
 use crate::zdbquery::ZDBPreparedQuery;
 use pgrx::PgBuiltInOids;
@@ -76,6 +77,10 @@ pub struct ElasticsearchSearchResponse {
 
     #[serde(skip)]
     fast_terms: Option<Vec<u64>>,
+
+    /// the hits of a hybrid query, already fused and limited
+    #[serde(skip)]
+    fused: Option<Vec<Hit>>,
 }
 
 impl InnerHit {
@@ -131,9 +136,18 @@ impl ElasticsearchSearchRequest {
         query: ZDBPreparedQuery,
         extra_fields: Option<Vec<&str>>,
     ) -> std::result::Result<ElasticsearchSearchResponse, ElasticsearchError> {
+        if let Some(hybrid) = query.hybrid().cloned() {
+            return ElasticsearchSearchRequest::hybrid_search(
+                elasticsearch,
+                query,
+                hybrid,
+                extra_fields,
+            );
+        }
+
         let mut should_sort_hits = false;
         let mut url = String::new();
-        url.push_str(&elasticsearch.base_url());
+        url.push_str(&elasticsearch.search_url());
         url.push_str("/_search");
         url.push_str("?search_type=query_then_fetch");
         url.push_str("&_source=false");
@@ -182,6 +196,7 @@ impl ElasticsearchSearchRequest {
                     shards: None,
                     hits: None,
                     fast_terms: None,
+                    fused: None,
                 });
             }
             Some(limit) if limit <= elasticsearch.options.max_result_window() as u64 => {
@@ -305,6 +320,72 @@ impl ElasticsearchSearchRequest {
         )
     }
 
+    /// Run each of the hybrid query's queries on its own and fuse their results.  Each is limited
+    /// to its own `dsl.limit()`, or else the hybrid query's offset plus limit, which also means
+    /// each has the MVCC visibility clause applied.  Ranking a query's hits takes one request, so
+    /// that limit can't be more than the index's `max_result_window`
+    fn hybrid_search(
+        elasticsearch: &Elasticsearch,
+        query: ZDBPreparedQuery,
+        hybrid: Hybrid,
+        extra_fields: Option<Vec<&str>>,
+    ) -> std::result::Result<ElasticsearchSearchResponse, ElasticsearchError> {
+        let max_result_window = elasticsearch.options.max_result_window() as u64;
+        let window = query
+            .limit()
+            .map(|limit| limit + query.offset().unwrap_or_default());
+        let index = elasticsearch.index_relation();
+
+        let mut legs = Vec::with_capacity(hybrid.queries().len());
+        for leg in hybrid.queries() {
+            let limit = match leg.limit().or(window) {
+                Some(limit) if limit <= max_result_window => limit,
+                Some(limit) => panic!(
+                    "dsl.hybrid() can rank at most the index's max_result_window of {} hits per query, not {}",
+                    max_result_window, limit
+                ),
+                None => panic!(
+                    "dsl.hybrid() needs a dsl.limit(), either around it or on each of its queries"
+                ),
+            };
+            let mut leg = leg.clone().set_limit(Some(limit)).set_want_score(true);
+            for (field, highlight) in query.highlights() {
+                leg.highlights()
+                    .entry(field.clone())
+                    .or_insert_with(|| highlight.clone());
+            }
+            let leg = leg.prepare(&index, None).0;
+            let response = ElasticsearchSearchRequest::initial_search(
+                elasticsearch,
+                leg,
+                extra_fields.clone(),
+            )?;
+            legs.push(response.into_iter().collect::<Vec<_>>());
+        }
+
+        let min_score = query.min_score();
+        let fused = hybrid
+            .fuse(legs)
+            .into_iter()
+            .filter(|(score, ..)| *score >= min_score.unwrap_or(f64::MIN))
+            .skip(query.offset().unwrap_or_default() as usize)
+            .take(query.limit().map_or(usize::MAX, |limit| limit as usize))
+            .collect();
+
+        Ok(ElasticsearchSearchResponse {
+            elasticsearch: None,
+            limit: None,
+            offset: None,
+            track_scores: true,
+            should_sort_hits: false,
+            scroll_id: None,
+            shards: None,
+            hits: None,
+            fast_terms: None,
+            fused: Some(fused),
+        })
+    }
+
     fn scroll(
         elasticsearch: &Elasticsearch,
         scroll_id: &str,
@@ -312,7 +393,7 @@ impl ElasticsearchSearchRequest {
         should_sort_hits: bool,
     ) -> std::result::Result<ElasticsearchSearchResponse, ElasticsearchError> {
         let mut url = String::new();
-        url.push_str(&elasticsearch.options.url());
+        url.push_str(&elasticsearch.url());
         url.push_str("_search/scroll");
         url.push_str("?filter_path=");
         if track_scores {
@@ -350,7 +431,7 @@ impl ElasticsearchSearchRequest {
     ) -> std::result::Result<ElasticsearchSearchResponse, ElasticsearchError> {
         if fast_terms {
             let mut url = String::new();
-            url.push_str(&elasticsearch.base_url());
+            url.push_str(&elasticsearch.search_url());
             url.push_str("/_fastterms");
 
             Elasticsearch::execute_json_request(
@@ -384,6 +465,7 @@ impl ElasticsearchSearchRequest {
                         shards: None,
                         hits: None,
                         fast_terms: Some(fast_terms),
+                        fused: None,
                     })
                 },
             )
@@ -430,6 +512,10 @@ impl ElasticsearchSearchResponse {
     }
 
     pub fn len(&self) -> usize {
+        if let Some(fused) = self.fused.as_ref() {
+            return fused.len();
+        }
+
         match self.hits.as_ref() {
             Some(hits) => hits.total.value as usize,
             None => 0,
@@ -596,6 +682,7 @@ pub struct SearchResponseIntoIter {
     scroller: Option<Scroller>,
     limit: Option<u64>,
     cnt: u64,
+    fused: Option<std::vec::IntoIter<Hit>>,
     fast_terms: Option<std::vec::IntoIter<u64>>,
     // fast_terms: Option<std::vec::IntoIter<[u8; 6]>>,
     // fast_terms: Option<roaring::treemap::IntoIter>,
@@ -618,6 +705,10 @@ impl Iterator for SearchResponseIntoIter {
     );
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(fused) = self.fused.as_mut() {
+            return fused.next();
+        }
+
         match self.fast_terms.as_mut() {
             Some(fast_terms) => fast_terms.next().map(|ctid| (0.0, ctid, None, None)),
             None => {
@@ -647,11 +738,20 @@ impl IntoIterator for ElasticsearchSearchResponse {
     type IntoIter = SearchResponseIntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        if self.fast_terms.is_some() {
+        if let Some(fused) = self.fused {
+            SearchResponseIntoIter {
+                scroller: None,
+                limit: None,
+                cnt: 0,
+                fused: Some(fused.into_iter()),
+                fast_terms: None,
+            }
+        } else if self.fast_terms.is_some() {
             SearchResponseIntoIter {
                 scroller: None,
                 limit: self.limit,
                 cnt: 0,
+                fused: None,
                 fast_terms: Some(self.fast_terms.unwrap().into_iter()),
             }
         } else if self.elasticsearch.is_none() {
@@ -659,6 +759,7 @@ impl IntoIterator for ElasticsearchSearchResponse {
                 scroller: None,
                 limit: Some(0),
                 cnt: 0,
+                fused: None,
                 fast_terms: None,
             }
         } else {
@@ -683,6 +784,7 @@ impl IntoIterator for ElasticsearchSearchResponse {
                 scroller: Some(scroller),
                 limit: self.limit,
                 cnt: 0,
+                fused: None,
                 fast_terms: None,
             }
         }
@@ -815,4 +917,31 @@ mod tests {
         ).is_err());
         Ok(())
     }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_hybrid_rrf() -> spi::Result<()> {
+        Spi::run("CREATE TABLE test_hybrid AS SELECT * FROM generate_series(1, 10);")?;
+        Spi::run("CREATE INDEX idxtest_hybrid ON test_hybrid USING zombodb ((test_hybrid.*));")?;
+        Spi::connect(|client| {
+            let table = client.select(
+                "SELECT generate_series FROM test_hybrid WHERE test_hybrid ==> dsl.hybrid(ARRAY[
+                    dsl.sort('generate_series', 'asc', dsl.limit(3, dsl.match_all())),
+                    dsl.sort('generate_series', 'asc', dsl.terms('generate_series', 2, 9))
+                 ]) ORDER BY zdb.score(ctid) DESC;",
+                None,
+                &[],
+            )?;
+
+            let ids = table
+                .map(|row| {
+                    row.get::<i32>(1)
+                        .expect("SPI failed")
+                        .expect("value was NULL")
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![2, 1, 9, 3]);
+            Ok(())
+        })
+    }
 }
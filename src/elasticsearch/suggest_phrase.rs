@@ -0,0 +1,263 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use crate::zdbquery::mvcc::apply_visibility_clause;
+use crate::zdbquery::{ZDBPreparedQuery, ZDBQuery};
+use crate::zql::ast::{Expr, IndexLink};
+use crate::zql::transformations::correct_terms::{correct_string_terms, find_string_terms};
+use pgrx::prelude::*;
+use pgrx::*;
+use serde::*;
+use serde_json::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Deserialize)]
+pub struct SuggestPhraseOptions {
+    pub text: String,
+    pub score: f64,
+}
+
+pub struct ElasticsearchSuggestPhraseRequest {
+    elasticsearch: Elasticsearch,
+    collate: ZDBPreparedQuery,
+    fieldname: String,
+    text: String,
+    size: u32,
+    max_errors: Option<f32>,
+    confidence: Option<f32>,
+}
+
+impl ElasticsearchSuggestPhraseRequest {
+    pub fn new(
+        elasticsearch: &Elasticsearch,
+        collate: ZDBPreparedQuery,
+        fieldname: String,
+        text: String,
+        size: u32,
+        max_errors: Option<f32>,
+        confidence: Option<f32>,
+    ) -> Self {
+        ElasticsearchSuggestPhraseRequest {
+            elasticsearch: elasticsearch.clone(),
+            collate,
+            fieldname,
+            text,
+            size,
+            max_errors,
+            confidence,
+        }
+    }
+
+    pub fn execute(self) -> std::result::Result<Vec<SuggestPhraseOptions>, ElasticsearchError> {
+        let mut phrase = json! {
+            {
+                "field": self.fieldname,
+                "size": self.size,
+                "direct_generator": [{ "field": self.fieldname }],
+                "collate": {
+                    // Elasticsearch fills in {{suggestion}} for each candidate and drops those
+                    // that don't match a row visible to our transaction
+                    "query": {
+                        "source": apply_visibility_clause(&self.elasticsearch, self.collate, false)
+                    }
+                }
+            }
+        };
+        if let Some(max_errors) = self.max_errors {
+            phrase["max_errors"] = json!(max_errors);
+        }
+        if let Some(confidence) = self.confidence {
+            phrase["confidence"] = json!(confidence);
+        }
+
+        let body = json! {
+            {
+                "suggest" : {
+                    "suggestion" : {
+                        "text" : self.text,
+                        "phrase" : phrase
+                    }
+                }
+            }
+        };
+
+        let mut url = self.elasticsearch.alias_url();
+        url.push_str("/_search?size=0");
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().post(&url),
+            Some(body),
+            |body| {
+                #[derive(Deserialize)]
+                struct SuggestPhraseResponse {
+                    options: Vec<SuggestPhraseOptions>,
+                }
+
+                #[derive(Deserialize)]
+                #[serde(rename(deserialize = "suggest"))]
+                struct Suggest {
+                    suggestion: Vec<SuggestPhraseResponse>,
+                }
+
+                #[derive(Deserialize)]
+                struct WholeResponse {
+                    suggest: Suggest,
+                }
+
+                let response: WholeResponse = serde_json::from_reader(body)
+                    .expect("failed to deserialize suggest phrase response");
+                Ok(response
+                    .suggest
+                    .suggestion
+                    .into_iter()
+                    .flat_map(|suggestion| suggestion.options)
+                    .collect())
+            },
+        )
+    }
+}
+
+/// Ask the phrase suggester for corrections of `text` from `field_name`, keeping only those
+/// that match a visible row
+fn phrase_suggestions(
+    index: &PgRelation,
+    field_name: String,
+    text: String,
+    size: i32,
+    max_errors: Option<f32>,
+    confidence: Option<f32>,
+) -> Vec<SuggestPhraseOptions> {
+    if size < 1 {
+        panic!("size must be at least 1")
+    }
+
+    let collate = ZDBQuery::new_with_query_dsl(json! {
+        {
+            "match_phrase": {
+                field_name.clone(): "{{suggestion}}"
+            }
+        }
+    });
+    let (collate, index) = collate.prepare(index, Some(field_name.clone()));
+
+    let elasticsearch = Elasticsearch::new(&index);
+    elasticsearch
+        .suggest_phrase(
+            collate,
+            field_name,
+            text,
+            size as u32,
+            max_errors,
+            confidence,
+        )
+        .execute()
+        .expect("failed to suggest phrase")
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn suggest_phrase(
+    index: PgRelation,
+    field_name: String,
+    text: String,
+    size: default!(i32, 5),
+    max_errors: default!(Option<f32>, NULL),
+    confidence: default!(Option<f32>, NULL),
+) -> TableIterator<'static, (name!(suggestion, String), name!(score, f64))> {
+    TableIterator::new(
+        phrase_suggestions(&index, field_name, text, size, max_errors, confidence)
+            .into_iter()
+            .map(|option| (option.text, option.score))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Rewrite each single-term value in the ZQL `query` to the phrase suggester's best correction
+/// for it, if it has one, so that "did you mean" can offer a whole corrected query
+#[pg_extern(immutable, parallel_safe)]
+fn correct_query(index: PgRelation, query: &str) -> String {
+    let mut used_fields = HashSet::new();
+    let expr = Expr::from_str(
+        &index,
+        "zdb_all",
+        query,
+        &IndexLink::from_zdb(&index),
+        &None,
+        &mut used_fields,
+    )
+    .expect("failed to parse query");
+
+    let mut terms = Vec::new();
+    find_string_terms(&expr, &mut terms);
+
+    let mut corrections = HashMap::new();
+    for (field, term) in terms {
+        let key = (field, term.to_string());
+        if corrections.contains_key(&key) {
+            continue;
+        }
+
+        let field_index = match key.0.index.as_ref().map(|link| link.open_index()) {
+            Some(Ok(field_index)) => field_index,
+            _ => index.clone(),
+        };
+        let correction = phrase_suggestions(
+            &field_index,
+            key.0.field_name(),
+            key.1.clone(),
+            1,
+            None,
+            None,
+        )
+        .into_iter()
+        .next()
+        .map(|option| option.text)
+        .filter(|correction| correction.as_str() != term);
+
+        if let Some(correction) = correction {
+            corrections.insert(key, correction);
+        }
+    }
+
+    let mut expr = expr;
+    correct_string_terms(&mut expr, &corrections);
+    format!("{}", expr)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    fn setup() -> spi::Result<()> {
+        Spi::run(
+            "CREATE TABLE suggest_phrase_test(id serial8, title text);
+             CREATE INDEX idxsuggest_phrase_test ON suggest_phrase_test USING zombodb ((suggest_phrase_test.*));
+             INSERT INTO suggest_phrase_test(title) VALUES ('the quick brown fox'), ('the quirk brown fox');
+             DELETE FROM suggest_phrase_test WHERE title = 'the quirk brown fox';",
+        )
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_suggest_phrase_only_visible_rows() -> spi::Result<()> {
+        setup()?;
+
+        let suggestions = Spi::get_one::<Vec<String>>(
+            "SELECT array_agg(suggestion) FROM zdb.suggest_phrase('idxsuggest_phrase_test', 'title', 'quikc brown')",
+        )?;
+        assert_eq!(suggestions, Some(vec!["quick brown".to_string()]));
+        Ok(())
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_correct_query() -> spi::Result<()> {
+        setup()?;
+
+        let corrected = Spi::get_one::<String>(
+            "SELECT zdb.correct_query('idxsuggest_phrase_test', 'title:quikc AND title:fox')",
+        )?;
+        assert_eq!(
+            corrected,
+            Some(r#"(title:"quick" AND title:"fox")"#.to_string())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use serde_json::*;
+
+pub struct ElasticsearchUpdateByQueryRequest {
+    elasticsearch: Elasticsearch,
+    query: Value,
+    script: Value,
+}
+
+impl ElasticsearchUpdateByQueryRequest {
+    pub fn new(elasticsearch: &Elasticsearch, query: Value, script: Value) -> Self {
+        ElasticsearchUpdateByQueryRequest {
+            elasticsearch: elasticsearch.clone(),
+            query,
+            script,
+        }
+    }
+
+    /// Run the painless script on the documents matching the query in the index's backing
+    /// indices, returning how many were updated.  It's an error if any of them couldn't be
+    pub fn execute(self) -> std::result::Result<u64, ElasticsearchError> {
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().post(&format!(
+                "{}{}/_update_by_query?refresh=true&conflicts=abort",
+                self.elasticsearch.url(),
+                self.elasticsearch.backing_index_pattern()
+            )),
+            Some(json! { { "query": self.query, "script": self.script } }),
+            |body| {
+                let response: Value = serde_json::from_reader(body)
+                    .expect("failed to parse _update_by_query response");
+
+                let failures = response["failures"].as_array().map_or(0, Vec::len);
+                let version_conflicts = response["version_conflicts"].as_u64().unwrap_or_default();
+                if failures > 0 || version_conflicts > 0 {
+                    return Err(ElasticsearchError(
+                        None,
+                        format!(
+                            "_update_by_query had {} failures and {} version conflicts: {}",
+                            failures, version_conflicts, response["failures"]
+                        ),
+                    ));
+                }
+
+                Ok(response["updated"].as_u64().unwrap_or_default())
+            },
+        )
+    }
+}
@@ -12,7 +12,7 @@ impl ElasticsearchUpdateSettingsRequest {
         Elasticsearch::execute_json_request(
             self.0
                 .client()
-                .put(&format!("{}/_settings", self.0.base_url())),
+                .put(&format!("{}/_settings", self.0.search_url())),
             Some(json! {
                 {
                     "index": {
@@ -0,0 +1,35 @@
+use crate::elasticsearch::{Elasticsearch, ElasticsearchError};
+use crate::zdbquery::ZDBPreparedQuery;
+use serde_json::*;
+
+pub struct ElasticsearchValidateQueryRequest {
+    elasticsearch: Elasticsearch,
+    query: ZDBPreparedQuery,
+}
+
+impl ElasticsearchValidateQueryRequest {
+    pub fn new(elasticsearch: &Elasticsearch, query: ZDBPreparedQuery) -> Self {
+        ElasticsearchValidateQueryRequest {
+            elasticsearch: elasticsearch.clone(),
+            query,
+        }
+    }
+
+    pub fn execute(self) -> std::result::Result<serde_json::Value, ElasticsearchError> {
+        let body = json! {
+            {
+                "query": self.query.query_dsl()
+            }
+        };
+
+        let mut url = String::new();
+        url.push_str(&self.elasticsearch.search_url());
+        url.push_str("/_validate/query");
+        url.push_str("?explain=true");
+        Elasticsearch::execute_json_request(
+            self.elasticsearch.client().post(&url),
+            Some(body),
+            |body| Ok(serde_json::from_reader(body).expect("failed to parse response json")),
+        )
+    }
+}
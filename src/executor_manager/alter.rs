@@ -1,10 +1,13 @@
 use crate::access_method::options::ZDBIndexOptions;
 use crate::elasticsearch::Elasticsearch;
 use crate::mapping::{categorize_tupdesc, generate_default_mapping};
-use crate::utils::{is_zdb_index, lookup_zdb_index_tupdesc};
+use crate::utils::{
+    find_zdb_partition_ancestors, find_zdb_partition_indices, is_non_shadow_zdb_index,
+    is_partitioned_zdb_index, is_zdb_index, lookup_zdb_index_tupdesc,
+};
 use pgrx::{
-    pg_sys, register_xact_callback, spi, warning, IntoDatum, PgBuiltInOids, PgRelation,
-    PgXactCallbackEvent, Spi,
+    is_a, pg_sys, register_xact_callback, spi, warning, IntoDatum, PgBox, PgBuiltInOids, PgList,
+    PgRelation, PgXactCallbackEvent, Spi,
 };
 
 pub fn get_index_options_for_relation(relation: &PgRelation) -> Vec<ZDBIndexOptions> {
@@ -47,7 +50,7 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
             };
             (option, ZDBIndexOptions::from_relation(&index), index)
         }) {
-            if old_options.url() != new_options.url()
+            if old_options.nodes() != new_options.nodes()
                 && !unsafe { pg_sys::session_auth_is_superuser }
             {
                 panic!("You must be a superuser to change the 'url' parameter")
@@ -65,10 +68,23 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
                 warning!("Number of shards changed from {} to {}.  You must issue a REINDEX before this change will take effect", old_options.shards(), new_options.shards());
             }
 
+            if old_options.exclude_columns() != new_options.exclude_columns()
+                || old_options.unstored_columns() != new_options.unstored_columns()
+                || old_options.completion_columns() != new_options.completion_columns()
+            {
+                warning!("Excluded, unstored, or completion columns changed.  You must issue a REINDEX before this change will take effect for existing rows");
+            }
+
             if old_options.is_shadow_index() != new_options.is_shadow_index() {
                 panic!("The 'shadow' index property cannot be changed");
             }
 
+            if old_options.rollover() != new_options.rollover()
+                || old_options.rollover_retention() != new_options.rollover_retention()
+            {
+                panic!("The 'rollover' and 'rollover_retention' index properties can only be set during CREATE INDEX");
+            }
+
             let es = Elasticsearch::new(&index);
 
             // change the index settings
@@ -81,12 +97,28 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
             let tupdesc = lookup_zdb_index_tupdesc(&index);
             let heap_relation = index.heap_relation().expect("no heap relation for index!");
             let mut mapping = generate_default_mapping(&heap_relation);
-            let _ = categorize_tupdesc(&tupdesc, &heap_relation, Some(&mut mapping));
-            es.put_mapping(
-                serde_json::to_value(&mapping).expect("failed to serialize mapping to json"),
-            )
-            .execute()
-            .expect("failed to update index mapping");
+            let _ = categorize_tupdesc(&tupdesc, &index, Some(&mut mapping));
+            let mapping =
+                serde_json::to_value(&mapping).expect("failed to serialize mapping to json");
+            es.put_mapping(mapping.clone())
+                .execute()
+                .expect("failed to update index mapping");
+
+            // and future backing indices of a rollover index get them both too
+            if es.is_rollover() {
+                es.update_rollover_template(mapping.clone())
+                    .expect("failed to update rollover template");
+            }
+
+            // mirrors get the same settings and mapping
+            es.for_each_mirror("update index settings and mapping", |mirror| {
+                mirror.update_settings().execute()?;
+                mirror.put_mapping(mapping.clone()).execute()?;
+                if mirror.is_rollover() {
+                    mirror.update_rollover_template(mapping.clone())?;
+                }
+                Ok(())
+            });
 
             // if the user changed the alias
             if old_options.alias() != new_options.alias() {
@@ -94,6 +126,9 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
                 es.add_alias(new_options.alias())
                     .execute()
                     .expect("failed to add index to new alias");
+                es.for_each_mirror("add index to new alias", |mirror| {
+                    mirror.add_alias(new_options.alias()).execute()
+                });
 
                 // register a pre-commit callback which will remove the index from its old alias
                 register_xact_callback(PgXactCallbackEvent::PreCommit, move || {
@@ -102,6 +137,9 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
                     es.remove_alias(&alias)
                         .execute()
                         .expect("failed to remove index from old alias");
+                    es.for_each_mirror("remove index from old alias", |mirror| {
+                        mirror.remove_alias(&alias).execute()
+                    });
                 });
 
                 // register an abort callback which will remove the index from the new alias
@@ -121,3 +159,108 @@ pub fn alter_indices(prev_options: Option<Vec<ZDBIndexOptions>>) {
         }
     }
 }
+
+/// Returns the partitions `alter` attaches (`true`) to or detaches (`false`) from a partitioned table
+pub fn find_partition_commands(alter: &PgBox<pg_sys::AlterTableStmt>) -> Vec<(bool, pg_sys::Oid)> {
+    let mut partitions = Vec::new();
+    let cmds = unsafe { PgList::<pg_sys::AlterTableCmd>::from_pg(alter.cmds) };
+    for cmd in cmds.iter_ptr() {
+        let cmd = unsafe { PgBox::from_pg(cmd) };
+        let is_attach = match cmd.subtype {
+            pg_sys::AlterTableType::AT_AttachPartition => true,
+            pg_sys::AlterTableType::AT_DetachPartition => false,
+            _ => continue,
+        };
+
+        if unsafe { !is_a(cmd.def, pg_sys::NodeTag::T_PartitionCmd) } {
+            continue;
+        }
+
+        let partition_cmd = unsafe { PgBox::from_pg(cmd.def as *mut pg_sys::PartitionCmd) };
+        let relid = unsafe {
+            pg_sys::RangeVarGetRelidExtended(
+                partition_cmd.name,
+                pg_sys::AccessShareLock as pg_sys::LOCKMODE,
+                pg_sys::RVROption::RVR_MISSING_OK,
+                None,
+                std::ptr::null_mut(),
+            )
+        };
+        if relid != pg_sys::InvalidOid {
+            partitions.push((is_attach, relid));
+        }
+    }
+    partitions
+}
+
+/// Returns the options of each ZomboDB index on the partition `relid`, along with the aliases of
+/// the partitioned indices above it
+pub fn get_partition_aliases(relid: pg_sys::Oid) -> Vec<(ZDBIndexOptions, Vec<String>)> {
+    let partition =
+        unsafe { PgRelation::with_lock(relid, pg_sys::AccessShareLock as pg_sys::LOCKMODE) };
+    let mut aliases = Vec::new();
+
+    for index in partition.indices(pg_sys::AccessShareLock as pg_sys::LOCKMODE) {
+        if !is_zdb_index(&index) {
+            continue;
+        }
+
+        // a partition can itself be partitioned, in which case it's its leaf partitions that
+        // have Elasticsearch indices
+        let leaves = if is_partitioned_zdb_index(&index) {
+            find_zdb_partition_indices(&index, true)
+        } else if is_non_shadow_zdb_index(&index) {
+            vec![index]
+        } else {
+            vec![]
+        };
+
+        for leaf in leaves {
+            let ancestors = find_zdb_partition_ancestors(&leaf)
+                .iter()
+                .map(|ancestor| ZDBIndexOptions::from_relation(ancestor).alias().to_string())
+                .collect();
+            aliases.push((ZDBIndexOptions::from_relation(&leaf), ancestors));
+        }
+    }
+
+    aliases
+}
+
+pub fn attach_partition_aliases(aliases: Vec<(ZDBIndexOptions, Vec<String>)>) {
+    for (options, aliases) in aliases {
+        let es = Elasticsearch::from_options(options.clone());
+        for alias in &aliases {
+            es.add_alias(alias)
+                .execute()
+                .expect("failed to add partition's index to partitioned index alias");
+        }
+
+        // if the transaction aborts, the partition isn't attached after all
+        register_xact_callback(PgXactCallbackEvent::Abort, move || {
+            let es = Elasticsearch::from_options(options);
+            for alias in &aliases {
+                if let Err(e) = es.remove_alias(alias).execute() {
+                    warning!(
+                        "failed to remove index from partitioned index alias on transaction abort: {:?}",
+                        e
+                    )
+                }
+            }
+        });
+    }
+}
+
+pub fn detach_partition_aliases(aliases: Vec<(ZDBIndexOptions, Vec<String>)>) {
+    for (options, aliases) in aliases {
+        // the partition is only detached once the transaction commits
+        register_xact_callback(PgXactCallbackEvent::PreCommit, move || {
+            let es = Elasticsearch::from_options(options);
+            for alias in &aliases {
+                es.remove_alias(alias)
+                    .execute()
+                    .expect("failed to remove partition's index from partitioned index alias");
+            }
+        });
+    }
+}
@@ -6,9 +6,21 @@ use std::panic::AssertUnwindSafe;
 
 use crate::elasticsearch::Elasticsearch;
 use crate::gucs::ZDB_LOG_LEVEL;
-use crate::utils::{is_non_shadow_zdb_index, lookup_zdb_extension_oid};
+use crate::utils::{
+    find_zdb_partition_indices, is_non_shadow_zdb_index, is_partitioned_zdb_index,
+    lookup_zdb_extension_oid,
+};
 
 pub fn drop_index(index: &PgRelation) {
+    if is_partitioned_zdb_index(index) {
+        // a partitioned index has no remote index of its own, but dropping it also drops the
+        // indices on all its partitions
+        for partition in find_zdb_partition_indices(index, true) {
+            drop_index(&partition);
+        }
+        return;
+    }
+
     // we can only delete the remote index for actual ZDB indices
     if is_non_shadow_zdb_index(index) {
         // when the transaction commits, we'll make a best effort to delete this index
@@ -23,6 +35,9 @@ pub fn drop_index(index: &PgRelation) {
             // we're just going to assume it worked, throwing away any error
             // because raising an elog(ERROR) here would cause Postgres to panic
             es.delete_index().execute().ok();
+            for mirror in es.mirrors() {
+                mirror.delete_index().execute().ok();
+            }
         });
     }
 }
@@ -38,6 +53,7 @@ pub fn drop_schema(schema_oid: pg_sys::Oid) {
         let table = client.select(
             "select oid from pg_class
                     where relnamespace = $1
+                      and relkind = 'i'
                       and relam = (select oid from pg_am where amname = 'zombodb')",
             None,
             &[schema_oid.into()],
@@ -53,7 +69,8 @@ pub fn drop_extension(extension_oid: pg_sys::Oid) {
         Spi::connect(|client| {
             let table = client.select(
                 "select oid from pg_class
-                    where relam = (select oid from pg_am where amname = 'zombodb')",
+                    where relkind = 'i'
+                      and relam = (select oid from pg_am where amname = 'zombodb')",
                 None,
                 &[],
             )?;
@@ -1,9 +1,13 @@
+use crate::access_method::concurrent::{begin_index_statement, finish_concurrent_builds};
 use crate::access_method::rewriter::rewrite_opexrs;
+use crate::access_method::triggers::{restore_triggers, tables_with_triggers};
 use crate::executor_manager::alter::{
-    alter_indices, get_index_options_for_relation, get_index_options_for_schema,
+    alter_indices, attach_partition_aliases, detach_partition_aliases, find_partition_commands,
+    get_index_options_for_relation, get_index_options_for_schema, get_partition_aliases,
 };
 use crate::executor_manager::drop::{drop_extension, drop_index, drop_schema, drop_table};
 use crate::executor_manager::get_executor_manager;
+use crate::executor_manager::prepared::finish_prepared_transactions;
 use crate::walker::PlanWalker;
 use pgrx::*;
 use std::ffi::CStr;
@@ -61,6 +65,67 @@ impl PgHooks for ZDBHooks {
         };
         let is_rename = unsafe { is_a(utility_statement.as_ptr(), pg_sys::NodeTag::T_RenameStmt) };
         let is_drop = unsafe { is_a(utility_statement.as_ptr(), pg_sys::NodeTag::T_DropStmt) };
+        let is_transaction = unsafe {
+            is_a(
+                utility_statement.as_ptr(),
+                pg_sys::NodeTag::T_TransactionStmt,
+            )
+        };
+        let is_index = unsafe { is_a(utility_statement.as_ptr(), pg_sys::NodeTag::T_IndexStmt) };
+        let is_reindex =
+            unsafe { is_a(utility_statement.as_ptr(), pg_sys::NodeTag::T_ReindexStmt) };
+
+        // concurrent index builds span several transactions, so they're tracked per statement
+        let is_toplevel_index_build = (is_index || is_reindex)
+            && context == pg_sys::ProcessUtilityContext::PROCESS_UTILITY_TOPLEVEL;
+        if is_toplevel_index_build {
+            begin_index_statement();
+        }
+
+        if is_transaction {
+            let stmt = unsafe {
+                PgBox::from_pg(utility_statement.as_ptr() as *mut pg_sys::TransactionStmt)
+            };
+
+            match stmt.kind {
+                pg_sys::TransactionStmtKind::TRANS_STMT_PREPARE => {
+                    if unsafe {
+                        pg_sys::IsTransactionBlock() && !pg_sys::IsAbortedTransactionBlockState()
+                    } {
+                        let gid = unsafe { CStr::from_ptr(stmt.gid) };
+                        get_executor_manager()
+                            .set_prepared_gid(gid.to_str().expect("gid is not valid UTF8"));
+                    }
+                }
+
+                pg_sys::TransactionStmtKind::TRANS_STMT_COMMIT_PREPARED => {
+                    let gid = unsafe { CStr::from_ptr(stmt.gid) }
+                        .to_str()
+                        .expect("gid is not valid UTF8")
+                        .to_string();
+
+                    // the prepared transaction needs to have committed before its xids can be
+                    // marked as committed in Elasticsearch
+                    let result = prev_hook(
+                        pstmt,
+                        query_string,
+                        read_only_tree,
+                        context,
+                        params,
+                        query_env,
+                        dest,
+                        completion_tag,
+                    );
+
+                    finish_prepared_transactions(Some(&gid));
+                    return result;
+                }
+
+                // ROLLBACK PREPARED discards what the prepared transaction recorded, so its xids
+                // remain aborted in Elasticsearch, same as any other aborted transaction
+                _ => {}
+            }
+        }
 
         if is_alter {
             let alter = unsafe {
@@ -90,6 +155,14 @@ impl PgHooks for ZDBHooks {
             let prev_options = get_index_options_for_relation(&rel);
             drop(rel);
 
+            // partitions being detached need to know their aliases before they're detached
+            let partitions = find_partition_commands(&alter);
+            let detached_aliases = partitions
+                .iter()
+                .filter(|(is_attach, _)| !is_attach)
+                .flat_map(|(_, relid)| get_partition_aliases(*relid))
+                .collect::<Vec<_>>();
+
             // call the prev hook to go ahead and apply the ALTER statement to the index
             let result = prev_hook(
                 pstmt,
@@ -103,6 +176,14 @@ impl PgHooks for ZDBHooks {
             );
 
             alter_indices(Some(prev_options));
+            detach_partition_aliases(detached_aliases);
+            attach_partition_aliases(
+                partitions
+                    .iter()
+                    .filter(|(is_attach, _)| *is_attach)
+                    .flat_map(|(_, relid)| get_partition_aliases(*relid))
+                    .collect(),
+            );
             return result;
         } else if is_rename {
             let rename =
@@ -153,7 +234,11 @@ impl PgHooks for ZDBHooks {
 
             alter_indices(prev_options);
             return result;
-        } else if is_drop {
+        }
+
+        // tables that may need their triggers back once the indices they belonged to are dropped
+        let mut tables_losing_triggers = Vec::new();
+        if is_drop {
             let drop =
                 unsafe { PgBox::from_pg(utility_statement.as_ptr() as *mut pg_sys::DropStmt) };
 
@@ -189,6 +274,7 @@ impl PgHooks for ZDBHooks {
                             }
                             pg_sys::ObjectType::OBJECT_INDEX => {
                                 let rel = unsafe { PgRelation::from_pg_owned(rel) };
+                                tables_losing_triggers.extend(tables_with_triggers(&rel));
                                 drop_index(&rel)
                             }
                             pg_sys::ObjectType::OBJECT_SCHEMA => drop_schema(address.objectId),
@@ -204,7 +290,7 @@ impl PgHooks for ZDBHooks {
             }
         }
 
-        prev_hook(
+        let result = prev_hook(
             pstmt,
             query_string,
             read_only_tree,
@@ -213,7 +299,15 @@ impl PgHooks for ZDBHooks {
             query_env,
             dest,
             completion_tag,
-        )
+        );
+
+        if is_toplevel_index_build {
+            finish_concurrent_builds();
+        }
+        for heap_oid in tables_losing_triggers {
+            restore_triggers(heap_oid);
+        }
+        result
     }
 
     fn planner(
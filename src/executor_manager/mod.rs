@@ -1,4 +1,5 @@
 use crate::access_method::options::ZDBIndexOptions;
+use crate::elasticsearch::analyze::Token;
 use crate::elasticsearch::{Elasticsearch, ElasticsearchBulkRequest};
 use crate::mapping::{categorize_tupdesc, CategorizedAttribute};
 use crate::utils::{find_zdb_index, lookup_all_zdb_index_oids, lookup_zdb_index_tupdesc};
@@ -10,6 +11,7 @@ use std::collections::{HashMap, HashSet};
 pub mod alter;
 pub mod drop;
 pub mod hooks;
+pub mod prepared;
 
 static mut EXECUTOR_MANAGER: ExecutorManager = ExecutorManager::new();
 
@@ -22,7 +24,8 @@ pub struct BulkContext {
     pub es_bulk_request: ElasticsearchBulkRequest,
     pub attributes: Vec<CategorizedAttribute>,
     pub natts: usize,
-    pub dropped: Vec<bool>,
+    /// dropped columns, and those named in the index's `exclude_columns` option
+    pub skipped: Vec<bool>,
     pub is_shadow: bool,
     pub tupdesc: PgTupleDesc<'static>,
 }
@@ -35,8 +38,25 @@ pub struct QueryState {
         HashMap<String, Vec<String>>,
     >,
     zdb_index_lookup: HashMap<pg_sys::Oid, pg_sys::Oid>,
+
+    /// The HOT chain root of each tuple on the heap pages of rows we've been asked about.  A row
+    /// that's been HOT-updated is still known to Elasticsearch by its chain's root, but the executor
+    /// hands us the tid of the visible tuple at the end of the chain
+    hot_roots: HashMap<(pg_sys::Oid, pg_sys::BlockNumber), Vec<pg_sys::OffsetNumber>>,
+
+    /// What Elasticsearch's `_analyze` returned for each text highlighted by this query, by index
+    /// and field, so a text that appears in many rows is only sent once
+    analyzed_texts: HashMap<(pg_sys::Oid, String), HashMap<String, Vec<Token>>>,
+    analyzed_texts_len: usize,
+
+    /// The ZomboDB indices on each table this query has changed, as `zdb_update_trigger` and
+    /// `zdb_delete_trigger` need them for every row
+    ready_zdb_indices: HashMap<pg_sys::Oid, Vec<(pg_sys::Oid, bool)>>,
 }
 
+/// How many analyzed texts a query will remember for highlighting
+const MAX_ANALYZED_TEXTS: usize = 10_000;
+
 impl QueryState {
     #[inline]
     pub fn add_score(&mut self, heap_oid: pg_sys::Oid, ctid64: u64, score: f64) {
@@ -50,11 +70,9 @@ impl QueryState {
         }
     }
 
-    pub fn get_score(&self, heap_oid: pg_sys::Oid, ctid: pg_sys::ItemPointerData) -> f64 {
-        *self
-            .scores
-            .get(&(heap_oid, item_pointer_get_both(ctid)))
-            .unwrap_or(&0.0f64)
+    pub fn get_score(&mut self, heap_oid: pg_sys::Oid, ctid: pg_sys::ItemPointerData) -> f64 {
+        let key = self.resolve_key(heap_oid, ctid);
+        *self.scores.get(&key).unwrap_or(&0.0f64)
     }
 
     #[inline]
@@ -92,30 +110,93 @@ impl QueryState {
     }
 
     pub fn get_highlights(
-        &self,
+        &mut self,
         heap_oid: pg_sys::Oid,
         ctid: pg_sys::ItemPointerData,
     ) -> Option<&HashMap<String, Vec<String>>> {
-        self.highlights
-            .get(&(heap_oid, item_pointer_get_both(ctid)))
+        let key = self.resolve_key(heap_oid, ctid);
+        self.highlights.get(&key)
     }
 
     pub fn get_highlight(
-        &self,
+        &mut self,
         heap_oid: pg_sys::Oid,
         ctid: pg_sys::ItemPointerData,
         field: &str,
     ) -> Option<&Vec<String>> {
-        if let Some(map) = self
-            .highlights
-            .get(&(heap_oid, item_pointer_get_both(ctid)))
-        {
+        let key = self.resolve_key(heap_oid, ctid);
+        if let Some(map) = self.highlights.get(&key) {
             map.get(field)
         } else {
             None
         }
     }
 
+    pub fn get_ready_zdb_indices<F: FnOnce() -> Vec<(pg_sys::Oid, bool)>>(
+        &mut self,
+        heap_oid: pg_sys::Oid,
+        lookup: F,
+    ) -> Vec<(pg_sys::Oid, bool)> {
+        self.ready_zdb_indices
+            .entry(heap_oid)
+            .or_insert_with(lookup)
+            .clone()
+    }
+
+    pub fn get_analyzed_text(
+        &self,
+        index_oid: pg_sys::Oid,
+        field: &str,
+        text: &str,
+    ) -> Option<&Vec<Token>> {
+        self.analyzed_texts
+            .get(&(index_oid, field.to_string()))
+            .and_then(|texts| texts.get(text))
+    }
+
+    pub fn add_analyzed_text(
+        &mut self,
+        index_oid: pg_sys::Oid,
+        field: &str,
+        text: &str,
+        tokens: Vec<Token>,
+    ) {
+        if self.analyzed_texts_len >= MAX_ANALYZED_TEXTS {
+            return;
+        }
+
+        let texts = self
+            .analyzed_texts
+            .entry((index_oid, field.to_string()))
+            .or_default();
+        if texts.insert(text.to_string(), tokens).is_none() {
+            self.analyzed_texts_len += 1;
+        }
+    }
+
+    /// The key a visible tuple's score and highlights are stored under, which is its HOT chain's
+    /// root if it's a heap-only tuple
+    fn resolve_key(
+        &mut self,
+        index_oid: pg_sys::Oid,
+        ctid: pg_sys::ItemPointerData,
+    ) -> (pg_sys::Oid, (pg_sys::BlockNumber, pg_sys::OffsetNumber)) {
+        let (blockno, offno) = item_pointer_get_both(ctid);
+        let key = (index_oid, (blockno, offno));
+        if self.scores.contains_key(&key) || self.highlights.contains_key(&key) {
+            return key;
+        }
+
+        let roots = self
+            .hot_roots
+            .entry((index_oid, blockno))
+            .or_insert_with(|| unsafe { hot_chain_roots(index_oid, blockno) });
+        match roots.get((offno as usize).wrapping_sub(1)) {
+            Some(root) if *root != pg_sys::InvalidOffsetNumber => (index_oid, (blockno, *root)),
+            _ => key,
+        }
+    }
+
     pub fn lookup_index_for_first_field(
         &mut self,
         query_desc: *mut pg_sys::QueryDesc,
@@ -170,7 +251,20 @@ impl QueryState {
                     },
                     rtable,
                 );
-                let heap_oid = rentry.as_ref().unwrap().relid;
+                let mut heap_oid = rentry.as_ref().unwrap().relid;
+
+                if rentry.as_ref().unwrap().relkind
+                    == pg_sys::RELKIND_PARTITIONED_TABLE as std::os::raw::c_char
+                    && var.varno > 0
+                    && (var.varno as usize) <= PgList::<pg_sys::Node>::from_pg(rtable).len()
+                {
+                    // the Var was written against the partitioned table, but it's the partition
+                    // being scanned that has the index the ctid belongs to
+                    heap_oid = pg_sys::rt_fetch(var.varno as _, rtable)
+                        .as_ref()
+                        .unwrap()
+                        .relid;
+                }
 
                 if let Some(index_oid) = self.zdb_index_lookup.get(&heap_oid) {
                     return Some(*index_oid);
@@ -224,10 +318,36 @@ impl QueryState {
     }
 }
 
+/// The root offset of every HOT chain member on one page of the index's heap, indexed by offset - 1
+unsafe fn hot_chain_roots(
+    index_oid: pg_sys::Oid,
+    blockno: pg_sys::BlockNumber,
+) -> Vec<pg_sys::OffsetNumber> {
+    let heap_oid = pg_sys::IndexGetRelation(index_oid, false);
+    let heap = pg_sys::relation_open(heap_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE);
+    let mut root_offsets = vec![pg_sys::InvalidOffsetNumber; pg_sys::MaxOffsetNumber as usize];
+
+    let relkind = (*heap).rd_rel.as_ref().unwrap().relkind;
+    if (relkind == pg_sys::RELKIND_RELATION as std::os::raw::c_char
+        || relkind == pg_sys::RELKIND_MATVIEW as std::os::raw::c_char)
+        && blockno < pg_sys::RelationGetNumberOfBlocksInFork(heap, pg_sys::ForkNumber::MAIN_FORKNUM)
+    {
+        let buf = pg_sys::ReadBuffer(heap, blockno);
+        pg_sys::LockBuffer(buf, pg_sys::BUFFER_LOCK_SHARE as i32);
+        pg_sys::heap_get_root_tuples(pg_sys::BufferGetPage(buf), root_offsets.as_mut_ptr());
+        pg_sys::LockBuffer(buf, pg_sys::BUFFER_LOCK_UNLOCK as i32);
+        pg_sys::ReleaseBuffer(buf);
+    }
+
+    pg_sys::relation_close(heap, pg_sys::AccessShareLock as pg_sys::LOCKMODE);
+    root_offsets
+}
+
 pub struct ExecutorManager {
     bulk_requests: Option<HashMap<pg_sys::Oid, BulkContext>>,
     xids: Option<HashSet<pg_sys::TransactionId>>,
     query_stack: Option<Vec<(*mut pg_sys::QueryDesc, QueryState)>>,
+    prepared_gid: Option<String>,
     hooks_registered: bool,
 }
 
@@ -237,6 +357,7 @@ impl ExecutorManager {
             bulk_requests: None,
             xids: None,
             query_stack: None,
+            prepared_gid: None,
             hooks_registered: false,
         }
     }
@@ -267,7 +388,18 @@ impl ExecutorManager {
     }
 
     pub fn pop_query(&mut self) {
-        self.query_stack.as_mut().unwrap().pop();
+        let query_stack = self.query_stack.as_mut().unwrap();
+        query_stack.pop();
+
+        if query_stack.is_empty() {
+            // the top-level statement is finished, so any prior update still waiting for its
+            // `aminsert` came from a HOT update and won't ever get one
+            if let Some(bulk_requests) = self.bulk_requests.as_mut() {
+                for (_, bulk) in bulk_requests.iter_mut() {
+                    bulk.es_bulk_request.forget_prior_update();
+                }
+            }
+        }
     }
 
     pub fn push_xid(&mut self, xid: pg_sys::TransactionId) {
@@ -306,10 +438,33 @@ impl ExecutorManager {
     }
 
     pub fn commit(&mut self) {
+        // the UPDATEs and DELETEs of rows in a rollover index are made when its bulk request
+        // finishes, which needs to happen while our xids are still in progress
+        if self.bulk_requests.as_ref().is_some_and(|bulk_requests| {
+            bulk_requests
+                .values()
+                .any(|bulk| bulk.es_bulk_request.has_rollover_changes())
+        }) {
+            self.wait_for_completion();
+        }
+
         self.finalize_bulk_requests();
         self.cleanup();
     }
 
+    /// remember the global identifier of the transaction about to be prepared, but only if
+    /// it has made changes to any of our indices
+    pub fn set_prepared_gid(&mut self, gid: &str) {
+        if self.bulk_requests.is_some() {
+            self.prepared_gid.replace(gid.to_string());
+        }
+    }
+
+    pub fn prepare(&mut self) {
+        self.flush_prepared_bulk_requests();
+        self.cleanup();
+    }
+
     /// for use by code that needs to execute a search within a transaction
     pub fn wait_for_completion(&mut self) {
         if let Some(bulk_requests) = self.bulk_requests.take() {
@@ -323,7 +478,7 @@ impl ExecutorManager {
                 let elasticsearch = bulk.elasticsearch;
                 let attributes = bulk.attributes;
                 let natts = bulk.natts;
-                let dropped = bulk.dropped;
+                let skipped = bulk.skipped;
                 let tupdesc = bulk.tupdesc;
 
                 if let Err(e) = bulk.es_bulk_request.finish() {
@@ -338,7 +493,7 @@ impl ExecutorManager {
                         es_bulk_request,
                         attributes,
                         natts,
-                        dropped,
+                        skipped,
                         is_shadow: false,
                         tupdesc,
                     },
@@ -370,6 +525,31 @@ impl ExecutorManager {
         }
     }
 
+    fn flush_prepared_bulk_requests(&mut self) {
+        let gid = self
+            .prepared_gid
+            .take()
+            .expect("no global transaction identifier for PREPARE TRANSACTION");
+        let xids = self.used_xids();
+
+        if let Some(bulk_requests) = self.bulk_requests.take() {
+            // send everything we have to Elasticsearch, but leave our xids in progress.  They're
+            // only marked committed once the prepared transaction is, by whichever backend
+            // runs its COMMIT PREPARED
+            for (indexrelid, bulk) in bulk_requests
+                .into_iter()
+                .filter(|(_, bulk)| !bulk.is_shadow)
+            // shadow indexes don't do anything
+            {
+                if let Err(e) = bulk.es_bulk_request.finish() {
+                    panic!("{:?}", e);
+                }
+
+                prepared::record_prepared_transaction(&gid, indexrelid, &xids);
+            }
+        }
+    }
+
     fn terminate_bulk_requests(&mut self) {
         // forcefully terminate any of the bulk requests we have going on
         if let Some(bulk_requests) = self.bulk_requests.take() {
@@ -387,6 +567,7 @@ impl ExecutorManager {
         self.bulk_requests.take();
         self.xids.take();
         self.query_stack.take();
+        self.prepared_gid.take();
         self.hooks_registered = false;
     }
 
@@ -400,10 +581,7 @@ impl ExecutorManager {
         let xids = &self.xids;
         bulk_map.entry(indexrelid).or_insert_with(move || {
             let indexrel = unsafe { PgRelation::open(indexrelid) };
-            let is_shadow = ZDBIndexOptions::is_shadow_index_fast(&indexrel);
-            let elasticsearch = Elasticsearch::new(&indexrel);
-            let tupdesc = lookup_zdb_index_tupdesc(&indexrel); // this is allocated in TopTransactionContext, which as far as we're concerned is effectively the `'static` lifetime
-            let attributes = categorize_tupdesc(&tupdesc, &indexrel.heap_relation().unwrap(), None);
+            let mut bulk = BulkContext::new(&indexrel);
 
             if !get_executor_manager().hooks_registered {
                 // called when the top-level transaction commits
@@ -411,6 +589,11 @@ impl ExecutorManager {
                     get_executor_manager().commit()
                 });
 
+                // called when the top-level transaction is about to be prepared
+                register_xact_callback(PgXactCallbackEvent::PrePrepare, || {
+                    get_executor_manager().prepare()
+                });
+
                 // called when the top-level transaction aborts
                 register_xact_callback(PgXactCallbackEvent::Abort, || {
                     get_executor_manager().abort()
@@ -430,32 +613,48 @@ impl ExecutorManager {
                 get_executor_manager().hooks_registered = true;
             }
 
-            let mut es_bulk_request = elasticsearch.start_bulk();
-
             // only non-shadow indexes are written to
-            if !is_shadow {
+            if !bulk.is_shadow {
                 // mark xids that are already known to be in progress as
                 // also in progress for this new bulk context too
                 if let Some(xids) = xids.as_ref() {
                     for xid in xids {
-                        es_bulk_request
+                        bulk.es_bulk_request
                             .transaction_in_progress(*xid)
                             .expect("Failed to mark transaction as in progress for new bulk");
                     }
                 }
             }
 
-            BulkContext {
-                elasticsearch,
-                es_bulk_request,
-                attributes,
-                natts: tupdesc.natts as _,
-                dropped: (0..tupdesc.natts as usize)
-                    .map(|i| tupdesc.get(i).unwrap().is_dropped())
-                    .collect(),
-                is_shadow,
-                tupdesc,
-            }
+            bulk
         })
     }
 }
+
+impl BulkContext {
+    /// A new bulk context for the index, which isn't tied to the current transaction.  Parallel
+    /// build workers use these directly
+    pub fn new(indexrel: &PgRelation) -> Self {
+        let is_shadow = ZDBIndexOptions::is_shadow_index_fast(indexrel);
+        let elasticsearch = Elasticsearch::new(indexrel);
+        let tupdesc = lookup_zdb_index_tupdesc(indexrel); // this is allocated in TopTransactionContext, which as far as we're concerned is effectively the `'static` lifetime
+        let attributes = categorize_tupdesc(&tupdesc, indexrel, None);
+        let exclude_columns = ZDBIndexOptions::from_relation(indexrel).exclude_columns();
+        let es_bulk_request = elasticsearch.start_bulk();
+
+        BulkContext {
+            elasticsearch,
+            es_bulk_request,
+            attributes,
+            natts: tupdesc.natts as _,
+            skipped: (0..tupdesc.natts as usize)
+                .map(|i| {
+                    let attribute = tupdesc.get(i).unwrap();
+                    attribute.is_dropped() || exclude_columns.iter().any(|c| c == attribute.name())
+                })
+                .collect(),
+            is_shadow,
+            tupdesc,
+        }
+    }
+}
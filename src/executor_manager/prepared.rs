@@ -0,0 +1,164 @@
+use crate::elasticsearch::Elasticsearch;
+use pgrx::prelude::*;
+use pgrx::{spi, PgRelation};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Remember the xids a transaction being prepared used against an index, so that whichever
+/// backend runs its `COMMIT PREPARED` can mark them as committed in Elasticsearch.
+///
+/// The rows are written by the prepared transaction itself, so they only become visible once it
+/// commits.  A `ROLLBACK PREPARED` discards them, which leaves the xids in the index's aborted
+/// xids just like any other aborted transaction
+pub fn record_prepared_transaction(gid: &str, indexrelid: pg_sys::Oid, xids: &[u64]) {
+    as_table_owner(|| {
+        for xid in xids {
+            Spi::run_with_args(
+                "INSERT INTO zdb.prepared_transactions(gid, indexrelid, xid) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[gid.into(), indexrelid.into(), (*xid as i64).into()],
+            )
+            .expect("SPI failed");
+        }
+    })
+}
+
+/// Run `f` as the owner of `zdb.prepared_transactions`.  Nobody else can change it, as a prepared
+/// transaction whose rows went missing would never have its xids marked as committed
+fn as_table_owner<R, F: FnOnce() -> R>(f: F) -> R {
+    let owner = Spi::get_one::<pg_sys::Oid>(
+        "SELECT relowner FROM pg_class WHERE oid = 'zdb.prepared_transactions'::regclass",
+    )
+    .expect("SPI failed")
+    .expect("zdb.prepared_transactions has no owner");
+
+    let mut save_userid = pg_sys::InvalidOid;
+    let mut save_sec_context = 0;
+    unsafe {
+        pg_sys::GetUserIdAndSecContext(&mut save_userid, &mut save_sec_context);
+        pg_sys::SetUserIdAndSecContext(
+            owner,
+            save_sec_context | pg_sys::SECURITY_LOCAL_USERID_CHANGE as i32,
+        );
+    }
+
+    // an ERROR here restores the user when the transaction aborts
+    let result = f();
+
+    unsafe {
+        pg_sys::SetUserIdAndSecContext(save_userid, save_sec_context);
+    }
+
+    result
+}
+
+/// Mark the xids of committed prepared transactions as committed in the Elasticsearch indices they
+/// changed.  If `gid` is given then only that prepared transaction is finished.
+///
+/// Returns the number of prepared transactions that were finished
+pub fn finish_prepared_transactions(gid: Option<&str>) -> i64 {
+    // COMMIT PREPARED doesn't run with a snapshot, and we need one taken after the prepared
+    // transaction committed in order to see what it recorded
+    let pushed_snapshot = unsafe {
+        if !pg_sys::ActiveSnapshotSet() {
+            pg_sys::PushActiveSnapshot(pg_sys::GetTransactionSnapshot());
+            true
+        } else {
+            false
+        }
+    };
+
+    // COMMIT PREPARED can be run in a database where ZomboDB isn't installed
+    let installed =
+        Spi::get_one::<bool>("SELECT to_regclass('zdb.prepared_transactions') IS NOT NULL")
+            .expect("SPI failed")
+            .unwrap_or_default();
+    if !installed {
+        if pushed_snapshot {
+            unsafe { pg_sys::PopActiveSnapshot() };
+        }
+        return 0;
+    }
+
+    let mut committed = BTreeMap::<(String, pg_sys::Oid), Vec<u64>>::new();
+    let mut finished = BTreeSet::<String>::new();
+    as_table_owner(|| {
+        Spi::connect(|client| {
+            let table = client.select(
+                "SELECT p.gid, p.indexrelid, p.xid, txid_status(p.xid), c.oid IS NOT NULL
+                   FROM zdb.prepared_transactions p
+              LEFT JOIN pg_class c ON c.oid = p.indexrelid
+                  WHERE ($1 IS NULL OR p.gid = $1)
+                    AND p.gid NOT IN (SELECT gid FROM pg_prepared_xacts)",
+                None,
+                &[gid.into()],
+            )?;
+
+            for row in table {
+                let gid = row.get::<String>(1)?.expect("gid is NULL");
+                let indexrelid = row.get::<pg_sys::Oid>(2)?.expect("indexrelid is NULL");
+                let xid = row.get::<i64>(3)?.expect("xid is NULL") as u64;
+                let status = row.get::<String>(4)?;
+                let index_exists = row.get::<bool>(5)?.unwrap_or_default();
+
+                match status.as_deref() {
+                    Some("committed") if index_exists => committed
+                        .entry((gid.clone(), indexrelid))
+                        .or_default()
+                        .push(xid),
+                    Some("in progress") => continue,
+                    _ => {}
+                }
+                finished.insert(gid);
+            }
+            Ok::<_, spi::Error>(())
+        })
+        .expect("SPI failed")
+    });
+
+    // this runs after COMMIT PREPARED, when the transaction can no longer be rolled back, so
+    // failures are only warned about.  The prepared transaction's rows stay behind for
+    // `zdb.finish_prepared_transactions()` to try again
+    for ((gid, indexrelid), xids) in committed {
+        let index = unsafe {
+            PgRelation::with_lock(indexrelid, pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+        };
+        let mut bulk = Elasticsearch::new(&index).start_bulk();
+        let result = xids
+            .into_iter()
+            .try_for_each(|xid| bulk.transaction_committed_xid64(xid))
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|_| bulk.finish().map(|_| ()).map_err(|e| format!("{:?}", e)));
+
+        if let Err(e) = result {
+            warning!(
+                "failed to mark prepared transaction '{}' as committed in index {}, run zdb.finish_prepared_transactions() to try again: {}",
+                gid,
+                index.name(),
+                e
+            );
+            finished.remove(&gid);
+        }
+    }
+
+    as_table_owner(|| {
+        for gid in &finished {
+            Spi::run_with_args(
+                "DELETE FROM zdb.prepared_transactions WHERE gid = $1",
+                &[gid.as_str().into()],
+            )
+            .expect("SPI failed");
+        }
+    });
+
+    if pushed_snapshot {
+        unsafe { pg_sys::PopActiveSnapshot() };
+    }
+
+    finished.len() as i64
+}
+
+/// Finish prepared transactions whose `COMMIT PREPARED` was run by a backend that hadn't loaded
+/// ZomboDB, and so couldn't mark them as committed in Elasticsearch
+#[pg_extern(name = "finish_prepared_transactions", volatile, parallel_safe)]
+fn finish_all_prepared_transactions() -> i64 {
+    finish_prepared_transactions(None)
+}
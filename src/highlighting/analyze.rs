@@ -1,6 +1,17 @@
+use crate::mapping::{lookup_analysis_thing, lookup_filters};
 use cow_utils::*;
+use once_cell::sync::Lazy;
+use pgrx::prelude::*;
+use regex::Regex;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde_json::Value;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
+use std::ops::Range;
+use std::rc::Rc;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::{UnicodeSegmentation, UnicodeWordIndices};
 
 pub struct AnalyzedToken<'a> {
@@ -88,6 +99,646 @@ impl<'a> Iterator for FulltextWithShingles<'a> {
     }
 }
 
+/// Lucene's `EnglishAnalyzer.ENGLISH_STOP_WORDS_SET`, which is what Elasticsearch uses for `_english_`
+const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// An Elasticsearch tokenizer we know how to evaluate locally
+pub enum Tokenizer {
+    Standard,
+    Whitespace,
+    Keyword,
+    Letter,
+    Pattern { regex: Regex, group: i64 },
+}
+
+/// An Elasticsearch token filter we know how to evaluate locally
+pub enum TokenFilter {
+    Lowercase,
+    Uppercase,
+    AsciiFolding {
+        preserve_original: bool,
+    },
+    Stop {
+        words: HashSet<String>,
+        ignore_case: bool,
+    },
+    Stemmer(Stemmer),
+    Truncate(usize),
+    Trim,
+    Shingle {
+        separator: String,
+        output_unigrams: bool,
+        output_unigrams_if_no_shingles: bool,
+    },
+}
+
+/// A tokenizer followed by a chain of token filters, assembled from the definitions in
+/// `zdb.analyzers`, `zdb.normalizers`, `zdb.tokenizers` and `zdb.filters` (or from the
+/// Elasticsearch built-ins of the same name)
+pub struct LocalAnalyzer {
+    tokenizer: Tokenizer,
+    filters: Vec<TokenFilter>,
+}
+
+impl LocalAnalyzer {
+    pub fn analyze<'a>(&self, input: &'a str) -> Vec<AnalyzedToken<'a>> {
+        self.filters
+            .iter()
+            .fold(self.tokenizer.tokenize(input), |tokens, filter| {
+                filter.apply(tokens)
+            })
+    }
+}
+
+impl Tokenizer {
+    fn tokenize<'a>(&self, input: &'a str) -> Vec<AnalyzedToken<'a>> {
+        match self {
+            Tokenizer::Standard => Utf16WordIndices::new(input)
+                .enumerate()
+                .map(|(position, (range, token))| AnalyzedToken {
+                    token: Cow::Borrowed(token),
+                    position,
+                    start: range.start,
+                    end: range.end,
+                    type_: "<ALPHANUM>",
+                })
+                .collect(),
+            Tokenizer::Whitespace => {
+                utf16_tokens(input, split_ranges(input, char::is_whitespace), "word")
+            }
+            Tokenizer::Letter => {
+                utf16_tokens(input, split_ranges(input, |c| !c.is_alphabetic()), "word")
+            }
+            Tokenizer::Keyword => utf16_tokens(input, vec![0..input.len()], "word"),
+            Tokenizer::Pattern { regex, group } if *group < 0 => {
+                // the pattern matches the token separators
+                let mut ranges = Vec::new();
+                let mut last_end = 0;
+                for m in regex.find_iter(input) {
+                    ranges.push(last_end..m.start());
+                    last_end = m.end();
+                }
+                ranges.push(last_end..input.len());
+                utf16_tokens(input, ranges, "word")
+            }
+            Tokenizer::Pattern { regex, group } => utf16_tokens(
+                input,
+                regex
+                    .captures_iter(input)
+                    .filter_map(|captures| captures.get(*group as usize))
+                    .map(|m| m.range())
+                    .collect(),
+                "word",
+            ),
+        }
+    }
+}
+
+impl TokenFilter {
+    fn apply<'a>(&self, tokens: Vec<AnalyzedToken<'a>>) -> Vec<AnalyzedToken<'a>> {
+        match self {
+            TokenFilter::Lowercase => map_tokens(tokens, |s| s.cow_to_lowercase()),
+            TokenFilter::Uppercase => map_tokens(tokens, |s| s.cow_to_uppercase()),
+            TokenFilter::Trim => map_tokens(tokens, |s| Cow::Borrowed(s.trim())),
+            TokenFilter::AsciiFolding { preserve_original } => {
+                let mut folded = Vec::with_capacity(tokens.len());
+                for mut token in tokens {
+                    let folding = ascii_fold(&token.token);
+                    if folding.is_some() && *preserve_original {
+                        folded.push(AnalyzedToken {
+                            token: token.token.clone(),
+                            ..token
+                        });
+                    }
+                    if let Some(folding) = folding {
+                        token.token = Cow::Owned(folding);
+                    }
+                    folded.push(token);
+                }
+                folded
+            }
+            TokenFilter::Stop { words, ignore_case } => tokens
+                .into_iter()
+                .filter(|token| {
+                    if *ignore_case {
+                        !words.contains(&*token.token.cow_to_lowercase())
+                    } else {
+                        !words.contains(&*token.token)
+                    }
+                })
+                .collect(),
+            TokenFilter::Stemmer(stemmer) => tokens
+                .into_iter()
+                .map(|mut token| {
+                    token.token = match token.token {
+                        Cow::Borrowed(s) => stemmer.stem(s),
+                        Cow::Owned(s) => Cow::Owned(stemmer.stem(&s).into_owned()),
+                    };
+                    token
+                })
+                .collect(),
+            TokenFilter::Truncate(length) => tokens
+                .into_iter()
+                .map(|mut token| {
+                    if let Some((idx, _)) = token.token.char_indices().nth(*length) {
+                        token.token = match token.token {
+                            Cow::Borrowed(s) => Cow::Borrowed(&s[..idx]),
+                            Cow::Owned(mut s) => {
+                                s.truncate(idx);
+                                Cow::Owned(s)
+                            }
+                        };
+                    }
+                    token
+                })
+                .collect(),
+            TokenFilter::Shingle {
+                separator,
+                output_unigrams,
+                output_unigrams_if_no_shingles,
+            } => {
+                if tokens.len() < 2 {
+                    return if *output_unigrams || *output_unigrams_if_no_shingles {
+                        tokens
+                    } else {
+                        vec![]
+                    };
+                }
+
+                let mut shingled = Vec::with_capacity(tokens.len() * 2);
+                let mut iter = tokens.into_iter().peekable();
+                while let Some(this) = iter.next() {
+                    let shingle = iter.peek().map(|next| AnalyzedToken {
+                        token: Cow::Owned(format!("{}{}{}", this.token, separator, next.token)),
+                        position: this.position,
+                        start: this.start,
+                        end: next.end,
+                        type_: "shingle",
+                    });
+
+                    if *output_unigrams {
+                        shingled.push(this);
+                    }
+                    if let Some(shingle) = shingle {
+                        shingled.push(shingle);
+                    }
+                }
+                shingled
+            }
+        }
+    }
+}
+
+#[inline]
+fn map_tokens<'a>(
+    tokens: Vec<AnalyzedToken<'a>>,
+    f: for<'b> fn(&'b str) -> Cow<'b, str>,
+) -> Vec<AnalyzedToken<'a>> {
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            token.token = match token.token {
+                Cow::Borrowed(s) => f(s),
+                Cow::Owned(s) => Cow::Owned(f(&s).into_owned()),
+            };
+            token
+        })
+        .collect()
+}
+
+/// Folds `input` down to its ASCII equivalent, the same idea as Lucene's `ASCIIFoldingFilter`.
+///
+/// Returns `None` if the input doesn't need folding
+fn ascii_fold(input: &str) -> Option<String> {
+    if input.is_ascii() {
+        return None;
+    }
+
+    let mut folded = String::with_capacity(input.len());
+    for c in input.nfd().filter(|c| !is_combining_mark(*c)) {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'Æ' => folded.push_str("AE"),
+            'œ' => folded.push_str("oe"),
+            'Œ' => folded.push_str("OE"),
+            'ø' => folded.push('o'),
+            'Ø' => folded.push('O'),
+            'đ' | 'ð' => folded.push('d'),
+            'Đ' | 'Ð' => folded.push('D'),
+            'ł' => folded.push('l'),
+            'Ł' => folded.push('L'),
+            'þ' => folded.push_str("th"),
+            'Þ' => folded.push_str("TH"),
+            'ı' => folded.push('i'),
+            other => folded.push(other),
+        }
+    }
+
+    if folded == input {
+        None
+    } else {
+        Some(folded)
+    }
+}
+
+/// Byte ranges of the runs of characters in `input` that aren't separators
+fn split_ranges(input: &str, is_separator: impl Fn(char) -> bool) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (idx, c) in input.char_indices() {
+        match (is_separator(c), start) {
+            (true, Some(begin)) => {
+                ranges.push(begin..idx);
+                start = None;
+            }
+            (false, None) => start = Some(idx),
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        ranges.push(begin..input.len());
+    }
+    ranges
+}
+
+/// Turns ascending byte ranges of `input` into tokens whose offsets are UTF16 character offsets,
+/// which is how Elasticsearch reports them.  Empty ranges are discarded
+fn utf16_tokens<'a>(
+    input: &'a str,
+    ranges: Vec<Range<usize>>,
+    type_: &'static str,
+) -> Vec<AnalyzedToken<'a>> {
+    let mut last_byte = 0;
+    let mut last_utf16 = 0;
+    ranges
+        .into_iter()
+        .filter(|range| !range.is_empty())
+        .enumerate()
+        .map(|(position, range)| {
+            last_utf16 += input[last_byte..range.start].encode_utf16().count();
+            let start = last_utf16;
+            last_utf16 += input[range.clone()].encode_utf16().count();
+            last_byte = range.end;
+            AnalyzedToken {
+                token: Cow::Borrowed(&input[range]),
+                position,
+                start,
+                end: last_utf16,
+                type_,
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct AnalysisCache {
+    definitions: Option<AnalysisDefinitions>,
+    analyzers: HashMap<String, Option<Rc<LocalAnalyzer>>>,
+    normalizers: HashMap<String, Option<Rc<LocalAnalyzer>>>,
+}
+
+static mut ANALYSIS_CACHE: Lazy<AnalysisCache> = Lazy::new(Default::default);
+
+fn analysis_cache() -> &'static mut AnalysisCache {
+    unsafe {
+        if ANALYSIS_CACHE.definitions.is_none() {
+            // the definitions can change between transactions
+            register_xact_callback(PgXactCallbackEvent::Abort, || {
+                *ANALYSIS_CACHE = Default::default();
+            });
+            register_xact_callback(PgXactCallbackEvent::Commit, || {
+                *ANALYSIS_CACHE = Default::default();
+            });
+            ANALYSIS_CACHE.definitions = Some(AnalysisDefinitions::load());
+        }
+        &mut ANALYSIS_CACHE
+    }
+}
+
+/// Returns a [`LocalAnalyzer`] for the named analyzer, or `None` if any of its components can
+/// only be evaluated by Elasticsearch
+pub fn lookup_local_analyzer(name: &str) -> Option<Rc<LocalAnalyzer>> {
+    let cache = analysis_cache();
+    let definitions = cache.definitions.as_ref().unwrap();
+    cache
+        .analyzers
+        .entry(name.to_string())
+        .or_insert_with(|| definitions.analyzer(name).map(Rc::new))
+        .clone()
+}
+
+/// Returns a [`LocalAnalyzer`] for the named normalizer, or `None` if any of its components can
+/// only be evaluated by Elasticsearch
+pub fn lookup_local_normalizer(name: &str) -> Option<Rc<LocalAnalyzer>> {
+    let cache = analysis_cache();
+    let definitions = cache.definitions.as_ref().unwrap();
+    cache
+        .normalizers
+        .entry(name.to_string())
+        .or_insert_with(|| definitions.normalizer(name).map(Rc::new))
+        .clone()
+}
+
+struct AnalysisDefinitions {
+    analyzers: Value,
+    normalizers: Value,
+    tokenizers: Value,
+    filters: Value,
+}
+
+impl AnalysisDefinitions {
+    fn load() -> Self {
+        AnalysisDefinitions {
+            analyzers: lookup_analysis_thing("analyzers"),
+            normalizers: lookup_analysis_thing("normalizers"),
+            tokenizers: lookup_analysis_thing("tokenizers"),
+            filters: lookup_filters(),
+        }
+    }
+
+    fn analyzer(&self, name: &str) -> Option<LocalAnalyzer> {
+        match self.analyzers.get(name) {
+            Some(definition) => self.analyzer_from_definition(definition),
+            None => self.analyzer_from_definition(&serde_json::json!({ "type": name })),
+        }
+    }
+
+    fn normalizer(&self, name: &str) -> Option<LocalAnalyzer> {
+        let definition = match self.normalizers.get(name) {
+            Some(definition) => definition.clone(),
+            None if name == "lowercase" => serde_json::json!({ "filter": ["lowercase"] }),
+            None => return None,
+        };
+
+        if has_char_filters(&definition) {
+            return None;
+        }
+
+        Some(LocalAnalyzer {
+            tokenizer: Tokenizer::Keyword,
+            filters: self.filter_chain(definition.get("filter"))?,
+        })
+    }
+
+    fn analyzer_from_definition(&self, definition: &Value) -> Option<LocalAnalyzer> {
+        // char filters can move offsets around, and we don't track that
+        if has_char_filters(definition) {
+            return None;
+        }
+
+        let stopwords = definition.get("stopwords");
+        let (tokenizer, mut filters) = match definition.get("type").and_then(Value::as_str) {
+            Some("standard") => (Tokenizer::Standard, vec![TokenFilter::Lowercase]),
+            Some("simple") => (Tokenizer::Letter, vec![TokenFilter::Lowercase]),
+            Some("whitespace") => (Tokenizer::Whitespace, vec![]),
+            Some("keyword") => (Tokenizer::Keyword, vec![]),
+            Some("stop") => (
+                Tokenizer::Letter,
+                vec![
+                    TokenFilter::Lowercase,
+                    stop_filter(stopwords.unwrap_or(&Value::from("_english_")), false)?,
+                ],
+            ),
+            Some("pattern") => (
+                pattern_tokenizer(definition)?,
+                if definition.get("lowercase").and_then(Value::as_bool) == Some(false) {
+                    vec![]
+                } else {
+                    vec![TokenFilter::Lowercase]
+                },
+            ),
+            Some("custom") | None => (
+                self.tokenizer(definition.get("tokenizer")?)?,
+                self.filter_chain(definition.get("filter"))?,
+            ),
+            Some(_) => return None,
+        };
+
+        if matches!(
+            definition.get("type").and_then(Value::as_str),
+            Some("standard") | Some("pattern")
+        ) {
+            if let Some(stopwords) = stopwords {
+                filters.push(stop_filter(stopwords, false)?);
+            }
+        }
+
+        Some(LocalAnalyzer { tokenizer, filters })
+    }
+
+    fn tokenizer(&self, tokenizer: &Value) -> Option<Tokenizer> {
+        let definition = match tokenizer {
+            Value::String(name) => match self.tokenizers.get(name) {
+                Some(definition) => definition.clone(),
+                None => serde_json::json!({ "type": name }),
+            },
+            Value::Object(_) => tokenizer.clone(),
+            _ => return None,
+        };
+
+        match definition.get("type").and_then(Value::as_str)? {
+            "standard" => Some(Tokenizer::Standard),
+            "whitespace" => Some(Tokenizer::Whitespace),
+            "keyword" => Some(Tokenizer::Keyword),
+            "letter" => Some(Tokenizer::Letter),
+            "pattern" => pattern_tokenizer(&definition),
+            _ => None,
+        }
+    }
+
+    fn filter_chain(&self, filters: Option<&Value>) -> Option<Vec<TokenFilter>> {
+        match filters {
+            None => Some(vec![]),
+            Some(Value::Array(filters)) => filters.iter().map(|f| self.filter(f)).collect(),
+            Some(other) => Some(vec![self.filter(other)?]),
+        }
+    }
+
+    fn filter(&self, filter: &Value) -> Option<TokenFilter> {
+        let definition = match filter {
+            Value::String(name) => match self.filters.get(name) {
+                Some(definition) => definition.clone(),
+                None => serde_json::json!({ "type": name }),
+            },
+            Value::Object(_) => filter.clone(),
+            _ => return None,
+        };
+
+        match definition.get("type").and_then(Value::as_str)? {
+            "lowercase" if definition.get("language").is_none() => Some(TokenFilter::Lowercase),
+            "uppercase" => Some(TokenFilter::Uppercase),
+            "trim" => Some(TokenFilter::Trim),
+            "asciifolding" => Some(TokenFilter::AsciiFolding {
+                preserve_original: definition
+                    .get("preserve_original")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            }),
+            "stop" => stop_filter(
+                definition
+                    .get("stopwords")
+                    .unwrap_or(&Value::from("_english_")),
+                definition
+                    .get("ignore_case")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            ),
+            "snowball" => snowball_algorithm(
+                definition
+                    .get("language")
+                    .and_then(Value::as_str)
+                    .unwrap_or("English"),
+            )
+            .map(|algorithm| TokenFilter::Stemmer(Stemmer::create(algorithm))),
+            "stemmer" => stemmer_algorithm(
+                definition
+                    .get("language")
+                    .or_else(|| definition.get("name"))
+                    .and_then(Value::as_str)
+                    .unwrap_or("english"),
+            )
+            .map(|algorithm| TokenFilter::Stemmer(Stemmer::create(algorithm))),
+            "truncate" => Some(TokenFilter::Truncate(
+                definition
+                    .get("length")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(10) as usize,
+            )),
+            "shingle" => {
+                let min = definition
+                    .get("min_shingle_size")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(2);
+                let max = definition
+                    .get("max_shingle_size")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(2);
+
+                // we only know how to make bigrams
+                if min != 2 || max != 2 {
+                    return None;
+                }
+
+                Some(TokenFilter::Shingle {
+                    separator: definition
+                        .get("token_separator")
+                        .and_then(Value::as_str)
+                        .unwrap_or(" ")
+                        .to_string(),
+                    output_unigrams: definition
+                        .get("output_unigrams")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(true),
+                    output_unigrams_if_no_shingles: definition
+                        .get("output_unigrams_if_no_shingles")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn has_char_filters(definition: &Value) -> bool {
+    match definition.get("char_filter") {
+        Some(Value::Array(char_filters)) => !char_filters.is_empty(),
+        Some(Value::Null) | None => false,
+        Some(_) => true,
+    }
+}
+
+fn pattern_tokenizer(definition: &Value) -> Option<Tokenizer> {
+    if matches!(definition.get("flags").and_then(Value::as_str), Some(flags) if !flags.is_empty()) {
+        return None;
+    }
+
+    // Java regex syntax is mostly, but not entirely, compatible with ours.  If it doesn't compile
+    // then Elasticsearch will have to do the work
+    let regex = Regex::new(
+        definition
+            .get("pattern")
+            .and_then(Value::as_str)
+            .unwrap_or(r"\W+"),
+    )
+    .ok()?;
+    let group = definition
+        .get("group")
+        .and_then(Value::as_i64)
+        .unwrap_or(-1);
+
+    Some(Tokenizer::Pattern { regex, group })
+}
+
+fn stop_filter(stopwords: &Value, ignore_case: bool) -> Option<TokenFilter> {
+    let words = match stopwords {
+        Value::String(s) if s == "_english_" => {
+            ENGLISH_STOP_WORDS.iter().map(|s| s.to_string()).collect()
+        }
+        Value::String(s) if s == "_none_" => HashSet::new(),
+        Value::Array(words) => words
+            .iter()
+            .map(|word| word.as_str().map(|word| word.to_string()))
+            .collect::<Option<HashSet<_>>>()?,
+        // other languages and `stopwords_path`
+        _ => return None,
+    };
+
+    let words = if ignore_case {
+        words.into_iter().map(|word| word.to_lowercase()).collect()
+    } else {
+        words
+    };
+
+    Some(TokenFilter::Stop { words, ignore_case })
+}
+
+/// Maps the `language` of a `snowball` filter to its Snowball algorithm
+fn snowball_algorithm(language: &str) -> Option<Algorithm> {
+    match language {
+        "English" => Some(Algorithm::English),
+        "Danish" => Some(Algorithm::Danish),
+        "Dutch" => Some(Algorithm::Dutch),
+        "Finnish" => Some(Algorithm::Finnish),
+        "French" => Some(Algorithm::French),
+        "German" => Some(Algorithm::German),
+        "Hungarian" => Some(Algorithm::Hungarian),
+        "Italian" => Some(Algorithm::Italian),
+        "Norwegian" => Some(Algorithm::Norwegian),
+        "Portuguese" => Some(Algorithm::Portuguese),
+        "Romanian" => Some(Algorithm::Romanian),
+        "Russian" => Some(Algorithm::Russian),
+        "Spanish" => Some(Algorithm::Spanish),
+        "Swedish" => Some(Algorithm::Swedish),
+        "Turkish" => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
+/// Maps the `language` of a `stemmer` filter to a Snowball algorithm, but only for those
+/// languages where Elasticsearch itself uses the Snowball stemmer.  Notably, `english` is the
+/// original Porter stemmer, not Snowball's
+fn stemmer_algorithm(language: &str) -> Option<Algorithm> {
+    match language {
+        "porter2" => Some(Algorithm::English),
+        "danish" => Some(Algorithm::Danish),
+        "dutch" => Some(Algorithm::Dutch),
+        "finnish" => Some(Algorithm::Finnish),
+        "hungarian" => Some(Algorithm::Hungarian),
+        "norwegian" => Some(Algorithm::Norwegian),
+        "romanian" => Some(Algorithm::Romanian),
+        "russian" => Some(Algorithm::Russian),
+        "swedish" => Some(Algorithm::Swedish),
+        "turkish" => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
 /// Returns character-offsets of words in UTF16 encoded text.
 ///
 /// That is, if you have a UTF16-encoded Java `char[]` array, then these
@@ -140,3 +791,102 @@ impl<'a> Iterator for Utf16WordIndices<'a> {
 //         })
 //         .collect()
 // }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::highlighting::analyze::{lookup_local_analyzer, lookup_local_normalizer};
+    use pgrx::*;
+
+    fn analyze(analyzer: &str, input: &str) -> Vec<(String, usize, usize, usize)> {
+        lookup_local_analyzer(analyzer)
+            .expect("analyzer isn't supported locally")
+            .analyze(input)
+            .into_iter()
+            .map(|token| {
+                (
+                    token.token.to_string(),
+                    token.position,
+                    token.start,
+                    token.end,
+                )
+            })
+            .collect()
+    }
+
+    #[pg_test]
+    fn test_local_whitespace_analyzer() {
+        assert_eq!(
+            analyze("whitespace", "Déjà  vu\tAgain"),
+            vec![
+                ("Déjà".to_string(), 0, 0, 4),
+                ("vu".to_string(), 1, 6, 8),
+                ("Again".to_string(), 2, 9, 14),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_local_pattern_analyzer() {
+        assert_eq!(
+            analyze("pattern", "Foo-Bar,baz"),
+            vec![
+                ("foo".to_string(), 0, 0, 3),
+                ("bar".to_string(), 1, 4, 7),
+                ("baz".to_string(), 2, 8, 11),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_local_custom_analyzer() -> spi::Result<()> {
+        Spi::run(
+            r#"SELECT zdb.define_filter('test_local_stemmer', '{"type": "snowball", "language": "English"}');"#,
+        )?;
+        Spi::run(
+            r#"SELECT zdb.define_analyzer('test_local_custom', '{
+                "type": "custom",
+                "tokenizer": "whitespace",
+                "filter": ["lowercase", "asciifolding", "stop", "test_local_stemmer"]
+            }');"#,
+        )?;
+
+        assert_eq!(
+            analyze("test_local_custom", "The Café Runners were running"),
+            vec![
+                ("cafe".to_string(), 1, 4, 8),
+                ("runner".to_string(), 2, 9, 16),
+                ("were".to_string(), 3, 17, 21),
+                ("run".to_string(), 4, 22, 29),
+            ]
+        );
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_local_analyzer_with_char_filter_is_unsupported() -> spi::Result<()> {
+        Spi::run(
+            r#"SELECT zdb.define_analyzer('test_local_char_filter', '{
+                "type": "custom",
+                "tokenizer": "standard",
+                "char_filter": ["html_strip"]
+            }');"#,
+        )?;
+
+        assert!(lookup_local_analyzer("test_local_char_filter").is_none());
+        assert!(lookup_local_analyzer("english").is_none());
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_local_lowercase_normalizer() {
+        let tokens = lookup_local_normalizer("lowercase")
+            .expect("normalizer isn't supported locally")
+            .analyze("Hello World")
+            .into_iter()
+            .map(|token| (token.token.to_string(), token.start, token.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens, vec![("hello world".to_string(), 0, 11)]);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::elasticsearch::analyze::Token;
 use crate::elasticsearch::Elasticsearch;
-use crate::highlighting::analyze::AnalyzedToken;
+use crate::executor_manager::get_executor_manager;
+use crate::highlighting::analyze::{lookup_local_analyzer, lookup_local_normalizer, AnalyzedToken};
 use crate::utils::{get_highlight_analysis_info, has_date_subfield};
 use crate::zql::ast::{IndexLink, ProximityPart, ProximityTerm, QualifiedField, Term};
 use levenshtein::*;
@@ -39,6 +41,10 @@ pub struct DocumentHighlighter<'a> {
     data_type: Option<DataType>,
     field: String,
     index_oid: pg_sys::Oid,
+
+    /// (field, array_index, text) values that couldn't be analyzed locally and are
+    /// waiting on a batched `_analyze` request to Elasticsearch
+    pending: Vec<(String, u32, &'a str)>,
 }
 
 #[rustfmt::skip]
@@ -118,7 +124,8 @@ pub type HighlightCollection<'a> = Vec<((QualifiedField, String), HighlightMatch
 impl<'a> DocumentHighlighter<'a> {
     pub fn new(index: &PgRelation, field: &str, value: &'a Value) -> Self {
         if let Value::String(_) = value {
-            let (field_type, _, index_analyzer) = get_highlight_analysis_info(index, field);
+            let (field_type, normalizer, index_analyzer) =
+                get_highlight_analysis_info(index, field);
             let is_date = has_date_subfield(index, field);
             DocumentHighlighter::from_json(
                 index,
@@ -128,6 +135,7 @@ impl<'a> DocumentHighlighter<'a> {
                 &field_type,
                 is_date,
                 &index_analyzer,
+                &normalizer,
             )
             .into_iter()
             .next()
@@ -146,6 +154,31 @@ impl<'a> DocumentHighlighter<'a> {
         field_type: &Option<String>,
         is_date: bool,
         index_analyzer: &Option<String>,
+        normalizer: &Option<String>,
+    ) -> HashMap<(String, u32), Self> {
+        let mut highlighters = DocumentHighlighter::collect_highlighters(
+            index,
+            field,
+            value,
+            array_index,
+            field_type,
+            is_date,
+            index_analyzer,
+            normalizer,
+        );
+        DocumentHighlighter::analyze_pending(index, &mut highlighters);
+        highlighters
+    }
+
+    fn collect_highlighters(
+        index: &PgRelation,
+        field: &str,
+        value: &'a Value,
+        array_index: u32,
+        field_type: &Option<String>,
+        is_date: bool,
+        index_analyzer: &Option<String>,
+        normalizer: &Option<String>,
     ) -> HashMap<(String, u32), Self> {
         match value {
             Value::Object(o) => {
@@ -154,7 +187,7 @@ impl<'a> DocumentHighlighter<'a> {
                 let mut highlighters = HashMap::new();
 
                 o.iter().for_each(|(k, v)| {
-                    highlighters.extend(DocumentHighlighter::from_json(
+                    highlighters.extend(DocumentHighlighter::collect_highlighters(
                         index,
                         &format!("{}.{}", field, k),
                         v,
@@ -162,6 +195,7 @@ impl<'a> DocumentHighlighter<'a> {
                         field_type,
                         is_date,
                         index_analyzer,
+                        normalizer,
                     ))
                 });
 
@@ -174,7 +208,7 @@ impl<'a> DocumentHighlighter<'a> {
                 let mut highlighters = HashMap::new();
 
                 a.iter().enumerate().for_each(|(i, v)| {
-                    highlighters.extend(DocumentHighlighter::from_json(
+                    highlighters.extend(DocumentHighlighter::collect_highlighters(
                         index,
                         field,
                         v,
@@ -182,6 +216,7 @@ impl<'a> DocumentHighlighter<'a> {
                         field_type,
                         is_date,
                         index_analyzer,
+                        normalizer,
                     ))
                 });
 
@@ -194,16 +229,17 @@ impl<'a> DocumentHighlighter<'a> {
                     data_type: None,
                     field: field.into(),
                     index_oid: index.oid(),
+                    pending: Default::default(),
                 };
 
                 result.analyze_document(
-                    index,
                     field,
                     value,
                     array_index,
                     field_type,
                     is_date,
                     index_analyzer,
+                    normalizer,
                 );
 
                 let mut highlighters = HashMap::new();
@@ -213,15 +249,97 @@ impl<'a> DocumentHighlighter<'a> {
         }
     }
 
+    /// Analyzes every text value that [`DocumentHighlighter::analyze_document`] couldn't analyze
+    /// locally, using one `_analyze` request per distinct field for the texts the current query
+    /// hasn't already analyzed
+    fn analyze_pending(index: &PgRelation, highlighters: &mut HashMap<(String, u32), Self>) {
+        let mut by_field = BTreeMap::<String, Vec<((String, u32), u32, &'a str)>>::new();
+        for (key, highlighter) in highlighters.iter_mut() {
+            for (field, array_index, text) in highlighter.pending.drain(..) {
+                by_field
+                    .entry(field)
+                    .or_default()
+                    .push((key.clone(), array_index, text));
+            }
+        }
+
+        if by_field.is_empty() {
+            return;
+        }
+
+        for (field, pending) in by_field {
+            // texts this query has already analyzed for another row don't need to be sent again
+            let mut analyzed = pending
+                .iter()
+                .map(|(_, _, text)| {
+                    get_executor_manager()
+                        .peek_query_state()
+                        .and_then(|(_, state)| state.get_analyzed_text(index.oid(), &field, text))
+                        .cloned()
+                })
+                .collect::<Vec<_>>();
+            let texts = pending
+                .iter()
+                .zip(analyzed.iter())
+                .filter(|(_, tokens)| tokens.is_none())
+                .map(|((_, _, text), _)| *text)
+                .collect::<Vec<_>>();
+
+            if !texts.is_empty() {
+                let tokens = Elasticsearch::new(index)
+                    .analyze_with_field_texts(&field, &texts)
+                    .execute()
+                    .expect("failed to analyze text for highlighting")
+                    .tokens;
+
+                let mut groups = texts.iter().zip(split_analyzed_texts(&texts, tokens));
+                for slot in analyzed.iter_mut().filter(|tokens| tokens.is_none()) {
+                    let (text, tokens) = groups.next().expect("missing analyzed text");
+                    if let Some((_, state)) = get_executor_manager().peek_query_state() {
+                        state.add_analyzed_text(index.oid(), &field, text, tokens.clone());
+                    }
+                    slot.replace(tokens);
+                }
+            }
+
+            for ((key, array_index, _), tokens) in pending.into_iter().zip(analyzed) {
+                let tokens = tokens.expect("text was not analyzed");
+                let highlighter = highlighters
+                    .get_mut(&key)
+                    .expect("no highlighter for pending text");
+
+                for token in tokens {
+                    pgrx::check_for_interrupts!();
+                    highlighter
+                        .lookup
+                        .entry(Cow::Owned(token.token))
+                        .or_default()
+                        .push(TokenEntry {
+                            type_: Cow::Owned(token.type_),
+                            array_index,
+                            position: (<i32 as TryInto<u32>>::try_into(token.position)
+                                .expect("position overflowed"))
+                                + 1,
+                            start_offset: token
+                                .start_offset
+                                .try_into()
+                                .expect("start_offset overflowed"),
+                            end_offset: token.end_offset.try_into().expect("end_offset overflowed"),
+                        });
+                }
+            }
+        }
+    }
+
     fn analyze_document(
         &mut self,
-        index: &PgRelation,
         field: &str,
         value: &'a Value,
         array_index: u32,
         field_type: &Option<String>,
         is_date: bool,
         analyzer: &Option<String>,
+        normalizer: &Option<String>,
     ) {
         match value {
             Value::Bool(b) => {
@@ -310,104 +428,113 @@ impl<'a> DocumentHighlighter<'a> {
                     )
                 }
 
-                let results: Box<dyn Iterator<Item = (Cow<str>, TokenEntry)>> =
+                let results: Option<Box<dyn Iterator<Item = (Cow<str>, TokenEntry)>>> =
                     match (analyzer, field_type) {
-                        (None, None) => Box::new(
+                        (None, None) => Some(Box::new(
                             crate::highlighting::analyze::standard(s)
                                 .map(|x| map_analyzed_token(array_index, x)),
-                        ),
+                        )),
 
                         (Some(analyzer), _)
                             if analyzer == "standard"
                                 || analyzer == "zdb_standard"
                                 || analyzer == "fulltext" =>
                         {
-                            Box::new(
+                            Some(Box::new(
                                 crate::highlighting::analyze::standard(s)
                                     .map(|x| map_analyzed_token(array_index, x)),
-                            )
+                            ))
                         }
-                        (Some(analyzer), _) if analyzer == "fulltext_with_shingles" => Box::new(
-                            crate::highlighting::analyze::fulltext_with_shingles(s)
-                                .map(|x| map_analyzed_token(array_index, x)),
-                        ),
-                        (_, Some(field_type)) if field_type == "keyword" => {
-                            let lcase = s.to_lowercase();
-                            let len = lcase.len();
-                            Box::new(std::iter::once((
-                                Cow::Owned(lcase),
-                                TokenEntry {
-                                    type_: "word".into(),
-                                    array_index,
-                                    position: 1,
-                                    start_offset: 0,
-                                    end_offset: len.try_into().unwrap(),
-                                },
-                            )))
+                        (Some(analyzer), _) if analyzer == "fulltext_with_shingles" => {
+                            Some(Box::new(
+                                crate::highlighting::analyze::fulltext_with_shingles(s)
+                                    .map(|x| map_analyzed_token(array_index, x)),
+                            ))
                         }
-                        _ => {
-                            let es = Elasticsearch::new(index);
-                            Box::new(
-                                es.analyze_with_field(field, s)
-                                    .execute()
-                                    .expect("failed to analyze text for highlighting")
-                                    .tokens
-                                    .into_iter()
-                                    .map(|token| {
-                                        (
-                                            Cow::Owned(token.token),
-                                            TokenEntry {
-                                                type_: Cow::Owned(token.type_),
-                                                array_index,
-                                                position: (<i32 as TryInto<u32>>::try_into(
-                                                    token.position,
-                                                )
-                                                .expect("position overflowed"))
-                                                    + 1,
-                                                start_offset: token
-                                                    .start_offset
-                                                    .try_into()
-                                                    .expect("start_offset overflowed"),
-                                                end_offset: token
-                                                    .end_offset
-                                                    .try_into()
-                                                    .expect("end_offset overflowed"),
-                                            },
-                                        )
-                                    }),
-                            )
+                        (_, Some(field_type)) if field_type == "keyword" => match normalizer {
+                            None => {
+                                let lcase = s.to_lowercase();
+                                let len = lcase.len();
+                                Some(Box::new(std::iter::once((
+                                    Cow::Owned(lcase),
+                                    TokenEntry {
+                                        type_: "word".into(),
+                                        array_index,
+                                        position: 1,
+                                        start_offset: 0,
+                                        end_offset: len.try_into().unwrap(),
+                                    },
+                                ))))
+                            }
+                            Some(normalizer) => lookup_local_normalizer(normalizer).map(
+                                |normalizer| -> Box<dyn Iterator<Item = _>> {
+                                    Box::new(
+                                        normalizer
+                                            .analyze(s)
+                                            .into_iter()
+                                            .map(move |x| map_analyzed_token(array_index, x)),
+                                    )
+                                },
+                            ),
+                        },
+                        (Some(analyzer), _) => lookup_local_analyzer(analyzer).map(
+                            |analyzer| -> Box<dyn Iterator<Item = _>> {
+                                Box::new(
+                                    analyzer
+                                        .analyze(s)
+                                        .into_iter()
+                                        .map(move |x| map_analyzed_token(array_index, x)),
+                                )
+                            },
+                        ),
+                        (None, Some(field_type)) if field_type == "text" => {
+                            // text fields without an explicit analyzer use Elasticsearch's "standard"
+                            Some(Box::new(
+                                crate::highlighting::analyze::standard(s)
+                                    .map(|x| map_analyzed_token(array_index, x)),
+                            ))
                         }
+                        _ => None,
                     };
 
-                for (token, entry) in results {
-                    pgrx::check_for_interrupts!();
-                    self.lookup.entry(token).or_default().push(entry);
+                match results {
+                    Some(results) => {
+                        for (token, entry) in results {
+                            pgrx::check_for_interrupts!();
+                            self.lookup.entry(token).or_default().push(entry);
+                        }
+                    }
+
+                    // Elasticsearch will need to analyze this one for us
+                    None => self
+                        .pending
+                        .push((field.to_string(), array_index, s.as_str())),
                 }
                 self.data_type = Some(DataType::String);
             }
 
             Value::Array(values) => values.iter().enumerate().for_each(|(idx, v)| {
                 self.analyze_document(
-                    index,
                     field,
                     v,
                     array_index + (idx as u32),
                     field_type,
                     is_date,
                     analyzer,
+                    normalizer,
                 )
             }),
 
             Value::Object(o) => {
                 o.iter().for_each(|(k, v)| {
                     self.analyze_document(
-                        index,
                         &format!("{}.{}", field, k),
                         v,
                         array_index,
                         field_type,
                         is_date,
                         analyzer,
+                        normalizer,
                     );
                 });
             }
@@ -770,6 +897,37 @@ impl<'a> DocumentHighlighter<'a> {
     }
 }
 
+/// Splits the tokens from a multi-text `_analyze` request back into one group per text,
+/// rebasing their offsets and positions so each group looks like it was analyzed on its own.
+///
+/// Elasticsearch separates consecutive texts by an offset gap of 1 and by the field's
+/// `position_increment_gap`, which we don't know, so positions of all but the first
+/// text are rebased on their first token
+fn split_analyzed_texts(texts: &[&str], tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut groups = Vec::with_capacity(texts.len());
+    let mut tokens = tokens.into_iter().peekable();
+    let mut base_offset = 0i64;
+
+    for (i, text) in texts.iter().enumerate() {
+        let end_offset = base_offset + text.encode_utf16().count() as i64;
+        let mut base_position = if i == 0 { Some(0) } else { None };
+        let mut group = Vec::new();
+
+        while let Some(mut token) = tokens.next_if(|token| token.start_offset <= end_offset) {
+            let base_position = *base_position.get_or_insert(token.position);
+            token.position -= base_position;
+            token.start_offset -= base_offset;
+            token.end_offset -= base_offset;
+            group.push(token);
+        }
+
+        groups.push(group);
+        base_offset = end_offset + 1;
+    }
+
+    groups
+}
+
 #[pg_extern(immutable, parallel_safe)]
 fn highlight_term(
     index: PgRelation,
@@ -1031,7 +1189,10 @@ fn highlight_proximity(
 #[cfg(any(test, feature = "pg_test"))]
 #[pgrx::pg_schema]
 mod tests {
-    use crate::highlighting::document_highlighter::{DocumentHighlighter, TokenEntry};
+    use crate::elasticsearch::analyze::Token;
+    use crate::highlighting::document_highlighter::{
+        split_analyzed_texts, DocumentHighlighter, TokenEntry,
+    };
     use crate::zql::ast::Term;
     use pgrx::spi::SpiTupleTable;
     use pgrx::*;
@@ -1039,6 +1200,47 @@ mod tests {
     use serde_json::*;
     use std::borrow::Cow;
 
+    #[pg_test]
+    fn test_split_analyzed_texts() {
+        let token = |token: &str, position, start_offset, end_offset| Token {
+            type_: "<ALPHANUM>".into(),
+            token: token.into(),
+            position,
+            start_offset,
+            end_offset,
+        };
+
+        // as Elasticsearch analyzes an array of texts: offsets continue across them, and
+        // positions jump by the field's position_increment_gap
+        let groups = split_analyzed_texts(
+            &["quick fox", "", "lazy dog"],
+            vec![
+                token("quick", 0, 0, 5),
+                token("fox", 1, 6, 9),
+                token("lazy", 202, 11, 15),
+                token("dog", 203, 16, 19),
+            ],
+        );
+
+        let groups = groups
+            .iter()
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .map(|t| (t.token.as_str(), t.position, t.start_offset, t.end_offset))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            vec![
+                vec![("quick", 0, 0, 5), ("fox", 1, 6, 9)],
+                vec![],
+                vec![("lazy", 0, 0, 4), ("dog", 1, 5, 8)],
+            ]
+        );
+    }
+
     #[pg_test(error = "no matches found")]
     #[initialize(es = true)]
     fn test_look_for_match_none() {
@@ -14,7 +14,7 @@ impl QueryHighlighter {
     pub fn highlight<'a>(
         index: &PgRelation,
         document: &'a serde_json::Value,
-        fields: &HashSet<(String, Option<String>, Option<String>, bool)>,
+        fields: &HashSet<(String, Option<String>, Option<String>, Option<String>, bool)>,
         query: &Expr<'a>,
         highlighters: &'a mut HashMap<String, Vec<DocumentHighlighter<'a>>>,
     ) -> impl std::iter::Iterator<
@@ -31,9 +31,8 @@ impl QueryHighlighter {
     > + 'a {
         let document = document.as_object().expect("document not an object");
 
-        fields
-            .iter()
-            .for_each(|(base_field, field_type, index_analyzer, is_date)| {
+        fields.iter().for_each(
+            |(base_field, field_type, index_analyzer, normalizer, is_date)| {
                 if let Some(value) = document.get(base_field) {
                     for ((fieldname, _), highlighter) in DocumentHighlighter::from_json(
                         index,
@@ -43,11 +42,13 @@ impl QueryHighlighter {
                         field_type,
                         *is_date,
                         index_analyzer,
+                        normalizer,
                     ) {
                         highlighters.entry(fieldname).or_default().push(highlighter);
                     }
                 }
-            });
+            },
+        );
 
         let mut highlights: Vec<((QualifiedField, String), Vec<(&Cow<'_, str>, &TokenEntry)>)> =
             Default::default();
@@ -565,9 +566,9 @@ fn highlight_document_json(
 fn make_used_fields(
     index: &PgRelation,
     mut used_fields: HashSet<String>,
-) -> &'static HashSet<(String, Option<String>, Option<String>, bool)> {
+) -> &'static HashSet<(String, Option<String>, Option<String>, Option<String>, bool)> {
     static mut USED_FIELDS_CACHE: Lazy<
-        HashMap<String, HashSet<(String, Option<String>, Option<String>, bool)>>,
+        HashMap<String, HashSet<(String, Option<String>, Option<String>, Option<String>, bool)>>,
     > = Lazy::new(|| Default::default());
 
     unsafe {
@@ -600,10 +601,10 @@ fn make_used_fields(
                 .into_iter()
                 .map(|field| {
                     let base_field = field.split('.').next().map(|v| v.to_string()).unwrap();
-                    let (field_type, _, index_analyzer) =
+                    let (field_type, normalizer, index_analyzer) =
                         get_highlight_analysis_info(index, &base_field);
                     let is_date = has_date_subfield(index, &base_field);
-                    (base_field, field_type, index_analyzer, is_date)
+                    (base_field, field_type, index_analyzer, normalizer, is_date)
                 })
                 .collect::<HashSet<_>>()
         })
@@ -614,7 +615,7 @@ fn highlight_document_internal<'a>(
     index: PgRelation,
     document: &serde_json::Value,
     query: &Expr,
-    used_fields: &HashSet<(String, Option<String>, Option<String>, bool)>,
+    used_fields: &HashSet<(String, Option<String>, Option<String>, Option<String>, bool)>,
     dedup_results: bool,
 ) -> TableIterator<
     'a,
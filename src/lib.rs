@@ -66,6 +66,16 @@ extension_sql_file!(
         query_dsl::geo::point_array_to_json,
     ]
 );
+extension_sql_file!(
+    "../sql/_saved-queries.sql",
+    name = "saved_queries",
+    requires = [zombodb::zdbquery::saved_queries::validate_saved_query]
+);
+extension_sql_file!(
+    "../sql/_prepared-transactions.sql",
+    name = "prepared_transactions"
+);
+extension_sql_file!("../sql/_credentials.sql", name = "credentials");
 extension_sql_file!("../sql/_finalize.sql", finalize);
 
 #[allow(non_snake_case)]
@@ -126,6 +136,7 @@ pub mod pg_test {
             endpoint.leak(),
             "enable_seqscan = false",
             "enable_indexscan = true",
+            "max_prepared_transactions = 10",
         ]
     }
 }
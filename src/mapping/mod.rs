@@ -1,3 +1,4 @@
+use crate::access_method::options::ZDBIndexOptions;
 use crate::elasticsearch::Elasticsearch;
 use crate::json::builder::JsonBuilder;
 use crate::utils::{find_zdb_index, lookup_zdb_index_tupdesc, type_is_domain};
@@ -110,7 +111,7 @@ fn reapply_mapping(index_relation: PgRelation) -> bool {
         .heap_relation()
         .expect("no heap relation for index!");
     let mut mapping = generate_default_mapping(&heap_relation);
-    let _ = categorize_tupdesc(&tupdesc, &heap_relation, Some(&mut mapping));
+    let _ = categorize_tupdesc(&tupdesc, &index_relation, Some(&mut mapping));
 
     let es = Elasticsearch::new(&index_relation);
     es.put_mapping(serde_json::to_value(&mapping).expect("failed to serialize mapping to json"))
@@ -130,19 +131,28 @@ pub struct CategorizedAttribute {
 #[allow(clippy::cognitive_complexity)]
 pub fn categorize_tupdesc(
     tupdesc: &PgTupleDesc,
-    heap_relation: &PgRelation,
+    index_relation: &PgRelation,
     mut mapping: Option<&mut HashMap<String, serde_json::Value>>,
 ) -> Vec<CategorizedAttribute> {
     let mut categorized_attributes = Vec::with_capacity(tupdesc.len());
     let type_conversion_cache = lookup_type_conversions();
-    let user_mappings = if mapping.is_some() {
-        Some(lookup_mappings(heap_relation))
+    let options = ZDBIndexOptions::from_relation(index_relation);
+    let exclude_columns = options.exclude_columns();
+    let completion_columns = options.completion_columns();
+    let (user_mappings, index_mappings) = if mapping.is_some() {
+        let heap_relation = index_relation
+            .heap_relation()
+            .expect("no heap relation for index!");
+        (
+            Some(lookup_mappings(&heap_relation)),
+            Some(options.mappings()),
+        )
     } else {
-        None
+        (None, None)
     };
 
     for (attno, attribute) in tupdesc.iter().enumerate() {
-        if attribute.is_dropped() {
+        if attribute.is_dropped() || exclude_columns.iter().any(|c| c == attribute.name()) {
             continue;
         }
         let attname = attribute.name();
@@ -533,6 +543,20 @@ pub fn categorize_tupdesc(
                 }
             };
 
+            // the index's own `mappings` option has the final say
+            let mut definition =
+                merge_mapping_definition(definition, index_mappings.as_ref().unwrap().get(attname));
+
+            // and columns named by the `completion_columns` option get a completion subfield
+            if completion_columns.iter().any(|c| c == attname) {
+                if let Some(definition) = definition.as_object_mut() {
+                    let fields = definition.entry("fields").or_insert_with(|| json!({}));
+                    if let Some(fields) = fields.as_object_mut() {
+                        fields.insert("completion".to_owned(), json!({ "type": "completion" }));
+                    }
+                }
+            }
+
             mapping
                 .as_mut()
                 .unwrap()
@@ -549,6 +573,56 @@ pub fn categorize_tupdesc(
         });
     }
 
+    if let Some(index_mappings) = &index_mappings {
+        let is_indexed = |column: &String| {
+            // our attribute names are pre-quoted for the JsonBuilder
+            let quoted = serde_json::to_string(&json! {column})
+                .expect("failed to convert column name to json");
+            categorized_attributes
+                .iter()
+                .any(|attribute| attribute.attname == quoted)
+        };
+
+        for column in index_mappings.keys() {
+            if !is_indexed(column) {
+                panic!(
+                    "the mappings option names column {} which is not indexed",
+                    column
+                )
+            }
+        }
+
+        for column in &options.unstored_columns() {
+            if !is_indexed(column) {
+                panic!(
+                    "the unstored_columns option names column {} which is not indexed",
+                    column
+                )
+            }
+        }
+
+        for column in &completion_columns {
+            if !is_indexed(column) {
+                panic!(
+                    "the completion_columns option names column {} which is not indexed",
+                    column
+                )
+            }
+        }
+
+        for column in &exclude_columns {
+            if !tupdesc
+                .iter()
+                .any(|attribute| !attribute.is_dropped() && attribute.name() == column)
+            {
+                panic!(
+                    "the exclude_columns option names column {} which does not exist",
+                    column
+                )
+            }
+        }
+    }
+
     categorized_attributes
 }
 
@@ -602,6 +676,24 @@ fn handle_as_generic_string(is_array: bool, base_type_oid: pg_sys::Oid) -> Box<C
     }
 }
 
+/// Merge the properties of a column's definition from the index's `mappings` option over the
+/// definition ZomboDB would otherwise use
+fn merge_mapping_definition(
+    definition: serde_json::Value,
+    index_mapping: Option<&serde_json::Value>,
+) -> serde_json::Value {
+    match (definition, index_mapping) {
+        (Value::Object(mut definition), Some(Value::Object(index_mapping))) => {
+            for (key, value) in index_mapping {
+                definition.insert(key.clone(), value.clone());
+            }
+            Value::Object(definition)
+        }
+        (_, Some(index_mapping)) => index_mapping.clone(),
+        (definition, None) => definition,
+    }
+}
+
 pub fn generate_default_mapping(heap_relation: &PgRelation) -> HashMap<String, serde_json::Value> {
     let mut mapping = HashMap::new();
 
@@ -672,6 +764,39 @@ pub fn lookup_analysis_thing(table_name: &str) -> Value {
     }
 }
 
+/// The `zdb.filters` definitions plus a `synonym_graph` filter for each of `zdb.synonym_sets`, named
+/// after its set.  A synonym set replaces any filter of the same name
+pub fn lookup_filters() -> Value {
+    let mut filters = lookup_analysis_thing("filters");
+    if let (Value::Object(filters), Value::Object(synonyms)) =
+        (&mut filters, lookup_synonym_filters())
+    {
+        filters.extend(synonyms);
+    }
+    filters
+}
+
+/// A `synonym_graph` filter for each of `zdb.synonym_sets`, which loads its rules from the
+/// Elasticsearch synonyms set of the same name.  They're `updateable` so that
+/// `zdb.reload_search_analyzers()` can change an index's synonyms without reindexing, which also
+/// means Elasticsearch only allows them in search analyzers
+pub fn lookup_synonym_filters() -> Value {
+    match Spi::get_one::<Json>(
+        "SELECT json_object_agg(name, json_build_object('type', 'synonym_graph', 'synonyms_set', name, 'updateable', true)) FROM zdb.synonym_sets;",
+    ) {
+        Ok(Some(json)) => json.0,
+        Ok(None) | Err(_) => json! {{}},
+    }
+}
+
+/// The rules of each of `zdb.synonym_sets`, keyed by its name
+pub fn lookup_synonym_sets() -> Value {
+    match Spi::get_one::<Json>("SELECT json_object_agg(name, rules) FROM zdb.synonym_sets;") {
+        Ok(Some(json)) => json.0,
+        Ok(None) | Err(_) => json! {{}},
+    }
+}
+
 fn lookup_type_mapping(typoid: PgOid) -> Option<(Option<serde_json::Value>, Option<pg_sys::Oid>)> {
     let (json, regproc) = Spi::connect(|client| {
         let table = client
@@ -745,11 +870,7 @@ mod tests {
         let tupdesc = lookup_zdb_index_tupdesc(&index);
         let mut mapping = generate_default_mapping(&index.heap_relation().unwrap());
 
-        categorize_tupdesc(
-            &tupdesc,
-            &index.heap_relation().unwrap(),
-            Some(&mut mapping),
-        );
+        categorize_tupdesc(&tupdesc, &index, Some(&mut mapping));
         let mapping_json = serde_json::to_value(&mapping).unwrap();
 
         assert_eq!(
@@ -798,4 +919,33 @@ mod tests {
 
         Ok(())
     }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    unsafe fn test_index_mappings_option() -> spi::Result<()> {
+        Spi::run(
+            r#"CREATE TABLE test (id serial8, title text, body text);
+               CREATE INDEX idxtest ON test USING zombodb ((test.*))
+                      WITH (mappings='{"title": {"analyzer": "english"}, "body": {"index": false}}');"#,
+        )?;
+        let index = PgRelation::open_with_name("idxtest").expect("no such relation");
+        let tupdesc = lookup_zdb_index_tupdesc(&index);
+        let mut mapping = generate_default_mapping(&index.heap_relation().unwrap());
+        categorize_tupdesc(&tupdesc, &index, Some(&mut mapping));
+
+        assert_eq!(mapping["title"]["type"], "text");
+        assert_eq!(mapping["title"]["analyzer"], "english");
+        assert_eq!(mapping["body"]["index"], false);
+        Ok(())
+    }
+
+    #[pg_test(error = "the mappings option names column nope which is not indexed")]
+    #[initialize(es = true)]
+    unsafe fn test_index_mappings_option_unknown_column() -> spi::Result<()> {
+        Spi::run(
+            r#"CREATE TABLE test (id serial8, title text);
+               CREATE INDEX idxtest ON test USING zombodb ((test.*))
+                      WITH (mappings='{"nope": {"index": false}}');"#,
+        )
+    }
 }
@@ -1,4 +1,5 @@
 use pgrx::datum::{Date, Time, TimeWithTimeZone, Timestamp, TimestampWithTimeZone, ToIsoString};
+use pgrx::pg_sys;
 use serde::{Serialize, Serializer};
 
 #[derive(Debug)]
@@ -97,3 +98,34 @@ serialize!(ZDBTime);
 serialize!(ZDBTimeWithTimeZone);
 serialize!(ZDBDate);
 serialize!(ZDBTimestampWithTimeZone);
+
+/// Is `value` an Elasticsearch date-math expression, such as `now`, `now-7d/d`, or `2020-01-01||+1M`?
+pub fn is_date_math(value: &str) -> bool {
+    match value.strip_prefix("now") {
+        Some(math) => math.is_empty() || math.starts_with(['+', '-', '/']),
+        None => value.contains("||"),
+    }
+}
+
+/// Anchor the `now` of a date-math expression to the start of the current transaction so that
+/// it means the same instant for every query in the transaction.  Anything else is returned as-is
+pub fn resolve_date_math(value: &str) -> String {
+    match value.strip_prefix("now") {
+        Some(math) if is_date_math(value) => {
+            let now = transaction_timestamp();
+            if math.is_empty() {
+                now
+            } else {
+                format!("{}||{}", now, math)
+            }
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn transaction_timestamp() -> String {
+    let now =
+        TimestampWithTimeZone::try_from(unsafe { pg_sys::GetCurrentTransactionStartTimestamp() })
+            .expect("invalid transaction timestamp");
+    ZDBTimestampWithTimeZone::from(now).0
+}
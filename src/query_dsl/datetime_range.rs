@@ -15,7 +15,8 @@ mod pg_catalog {
 #[pgrx::pg_schema]
 mod dsl {
     use crate::misc::timestamp_support::{
-        ZDBDate, ZDBTime, ZDBTimeWithTimeZone, ZDBTimestamp, ZDBTimestampWithTimeZone,
+        resolve_date_math, ZDBDate, ZDBTime, ZDBTimeWithTimeZone, ZDBTimestamp,
+        ZDBTimestampWithTimeZone,
     };
     use crate::query_dsl::datetime_range::pg_catalog::*;
     use crate::zdbquery::ZDBQuery;
@@ -36,6 +37,8 @@ mod dsl {
         #[serde(skip_serializing_if = "Option::is_none")]
         boost: Option<f32>,
         relation: Option<Relation>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        time_zone: Option<String>,
     }
 
     #[pg_extern(immutable, parallel_safe, name = "datetime_range")]
@@ -55,6 +58,7 @@ mod dsl {
             gte: gte.map(|t| t.into()),
             boost,
             relation,
+            time_zone: None,
         };
         make_datetime_range_dsl(field, datetime_range)
     }
@@ -76,6 +80,7 @@ mod dsl {
             gte: gte.map(|t| t.into()),
             boost,
             relation,
+            time_zone: None,
         };
         make_datetime_range_dsl(field, datetime_range)
     }
@@ -97,6 +102,7 @@ mod dsl {
             gte: gte.map(|ts| ts.into()),
             boost,
             relation,
+            time_zone: None,
         };
         make_datetime_range_dsl(field, datetime_range)
     }
@@ -118,6 +124,7 @@ mod dsl {
             gte: gte.map(|tsz| tsz.into()),
             boost,
             relation,
+            time_zone: None,
         };
         make_datetime_range_dsl(field, datetime_range)
     }
@@ -139,6 +146,34 @@ mod dsl {
             gte: gte.map(|t| t.into()),
             boost,
             relation,
+            time_zone: None,
+        };
+        make_datetime_range_dsl(field, datetime_range)
+    }
+
+    /// Bounds are Elasticsearch date-math expressions, such as `now-7d/d`, or anything else
+    /// Elasticsearch can parse as a date.  `now` is resolved to the start of the current transaction.
+    /// It has its own name so that text literals given to `dsl.datetime_range()` still resolve to
+    /// its `timestamp with time zone` form
+    #[pg_extern(stable, parallel_safe, name = "datetime_range_math")]
+    fn datetime_range_date_math(
+        field: &str,
+        lt: default!(Option<&str>, NULL),
+        gt: default!(Option<&str>, NULL),
+        lte: default!(Option<&str>, NULL),
+        gte: default!(Option<&str>, NULL),
+        boost: default!(Option<f32>, NULL),
+        relation: default!(Option<Relation>, "'intersects'"),
+        time_zone: default!(Option<&str>, NULL),
+    ) -> ZDBQuery {
+        let datetime_range: DateTimeRange<String> = DateTimeRange {
+            lt: lt.map(resolve_date_math),
+            lte: lte.map(resolve_date_math),
+            gt: gt.map(resolve_date_math),
+            gte: gte.map(resolve_date_math),
+            boost,
+            relation,
+            time_zone: time_zone.map(|tz| tz.to_string()),
         };
         make_datetime_range_dsl(field, datetime_range)
     }
@@ -345,6 +380,62 @@ mod tests {
         );
     }
 
+    #[pg_test]
+    fn test_datetime_range_date_math() {
+        let (zdbquery, now) = Spi::get_two::<ZDBQuery, String>(
+            "SELECT dsl.datetime_range_math(
+            'fieldname',
+             lt => 'now+1M',
+             gte => 'now/d',
+             time_zone => '-07:00'
+              ), to_json(now() AT TIME ZONE 'UTC')#>>'{}';",
+        )
+        .expect("SPI failed");
+        let zdbquery = zdbquery.expect("SPI datum was NULL");
+        let now = now.expect("SPI datum was NULL");
+        let dsl = zdbquery.into_value();
+
+        assert_eq!(
+            dsl,
+            json! {
+                {
+                    "range": {
+                        "fieldname": {
+                            "lt": format!("{}+00:00||+1M", now),
+                            "gte": format!("{}+00:00||/d", now),
+                            "relation": "intersects",
+                            "time_zone": "-07:00"
+                        }
+                    }
+                }
+            }
+        );
+    }
+
+    #[pg_test]
+    fn test_datetime_range_date_math_not_anchored() {
+        let zdbquery = Spi::get_one::<ZDBQuery>(
+            "SELECT dsl.datetime_range_math('fieldname', gt => '2020-01-01||+1M/d');",
+        )
+        .expect("SPI failed")
+        .expect("SPI datum was NULL");
+        let dsl = zdbquery.into_value();
+
+        assert_eq!(
+            dsl,
+            json! {
+                {
+                    "range": {
+                        "fieldname": {
+                            "gt": "2020-01-01||+1M/d",
+                            "relation": "intersects"
+                        }
+                    }
+                }
+            }
+        );
+    }
+
     #[pg_test(error = "function dsl.datetime_range(unknown) is not unique")]
     fn test_datetime_range_with_defaults() {
         Spi::get_one::<ZDBQuery>("SELECT dsl.datetime_range('fieldname');")
@@ -0,0 +1,99 @@
+#[pgrx::pg_schema]
+mod dsl {
+    use crate::zdbquery::{FusionMethod, ZDBQuery};
+    use pgrx::*;
+
+    #[pg_extern(immutable, parallel_safe)]
+    pub fn hybrid(
+        queries: Array<ZDBQuery>,
+        method: default!(FusionMethod, "'rrf'"),
+        weights: default!(Option<Vec<f32>>, NULL),
+        rank_constant: default!(Option<i32>, NULL),
+    ) -> ZDBQuery {
+        let queries = queries
+            .iter()
+            .map(|zdbquery| zdbquery.expect("found NULL zdbquery in queries"))
+            .collect::<Vec<_>>();
+        if queries.is_empty() {
+            panic!("dsl.hybrid() needs at least one query");
+        }
+        if let Some(weights) = weights.as_ref() {
+            if weights.len() != queries.len() {
+                panic!(
+                    "dsl.hybrid() was given {} weights for {} queries",
+                    weights.len(),
+                    queries.len()
+                );
+            }
+        }
+        let rank_constant = rank_constant.map(|rank_constant| {
+            if rank_constant < 1 {
+                panic!("rank_constant must be at least 1");
+            }
+            rank_constant as u32
+        });
+
+        ZDBQuery::new_hybrid(queries, method, weights, rank_constant)
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::zdbquery::ZDBQuery;
+    use pgrx::*;
+    use serde_json::json;
+
+    #[pg_test]
+    fn test_hybrid() {
+        let zdbquery = Spi::get_one::<ZDBQuery>(
+            "SELECT dsl.hybrid(ARRAY['cats', dsl.term('title', 'dogs')], 'linear', ARRAY[1.0, 0.5]::real[])")
+            .expect("SPI failed").expect("SPI datum was NULL");
+
+        assert_eq!(
+            zdbquery.into_value(),
+            json! {
+                {
+                    "query_dsl": {
+                        "bool": {
+                            "should": [
+                                { "query_string": { "query": "cats" } },
+                                { "term": { "title": { "value": "dogs" } } }
+                            ]
+                        }
+                    },
+                    "hybrid": {
+                        "queries": [
+                            { "query_dsl": { "query_string": { "query": "cats" } } },
+                            { "query_dsl": { "term": { "title": { "value": "dogs" } } } }
+                        ],
+                        "method": "linear",
+                        "weights": [1.0, 0.5]
+                    }
+                }
+            }
+        );
+    }
+
+    #[pg_test(error = "dsl.hybrid() was given 1 weights for 2 queries")]
+    fn test_hybrid_weights_mismatch() {
+        Spi::run(
+            "SELECT dsl.hybrid(ARRAY['cats', 'dogs']::zdbquery[], weights => ARRAY[1.0]::real[]);",
+        )
+        .expect("SPI failed");
+    }
+
+    #[pg_test(
+        error = "dsl.hybrid() needs a dsl.limit(), either around it or on each of its queries"
+    )]
+    #[initialize(es = true)]
+    fn test_hybrid_requires_limit() {
+        Spi::run(
+            "CREATE TABLE hybrid_no_limit(id serial8, title text);
+             CREATE INDEX idxhybrid_no_limit ON hybrid_no_limit USING zombodb ((hybrid_no_limit.*));
+             INSERT INTO hybrid_no_limit(title) VALUES ('cats'), ('dogs');
+             SELECT * FROM hybrid_no_limit WHERE hybrid_no_limit ==> dsl.hybrid(ARRAY['cats', 'dogs']::zdbquery[]);",
+        )
+        .expect("SPI failed");
+    }
+}
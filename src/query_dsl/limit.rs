@@ -1,5 +1,6 @@
 #[pgrx::pg_schema]
 mod dsl {
+    use crate::utils::is_non_shadow_zdb_index;
     use crate::zdbquery::ZDBQuery;
     use pgrx::*;
 
@@ -34,6 +35,15 @@ mod dsl {
     pub fn row_estimate(row_estimate: i64, query: ZDBQuery) -> ZDBQuery {
         query.set_row_estimate(Some(row_estimate))
     }
+
+    /// Search a specific one of the table's ZomboDB indices
+    #[pg_extern(immutable, parallel_safe)]
+    pub fn index(index: PgRelation, query: ZDBQuery) -> ZDBQuery {
+        if !is_non_shadow_zdb_index(&index) {
+            panic!("{} is not a ZomboDB index", index.name());
+        }
+        query.set_target_index(Some(index.oid()))
+    }
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -92,6 +102,11 @@ mod tests {
         )
     }
 
+    #[pg_test(error = "pg_class is not a ZomboDB index")]
+    fn test_index_not_zdb() -> spi::Result<()> {
+        Spi::run("SELECT dsl.index('pg_class', dsl.match_all())")
+    }
+
     #[pg_test]
     fn test_row_estimate() {
         let zdbquery = row_estimate(200, ZDBQuery::new_with_query_string("test"));
@@ -5,6 +5,7 @@ mod field_exists;
 mod field_missing;
 mod fuzzy;
 mod geo;
+mod hybrid;
 mod limit;
 mod match_all;
 mod matches;
@@ -46,6 +46,7 @@ mod dsl {
                                         &mut used_fields,
                                         &mut fieldname_stack,
                                         &mut operator_stack,
+                                        &mut Vec::new(),
                                         &e.unwrap(),
                                     )
                                     .expect("failed to parse index link"),
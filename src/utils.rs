@@ -1,4 +1,5 @@
 use crate::access_method::options::ZDBIndexOptions;
+use crate::executor_manager::get_executor_manager;
 use crate::zql::ast::IndexLink;
 use byteorder::ReadBytesExt;
 use pgrx::pg_sys::AsPgCStr;
@@ -6,21 +7,15 @@ use pgrx::*;
 use serde_json::Value;
 use std::io::Read;
 
-pub fn count_non_shadow_zdb_indices(
+/// The table's non-shadow ZomboDB indices, other than `current_index`
+pub fn other_non_shadow_zdb_indices(
     heap_relation: &PgRelation,
     current_index: &PgRelation,
-) -> usize {
-    let mut cnt = 0;
-    for index in heap_relation.indices(pg_sys::AccessShareLock as pg_sys::LOCKMODE) {
-        if index.oid() != current_index.oid()
-            && is_zdb_index(&index)
-            && !ZDBIndexOptions::is_shadow_index_fast(&index)
-        {
-            cnt += 1;
-        }
-    }
-
-    cnt
+) -> Vec<PgRelation> {
+    heap_relation
+        .indices(pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+        .filter(|index| index.oid() != current_index.oid() && is_non_shadow_zdb_index(index))
+        .collect()
 }
 
 pub fn get_heap_relation_for_func_expr(
@@ -205,6 +200,102 @@ pub fn is_view(relation: &PgRelation) -> bool {
     rel.relkind == pg_sys::RELKIND_VIEW as std::os::raw::c_char
 }
 
+#[inline]
+pub fn is_partitioned_zdb_index(index: &PgRelation) -> bool {
+    let rel = unsafe { PgBox::from_pg(index.rd_rel) };
+    rel.relkind == pg_sys::RELKIND_PARTITIONED_INDEX as std::os::raw::c_char && is_zdb_index(index)
+}
+
+/// Returns the ZomboDB indices on the leaf partitions below the partitioned `index`, which are
+/// the ones that actually have an Elasticsearch index.
+///
+/// Unless `include_pruned` is set, if the currently-executing statement scans any of those
+/// partitions then only the partitions it scans are returned, as Postgres has pruned the others
+pub fn find_zdb_partition_indices(index: &PgRelation, include_pruned: bool) -> Vec<PgRelation> {
+    let oids = Spi::connect(|client| {
+        let mut oids = Vec::new();
+        let table = client.select(
+            "SELECT relid FROM pg_partition_tree($1) WHERE isleaf ORDER BY level, relid",
+            None,
+            &[index.oid().into()],
+        )?;
+        for row in table {
+            if let Some(oid) = row.get::<pg_sys::Oid>(1)? {
+                oids.push(oid);
+            }
+        }
+        Ok::<_, spi::Error>(oids)
+    })
+    .expect("SPI failed");
+
+    let partitions = oids
+        .into_iter()
+        .map(|oid| unsafe {
+            PgRelation::with_lock(oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+        })
+        .filter(is_non_shadow_zdb_index)
+        .collect::<Vec<_>>();
+
+    if include_pruned {
+        return partitions;
+    }
+
+    let scanned = scanned_relations();
+    if partitions
+        .iter()
+        .any(|partition| scanned.contains(&partition.heap_relation().expect("not an index").oid()))
+    {
+        partitions
+            .into_iter()
+            .filter(|partition| {
+                scanned.contains(&partition.heap_relation().expect("not an index").oid())
+            })
+            .collect()
+    } else {
+        partitions
+    }
+}
+
+/// Returns the partitioned ZomboDB indices above `index`, nearest first
+pub fn find_zdb_partition_ancestors(index: &PgRelation) -> Vec<PgRelation> {
+    Spi::connect(|client| {
+        let mut ancestors = Vec::new();
+        let table = client.select(
+            "SELECT relid FROM pg_partition_ancestors($1) WHERE relid <> $1",
+            None,
+            &[index.oid().into()],
+        )?;
+        for row in table {
+            if let Some(oid) = row.get::<pg_sys::Oid>(1)? {
+                ancestors.push(unsafe {
+                    PgRelation::with_lock(oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+                });
+            }
+        }
+        Ok::<_, spi::Error>(ancestors)
+    })
+    .expect("SPI failed")
+}
+
+/// The relations in the range table of the currently-executing statement, if any
+fn scanned_relations() -> Vec<pg_sys::Oid> {
+    let mut relids = Vec::new();
+    if let Some((query_desc, _)) = get_executor_manager().peek_query_state() {
+        unsafe {
+            let plannedstmt = query_desc.as_ref().unwrap().plannedstmt;
+            if let Some(plannedstmt) = plannedstmt.as_ref() {
+                let rtable = PgList::<pg_sys::RangeTblEntry>::from_pg(plannedstmt.rtable);
+                for rte in rtable.iter_ptr() {
+                    if (*rte).rtekind == pg_sys::RTEKind::RTE_RELATION {
+                        relids.push((*rte).relid);
+                    }
+                }
+            }
+        }
+    }
+    relids
+}
+
 pub fn lookup_zdb_extension_oid() -> pg_sys::Oid {
     match Spi::get_one::<pg_sys::Oid>("(SELECT oid FROM pg_extension WHERE extname = 'zombodb' UNION ALL SELECT 0) ORDER BY 1 DESC;")
         .expect("SPI failed")
@@ -0,0 +1,129 @@
+//! Hybrid search:  several queries, such as a lexical query and a vector similarity query, run
+//! separately and their ranked results fused into one list, either by Reciprocal Rank Fusion or
+//! by a weighted sum of their min-max normalized scores
+use crate::elasticsearch::search::Fields;
+use crate::zdbquery::{FusionMethod, ZDBQuery};
+use serde::*;
+use std::collections::HashMap;
+
+/// Same as Elasticsearch's `rrf` retriever
+pub const DEFAULT_RANK_CONSTANT: u32 = 60;
+
+pub type Hit = (
+    f64,
+    u64,
+    Option<Fields>,
+    Option<HashMap<String, Vec<String>>>,
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hybrid {
+    pub(super) queries: Vec<ZDBQuery>,
+    pub(super) method: FusionMethod,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) weights: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) rank_constant: Option<u32>,
+}
+
+impl Hybrid {
+    pub fn queries(&self) -> &[ZDBQuery] {
+        &self.queries
+    }
+
+    /// Fuse the hits of each of our queries, which are in the order their query ranked them,
+    /// into one list ordered by fused score
+    pub fn fuse(&self, legs: Vec<Vec<Hit>>) -> Vec<Hit> {
+        let rank_constant = self.rank_constant.unwrap_or(DEFAULT_RANK_CONSTANT) as f64;
+        let mut fused = HashMap::<u64, Hit>::new();
+
+        for (i, hits) in legs.into_iter().enumerate() {
+            let weight = self
+                .weights
+                .as_ref()
+                .and_then(|weights| weights.get(i))
+                .map_or(1.0, |weight| *weight as f64);
+            let (min, max) = hits.iter().fold((f64::MAX, f64::MIN), |(min, max), hit| {
+                (min.min(hit.0), max.max(hit.0))
+            });
+
+            for (rank, (score, ctid, fields, highlights)) in hits.into_iter().enumerate() {
+                let contribution = match self.method {
+                    FusionMethod::rrf => weight / (rank_constant + rank as f64 + 1.0),
+                    FusionMethod::linear if max > min => weight * (score - min) / (max - min),
+                    FusionMethod::linear => weight,
+                };
+
+                let entry = fused
+                    .entry(ctid)
+                    .or_insert_with(|| (0.0, ctid, fields, None));
+                entry.0 += contribution;
+                if let Some(highlights) = highlights {
+                    let merged = entry.3.get_or_insert_with(HashMap::new);
+                    for (field, mut fragments) in highlights {
+                        merged.entry(field).or_default().append(&mut fragments);
+                    }
+                }
+            }
+        }
+
+        let mut fused = fused.into_values().collect::<Vec<_>>();
+        fused.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        fused
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::zdbquery::hybrid::{Hit, Hybrid};
+    use crate::zdbquery::{FusionMethod, ZDBQuery};
+    use pgrx::*;
+
+    fn hits(scored: &[(f64, u64)]) -> Vec<Hit> {
+        scored
+            .iter()
+            .map(|(score, ctid)| (*score, *ctid, None, None))
+            .collect()
+    }
+
+    fn hybrid(method: FusionMethod, weights: Option<Vec<f32>>) -> Hybrid {
+        Hybrid {
+            queries: vec![ZDBQuery::new_with_query_string("a"); 2],
+            method,
+            weights,
+            rank_constant: Some(1),
+        }
+    }
+
+    #[pg_test]
+    fn test_rrf() {
+        let fused = hybrid(FusionMethod::rrf, None).fuse(vec![
+            hits(&[(9.0, 1), (5.0, 2)]),
+            hits(&[(0.9, 2), (0.8, 3)]),
+        ]);
+
+        let fused = fused
+            .iter()
+            .map(|(score, ctid, ..)| (*ctid, *score))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fused,
+            vec![(2, 1.0 / 3.0 + 1.0 / 2.0), (1, 1.0 / 2.0), (3, 1.0 / 3.0)]
+        );
+    }
+
+    #[pg_test]
+    fn test_linear() {
+        let fused = hybrid(FusionMethod::linear, Some(vec![1.0, 2.0])).fuse(vec![
+            hits(&[(9.0, 1), (5.0, 2), (1.0, 3)]),
+            hits(&[(0.8, 3), (0.4, 1)]),
+        ]);
+
+        let fused = fused
+            .iter()
+            .map(|(score, ctid, ..)| (*ctid, *score))
+            .collect::<Vec<_>>();
+        assert_eq!(fused, vec![(3, 2.0), (1, 1.0), (2, 0.5)]);
+    }
+}
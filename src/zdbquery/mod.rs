@@ -1,13 +1,17 @@
 use serde_json::{json, Value};
 
 mod cast;
+pub mod hybrid;
 pub mod mvcc;
 mod opclass;
+mod saved_queries;
+mod validate;
 
 use crate::gucs::ZDB_DEFAULT_ROW_ESTIMATE;
 use crate::query_dsl::nested::pg_catalog::ScoreMode;
-use crate::zql::ast::{Expr, IndexLink, QualifiedField};
-use crate::zql::dsl::expr_to_dsl;
+use crate::zdbquery::hybrid::Hybrid;
+use crate::zql::ast::{Directive, Expr, IndexLink, QualifiedField};
+use crate::zql::dsl::expr_to_dsl_with_time_zone;
 use crate::zql::transformations::field_finder::find_link_for_field;
 pub use pg_catalog::*;
 use pgrx::prelude::*;
@@ -108,6 +112,7 @@ impl PartialEq<Value> for ZDBQueryClause {
 #[allow(non_camel_case_types)]
 #[pgrx::pg_schema]
 mod pg_catalog {
+    use crate::zdbquery::hybrid::Hybrid;
     use crate::zdbquery::ZDBQueryClause;
     use crate::zql::ast::IndexLink;
     use pgrx::*;
@@ -138,9 +143,20 @@ mod pg_catalog {
         pub(super) highlights: HashMap<String, Value>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub(super) link_options: Option<Vec<IndexLink>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "index")]
+        pub(super) target_index: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(super) hybrid: Option<Hybrid>,
     }
 
-    #[derive(PostgresEnum, Serialize, Deserialize)]
+    #[derive(Debug, Copy, Clone, PartialEq, PostgresEnum, Serialize, Deserialize)]
+    pub enum FusionMethod {
+        rrf,
+        linear,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, PostgresEnum, Serialize, Deserialize)]
     pub enum SortDirection {
         asc,
         desc,
@@ -201,6 +217,8 @@ impl ZDBQuery {
             query_dsl: Some(ZDBQueryClause::opaque(query_dsl)),
             highlights: HashMap::new(),
             link_options: None,
+            target_index: None,
+            hybrid: None,
         }
     }
 
@@ -215,6 +233,8 @@ impl ZDBQuery {
             query_dsl: Some(clause),
             highlights: HashMap::new(),
             link_options: None,
+            target_index: None,
+            hybrid: None,
         }
     }
 
@@ -234,6 +254,8 @@ impl ZDBQuery {
             query_dsl: Some(ZDBQueryClause::zdb(query)),
             highlights: HashMap::new(),
             link_options: None,
+            target_index: None,
+            hybrid: None,
         }
     }
 
@@ -271,6 +293,8 @@ impl ZDBQuery {
             && self.query_dsl.is_none()
             && self.highlights.is_empty()
             && self.link_options.is_none()
+            && self.target_index.is_none()
+            && self.hybrid.is_none()
     }
 
     pub fn only_query_dsl(&self) -> bool {
@@ -283,6 +307,8 @@ impl ZDBQuery {
             && self.sort_json.is_none()
             && self.highlights.is_empty()
             && self.link_options.is_none()
+            && self.target_index.is_none()
+            && self.hybrid.is_none()
     }
 
     pub fn want_score(&self) -> bool {
@@ -386,6 +412,64 @@ impl ZDBQuery {
         self
     }
 
+    pub fn target_index(&self) -> Option<pg_sys::Oid> {
+        self.target_index.map(pg_sys::Oid::from)
+    }
+
+    pub fn set_target_index(mut self, index: Option<pg_sys::Oid>) -> Self {
+        self.target_index = index.map(|oid| oid.as_u32());
+        self
+    }
+
+    pub fn hybrid(&self) -> Option<&Hybrid> {
+        self.hybrid.as_ref()
+    }
+
+    /// Fuse the results of `queries`, which are also ORed together to find the rows that
+    /// match any of them when the query isn't used to search, such as to count or aggregate
+    pub fn new_hybrid(
+        queries: Vec<ZDBQuery>,
+        method: FusionMethod,
+        weights: Option<Vec<f32>>,
+        rank_constant: Option<u32>,
+    ) -> Self {
+        let should = queries
+            .iter()
+            .map(|query| query.query_dsl())
+            .collect::<Vec<_>>();
+        let mut query =
+            ZDBQuery::new_with_query_clause(ZDBQueryClause::bool(None, Some(should), None, None));
+        query.hybrid = Some(Hybrid {
+            queries,
+            method,
+            weights,
+            rank_constant,
+        });
+        query
+    }
+
+    /// The index this query should search:  the one it was pointed at with `dsl.index()`, which
+    /// must be on the same table as `index`, or else `index` itself
+    pub fn resolve_index(&self, index: &PgRelation) -> PgRelation {
+        match self.target_index() {
+            Some(target_oid) if target_oid != index.oid() => {
+                let target = unsafe {
+                    PgRelation::with_lock(target_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+                };
+                let heap_oid = |index: &PgRelation| index.heap_relation().map(|heap| heap.oid());
+                if heap_oid(&target) != heap_oid(index) {
+                    panic!(
+                        "the query targets index {}, which isn't on the same table as index {}",
+                        target.name(),
+                        index.name()
+                    );
+                }
+                target
+            }
+            _ => index.clone(),
+        }
+    }
+
     pub fn query_dsl(&self) -> ZDBQueryClause {
         self.query_dsl
             .as_ref()
@@ -451,10 +535,11 @@ impl ZDBQuery {
         let index_links = self
             .link_options()
             .unwrap_or_else(|| IndexLink::from_zdb(index));
-        self.rewrite(index, &index_links, target_link);
-        let json = serde_json::to_value(&self.query_dsl)
+        let directives = self.rewrite(index, &index_links, target_link);
+        let query = self.apply_directives(directives);
+        let json = serde_json::to_value(&query.query_dsl)
             .expect("failed to convert ZDBQuery to a json Value");
-        ZDBPreparedQuery(self, json)
+        ZDBPreparedQuery(query, json)
     }
 
     fn rewrite(
@@ -462,7 +547,8 @@ impl ZDBQuery {
         index: &PgRelation,
         index_links: &[IndexLink],
         target_link: Option<IndexLink>,
-    ) {
+    ) -> Vec<Directive> {
+        let mut directives = Vec::new();
         ZDBQuery::rewrite_zdb_query_clause(
             self.query_dsl
                 .as_mut()
@@ -471,7 +557,51 @@ impl ZDBQuery {
             index,
             &IndexLink::from_relation(index),
             &target_link,
-        )
+            &mut directives,
+        );
+        directives
+    }
+
+    /// Apply the `#limit()`, `#sort()`, etc directives found in this query's ZQL.  Anything
+    /// already set through the SQL builder functions takes precedence, as does the first
+    /// occurrence of a repeated directive
+    fn apply_directives(mut self, directives: Vec<Directive>) -> Self {
+        for directive in directives {
+            match directive {
+                Directive::Limit(limit) if self.limit.is_none() => {
+                    self = self.set_limit(Some(limit));
+                }
+                Directive::Offset(offset) if self.offset.is_none() => {
+                    self = self.set_offset(Some(offset));
+                }
+                Directive::MinScore(min_score) if self.min_score.is_none() => {
+                    self = self.set_min_score(Some(min_score));
+                }
+                Directive::Sort(fields) if self.sort_json.is_none() => {
+                    self = self.set_sort_descriptors(fields.into_iter().map(|(field, order)| {
+                        Some(SortDescriptor {
+                            field,
+                            options: SortDescriptorOptions {
+                                order,
+                                mode: None,
+                                nested_path: None,
+                                nested_filter: None,
+                            },
+                        })
+                    }));
+                }
+                Directive::Highlight(fields) => {
+                    for field in fields {
+                        // same as the defaults of `zdb.highlight()`
+                        self.highlights().entry(field).or_insert_with(
+                            || json! {{ "require_field_match": false, "force_source": true }},
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        self
     }
 
     fn rewrite_zdb_query_clause(
@@ -480,6 +610,7 @@ impl ZDBQuery {
         index: &PgRelation,
         root_link: &IndexLink,
         target_link: &Option<IndexLink>,
+        directives: &mut Vec<Directive>,
     ) {
         if clause.zdb.is_some() {
             if let Some(zdb) = clause.zdb.as_ref() {
@@ -489,16 +620,26 @@ impl ZDBQuery {
                     return;
                 }
                 let mut used_fields = HashSet::new();
-                let expr = Expr::from_str(
+                let first_directive = directives.len();
+                let expr = Expr::from_str_with_directives(
                     index,
                     "zdb_all",
                     &zdb.query,
                     index_links,
                     target_link,
                     &mut used_fields,
+                    directives,
                 )
                 .expect("failed to parse query");
-                let parsed = expr_to_dsl(root_link, index_links, &expr);
+                // a `#time_zone()` applies to the date ranges of the query it's part of
+                let time_zone =
+                    directives[first_directive..]
+                        .iter()
+                        .find_map(|directive| match directive {
+                            Directive::TimeZone(time_zone) => Some(time_zone.as_str()),
+                            _ => None,
+                        });
+                let parsed = expr_to_dsl_with_time_zone(root_link, index_links, &expr, time_zone);
 
                 clause.zdb = None;
                 clause.opaque = Some(parsed);
@@ -512,6 +653,7 @@ impl ZDBQuery {
                         index,
                         root_link,
                         target_link,
+                        directives,
                     );
                 });
             }
@@ -524,6 +666,7 @@ impl ZDBQuery {
                         index,
                         root_link,
                         target_link,
+                        directives,
                     );
                 });
             }
@@ -536,6 +679,7 @@ impl ZDBQuery {
                         index,
                         root_link,
                         target_link,
+                        directives,
                     );
                 });
             }
@@ -548,6 +692,7 @@ impl ZDBQuery {
                         index,
                         root_link,
                         target_link,
+                        directives,
                     );
                 });
             }
@@ -558,6 +703,7 @@ impl ZDBQuery {
                 index,
                 root_link,
                 target_link,
+                directives,
             );
         } else if let Some(boosting) = &mut clause.boosting {
             ZDBQuery::rewrite_zdb_query_clause(
@@ -566,6 +712,7 @@ impl ZDBQuery {
                 index,
                 root_link,
                 target_link,
+                directives,
             );
             ZDBQuery::rewrite_zdb_query_clause(
                 boosting.negative.as_mut(),
@@ -573,10 +720,18 @@ impl ZDBQuery {
                 index,
                 root_link,
                 target_link,
+                directives,
             );
         } else if let Some(dis_max) = &mut clause.dis_max {
             dis_max.queries.iter_mut().for_each(|c| {
-                ZDBQuery::rewrite_zdb_query_clause(c, index_links, index, root_link, target_link);
+                ZDBQuery::rewrite_zdb_query_clause(
+                    c,
+                    index_links,
+                    index,
+                    root_link,
+                    target_link,
+                    directives,
+                );
             });
         } else if let Some(constant_score) = &mut clause.constant_score {
             ZDBQuery::rewrite_zdb_query_clause(
@@ -585,6 +740,7 @@ impl ZDBQuery {
                 index,
                 root_link,
                 target_link,
+                directives,
             );
         }
     }
@@ -754,6 +910,10 @@ impl ZDBPreparedQuery {
         self.0.want_score.unwrap_or_default()
     }
 
+    pub fn hybrid(&self) -> Option<&Hybrid> {
+        self.0.hybrid()
+    }
+
     pub fn has_highlights(&self) -> bool {
         !self.0.highlights.is_empty()
     }
@@ -1,6 +1,8 @@
+use crate::access_method::options::ZDBIndexOptions;
 use crate::elasticsearch::Elasticsearch;
 use crate::executor_manager::get_executor_manager;
 use crate::gucs::ZDB_IGNORE_VISIBILITY;
+use crate::utils::find_zdb_partition_indices;
 use crate::zdbquery::{ZDBPreparedQuery, ZDBQuery, ZDBQueryClause};
 use pgrx::*;
 use serde_json::json;
@@ -26,14 +28,63 @@ pub fn apply_visibility_clause(
     query: ZDBPreparedQuery,
     force: bool,
 ) -> serde_json::Value {
+    let partitions = elasticsearch.is_partitioned().then(|| {
+        find_zdb_partition_indices(&elasticsearch.index_relation(), false)
+            .iter()
+            .map(|partition| {
+                ZDBIndexOptions::from_relation(partition)
+                    .index_name()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    });
+
     if ZDB_IGNORE_VISIBILITY.get() && !force {
         // if we're configured to ignore visibility, then we simply return the
-        // query_dsl of the provided query
-        return query.take_query_dsl();
+        // query_dsl of the provided query, limited to the partitions that weren't pruned
+        return match partitions {
+            None => query.take_query_dsl(),
+            Some(partitions) => json! {
+                {
+                    "bool": {
+                        "must": [query.query_dsl()],
+                        "filter": [{ "terms": { "_index": partitions } }]
+                    }
+                }
+            },
+        };
     }
 
     let dsl = query.query_dsl();
-    let clause = build_visibility_clause(elasticsearch.index_name());
+    let clause = match partitions {
+        None => build_visibility_clause(elasticsearch.index_name()),
+
+        // a partitioned index is searched through its alias, and each partition's Elasticsearch
+        // index has its own aborted xids, so each needs its own visibility clause
+        Some(partitions) => {
+            let clauses = partitions
+                .iter()
+                .map(|index_name| {
+                    json! {
+                        {
+                            "bool": {
+                                "filter": [
+                                    { "term": { "_index": index_name } },
+                                    build_visibility_clause(index_name)
+                                ]
+                            }
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if clauses.is_empty() {
+                json! { { "match_none": {} } }
+            } else {
+                json! { { "bool": { "should": clauses, "minimum_should_match": 1 } } }
+            }
+        }
+    };
     json! {
         {
             "bool": {
@@ -269,3 +320,35 @@ pub fn build_visibility_clause(index_name: &str) -> serde_json::Value {
 
     }
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_partitioned_count() {
+        Spi::run(
+            "CREATE TABLE test_partitioned (id int8, title text) PARTITION BY RANGE (id);
+             CREATE TABLE test_partitioned_1 PARTITION OF test_partitioned FOR VALUES FROM (1) TO (11);
+             CREATE TABLE test_partitioned_2 PARTITION OF test_partitioned FOR VALUES FROM (11) TO (21);
+             INSERT INTO test_partitioned SELECT id, 'title ' || id FROM generate_series(1, 20) id;
+             CREATE INDEX idxtest_partitioned ON test_partitioned USING zombodb ((test_partitioned.*));",
+        )
+        .expect("SPI failed");
+
+        let count =
+            Spi::get_one::<i64>("SELECT zdb.count('idxtest_partitioned', dsl.match_all());")
+                .expect("SPI failed")
+                .expect("count was NULL");
+        assert_eq!(count, 20);
+
+        let count = Spi::get_one::<i64>(
+            "SELECT count(*) FROM test_partitioned WHERE test_partitioned ==> 'title:title';",
+        )
+        .expect("SPI failed")
+        .expect("count was NULL");
+        assert_eq!(count, 20);
+    }
+}
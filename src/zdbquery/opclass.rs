@@ -23,6 +23,16 @@ fn anyelement_cmpfunc(
         None => return false,
     };
 
+    // the query may have been pointed at another of the table's ZomboDB indices
+    let index_oid = match query.target_index() {
+        Some(_) => query
+            .resolve_index(&unsafe {
+                PgRelation::with_lock(index_oid, pg_sys::AccessShareLock as pg_sys::LOCKMODE)
+            })
+            .oid(),
+        None => index_oid,
+    };
+
     // SAFETY:  Right here, we require that the actual Datum behind `element` be an [`ItemPointerData`]
     //  and it will be so long as ZomboDB's rewriter has run.  The only way that wouldn't happen is
     //  if "zombodb.so" wasn't loaded by the time we got here, and is literally impossible since this
@@ -172,7 +182,13 @@ fn restrict(
                         count_estimate = estimate as u64;
                     } else {
                         // ask Elasticsearch to estimate our selectivity
-                        let es = Elasticsearch::new(&heap_relation);
+                        let es = match zdbquery.target_index() {
+                            Some(index_oid) => Elasticsearch::new(&PgRelation::with_lock(
+                                index_oid,
+                                pg_sys::AccessShareLock as pg_sys::LOCKMODE,
+                            )),
+                            None => Elasticsearch::new(&heap_relation),
+                        };
                         count_estimate = es
                             .raw_count(zdbquery.prepare(&es.index_relation(), None).0)
                             .execute()
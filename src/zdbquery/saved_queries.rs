@@ -0,0 +1,440 @@
+//! Support for parameterized queries stored in the `zdb.saved_queries` catalog table.
+//!
+//! A saved query is either ZQL or JSON (a ZDBQuery or raw QueryDSL) containing `{{param}}`
+//! placeholders.  The `params` column is a JSON object of the parameters the query accepts,
+//! mapping each name to its default value.  A `null` default means the parameter is required.
+
+use crate::elasticsearch::pg_catalog::ArbitraryRequestType;
+use crate::elasticsearch::Elasticsearch;
+use crate::zdbquery::ZDBQuery;
+use once_cell::sync::Lazy;
+use pgrx::prelude::*;
+use pgrx::{JsonB, PgRelation};
+use regex::Regex;
+use serde_json::{json, Map, Value};
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").expect("invalid placeholder regex")
+});
+
+/// prefix of the values we substitute for each parameter when building a search template
+const TEMPLATE_SENTINEL: &str = "zdbtemplateparam";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Syntax {
+    Zql,
+    Json,
+}
+
+impl Syntax {
+    fn of(template: &str) -> Self {
+        // a template is JSON if it parses as a JSON object once every placeholder is
+        // replaced with a JSON literal.  Anything else is ZQL, which mirrors how
+        // `ZDBQuery::from_str()` decides between the two
+        match serde_json::from_str::<Value>(&PLACEHOLDER.replace_all(template, "null")) {
+            Ok(Value::Object(_)) => Syntax::Json,
+            _ => Syntax::Zql,
+        }
+    }
+
+    fn quotes(&self) -> &'static [char] {
+        match self {
+            Syntax::Zql => &['"', '\''],
+            Syntax::Json => &['"'],
+        }
+    }
+
+    fn render(&self, name: &str, value: &Value, open: Option<char>) -> Result<String, String> {
+        match (self, open) {
+            (Syntax::Json, None) => Ok(value.to_string()),
+            (Syntax::Json, Some(_)) => Ok(json_string_contents(&scalar_text(name, value)?)),
+            (Syntax::Zql, Some(quote)) => Ok(escape_zql(&scalar_text(name, value)?, quote)),
+            (Syntax::Zql, None) => match value {
+                Value::Array(values) => Ok(format!(
+                    "[{}]",
+                    values
+                        .iter()
+                        .map(|value| zql_literal(name, value))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .join(", ")
+                )),
+                value => zql_literal(name, value),
+            },
+        }
+    }
+}
+
+struct SavedQuery {
+    query: String,
+    params: Map<String, Value>,
+}
+
+impl SavedQuery {
+    fn lookup(name: &str) -> SavedQuery {
+        let (query, params) = Spi::connect(|client| {
+            let table = client
+                .select(
+                    "SELECT query, params FROM zdb.saved_queries WHERE name = $1;",
+                    None,
+                    &[name.into()],
+                )?
+                .first();
+
+            if table.is_empty() {
+                return Ok((None, None));
+            }
+
+            table.get_two::<String, JsonB>()
+        })
+        .expect("SPI failed");
+
+        match (query, params) {
+            (Some(query), Some(JsonB(Value::Object(params)))) => SavedQuery { query, params },
+            _ => panic!("no such saved query: {}", name),
+        }
+    }
+
+    fn expand(&self, params: &Value) -> Result<String, String> {
+        let supplied = params
+            .as_object()
+            .ok_or_else(|| "saved query parameters must be a JSON object".to_string())?;
+
+        if let Some(unknown) = supplied
+            .keys()
+            .find(|name| !self.params.contains_key(*name))
+        {
+            return Err(format!("unknown saved query parameter: {}", unknown));
+        }
+
+        let mut resolved = Map::new();
+        for (name, default) in &self.params {
+            match supplied.get(name) {
+                Some(value) => resolved.insert(name.clone(), value.clone()),
+                None if default.is_null() => {
+                    return Err(format!("missing required saved query parameter: {}", name))
+                }
+                None => resolved.insert(name.clone(), default.clone()),
+            };
+        }
+
+        expand_template(&self.query, &resolved)
+    }
+
+    fn search_template_source(&self, index: &PgRelation) -> Result<Value, String> {
+        // expand each parameter to a unique sentinel, let ZomboDB generate the QueryDSL as
+        // it would for any other query, and then swap the sentinels for mustache tags
+        let names = self.params.keys().collect::<Vec<_>>();
+        let sentinels = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                (
+                    name.to_string(),
+                    Value::String(format!("{}{}", TEMPLATE_SENTINEL, i)),
+                )
+            })
+            .collect::<Map<_, _>>();
+        let expanded = expand_template(&self.query, &sentinels)?;
+        let (prepared, _) = ZDBQuery::from_str(&expanded).prepare(index, None);
+
+        let mut body = json! {{ "query": prepared.query_dsl() }};
+        if let Some(limit) = prepared.limit() {
+            body["size"] = json!(limit);
+        }
+        if let Some(offset) = prepared.offset() {
+            body["from"] = json!(offset);
+        }
+        if let Some(min_score) = prepared.min_score() {
+            body["min_score"] = json!(min_score);
+        }
+        if let Some(sort_json) = prepared.sort_json() {
+            body["sort"] = sort_json.clone();
+        }
+
+        let mut source = serde_json::to_string(&body).expect("failed to serialize template");
+
+        // go backwards so that, ie, "param1" doesn't clobber the front of "param10"
+        for (i, name) in names.iter().enumerate().rev() {
+            let sentinel = format!("{}{}", TEMPLATE_SENTINEL, i);
+            if PLACEHOLDER
+                .captures_iter(&self.query)
+                .any(|c| &c[1] == name.as_str())
+                && !source.contains(&sentinel)
+            {
+                return Err(format!(
+                    "parameter `{}` cannot be represented in a search template",
+                    name
+                ));
+            }
+
+            let tag = match &self.params[*name] {
+                Value::Null => format!("{{{{{}}}}}", name),
+                default => format!(
+                    "{{{{{0}}}}}{{{{^{0}}}}}{1}{{{{/{0}}}}}",
+                    name,
+                    json_string_contents(&scalar_text(name, default)?)
+                ),
+            };
+            source = source.replace(&sentinel, &tag);
+        }
+
+        // every sentinel lived inside a JSON string, so the source is still valid JSON
+        Ok(serde_json::from_str(&source).expect("search template is not valid JSON"))
+    }
+}
+
+/// Ensure that every placeholder in `query` is declared in `params`, and that every declared
+/// parameter is used.  This backs the CHECK constraint on `zdb.saved_queries`.
+#[pg_extern(immutable, parallel_safe)]
+fn validate_saved_query(query: &str, params: JsonB) -> bool {
+    if let Err(e) = validate_template(query, &params.0) {
+        panic!("invalid saved query: {}", e);
+    }
+    true
+}
+
+/// Expand the saved query named `name` using `params` and return it as a ZDBQuery.
+///
+/// This isn't an overload of `zdb.query()` as that would capture existing calls like
+/// `zdb.query('idxname', 'some query')`, whose arguments are untyped literals
+#[pg_extern(stable, parallel_safe)]
+fn saved_query(name: &str, params: default!(JsonB, "'{}'")) -> ZDBQuery {
+    ZDBQuery::from_str(&expand_query(name, params))
+}
+
+/// Expand the saved query named `name` using `params` and return its text
+#[pg_extern(stable, parallel_safe)]
+fn expand_query(name: &str, params: default!(JsonB, "'{}'")) -> String {
+    SavedQuery::lookup(name)
+        .expand(&params.0)
+        .unwrap_or_else(|e| panic!("failed to expand saved query `{}`: {}", name, e))
+}
+
+/// Like `zdb.dump_query()`, but for a saved query.  `params` has no default so that two-argument
+/// calls continue to resolve to `zdb.dump_query(regclass, zdbquery)`
+#[pg_extern(stable, parallel_safe, name = "dump_query")]
+fn dump_saved_query(index: PgRelation, name: &str, params: JsonB) -> String {
+    serde_json::to_string_pretty(
+        saved_query(name, params)
+            .prepare(&index, None)
+            .0
+            .query_dsl(),
+    )
+    .expect("failed to convert DSL to text")
+}
+
+/// Store the saved query named `name` in the Elasticsearch cluster hosting `index` as a
+/// mustache search template with the same name
+#[pg_extern(volatile, parallel_safe)]
+fn register_search_template(index: PgRelation, name: &str) -> String {
+    let source = SavedQuery::lookup(name)
+        .search_template_source(&index)
+        .unwrap_or_else(|e| panic!("failed to build search template `{}`: {}", name, e));
+    let es = Elasticsearch::new(&index);
+    es.arbitrary_request(
+        ArbitraryRequestType::PUT,
+        &format!("/_scripts/{}", name),
+        Some(json! {{
+            "script": {
+                "lang": "mustache",
+                "source": source
+            }
+        }}),
+    )
+    .unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn validate_template(template: &str, params: &Value) -> Result<(), String> {
+    let params = params
+        .as_object()
+        .ok_or_else(|| "parameters must be a JSON object".to_string())?;
+
+    let mut referenced = Vec::new();
+    let mut covered = Vec::new();
+    for captures in PLACEHOLDER.captures_iter(template) {
+        let name = captures.get(1).unwrap().as_str();
+        if !params.contains_key(name) {
+            return Err(format!(
+                "placeholder `{}` is not a declared parameter",
+                name
+            ));
+        }
+        referenced.push(name);
+        covered.push(captures.get(0).unwrap().range());
+    }
+
+    if let Some((start, _)) = template
+        .match_indices("{{")
+        .find(|(start, _)| !covered.iter().any(|range| range.contains(start)))
+    {
+        return Err(format!("malformed placeholder at offset {}", start));
+    }
+
+    if let Some(unused) = params
+        .keys()
+        .find(|name| !referenced.contains(&name.as_str()))
+    {
+        return Err(format!("parameter `{}` is never referenced", unused));
+    }
+
+    if template.trim_start().starts_with('{') && Syntax::of(template) != Syntax::Json {
+        return Err("query looks like JSON but does not parse".to_string());
+    }
+
+    Ok(())
+}
+
+fn expand_template(template: &str, params: &Map<String, Value>) -> Result<String, String> {
+    let syntax = Syntax::of(template);
+    let mut expanded = String::with_capacity(template.len());
+    let mut open = None;
+    let mut last = 0;
+
+    for captures in PLACEHOLDER.captures_iter(template) {
+        let placeholder = captures.get(0).unwrap();
+        let name = captures.get(1).unwrap().as_str();
+        let literal = &template[last..placeholder.start()];
+
+        open = scan_quotes(literal, open, syntax.quotes());
+        expanded.push_str(literal);
+
+        let value = params
+            .get(name)
+            .ok_or_else(|| format!("no value for parameter `{}`", name))?;
+        expanded.push_str(&syntax.render(name, value, open)?);
+        last = placeholder.end();
+    }
+
+    expanded.push_str(&template[last..]);
+    Ok(expanded)
+}
+
+/// Returns the quote character that is still open at the end of `text`, if any
+fn scan_quotes(text: &str, mut open: Option<char>, quotes: &[char]) -> Option<char> {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match open {
+            _ if c == '\\' => {
+                // skip whatever is escaped
+                chars.next();
+            }
+            Some(quote) if c == quote => open = None,
+            None if quotes.contains(&c) => open = Some(c),
+            _ => {}
+        }
+    }
+    open
+}
+
+fn scalar_text(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!(
+            "parameter `{}` must be a string, number, or boolean when used inside a quoted string",
+            name
+        )),
+    }
+}
+
+fn zql_literal(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::Null => Ok("null".to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::String(s) => Ok(format!("\"{}\"", escape_zql(s, '"'))),
+        _ => Err(format!(
+            "parameter `{}` cannot be expanded into a ZQL value",
+            name
+        )),
+    }
+}
+
+/// Escape `s` for use inside a ZQL string quoted with `quote` such that it's treated literally
+fn escape_zql(s: &str, quote: char) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || c == quote || c == '*' || c == '?' || c == '~' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The JSON-escaped form of `s`, without the surrounding double-quotes
+fn json_string_contents(s: &str) -> String {
+    let quoted = serde_json::to_string(s).expect("failed to serialize string");
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    #[pg_test]
+    fn test_expand_zql() {
+        Spi::run(
+            r#"SELECT zdb.define_saved_query('by_title', 'title:{{title}} AND body:"{{word}}*"', '{"title": null, "word": "a\"b"}');"#,
+        )
+        .expect("SPI failed");
+
+        let expanded = Spi::get_one::<String>(
+            r#"SELECT zdb.expand_query('by_title', '{"title": "it''s ~here"}');"#,
+        )
+        .expect("SPI failed")
+        .expect("expanded query was null");
+        assert_eq!(expanded, r#"title:"it's \~here" AND body:"a\"b*""#);
+    }
+
+    #[pg_test]
+    fn test_expand_zql_array() {
+        Spi::run(
+            "SELECT zdb.define_saved_query('by_ids', 'id:{{ids}} OR tag:''{{tag}}''', '{\"ids\": null, \"tag\": null}');",
+        )
+        .expect("SPI failed");
+
+        let expanded = Spi::get_one::<String>(
+            r#"SELECT zdb.expand_query('by_ids', '{"ids": [1, 2, "x"], "tag": "o''brien"}');"#,
+        )
+        .expect("SPI failed")
+        .expect("expanded query was null");
+        assert_eq!(expanded, r#"id:[1, 2, "x"] OR tag:'o\'brien'"#);
+    }
+
+    #[pg_test]
+    fn test_expand_json() {
+        Spi::run(
+            r#"SELECT zdb.define_saved_query('by_json', '{"match": {"title": {"query": "{{q}}", "boost": {{boost}}}}}', '{"q": null, "boost": 1.5}');"#,
+        )
+        .expect("SPI failed");
+
+        let expanded =
+            Spi::get_one::<String>(r#"SELECT zdb.expand_query('by_json', '{"q": "say \"hi\""}');"#)
+                .expect("SPI failed")
+                .expect("expanded query was null");
+        assert_eq!(
+            expanded,
+            r#"{"match": {"title": {"query": "say \"hi\"", "boost": 1.5}}}"#
+        );
+    }
+
+    #[pg_test(
+        error = "failed to expand saved query `required`: missing required saved query parameter: title"
+    )]
+    fn test_missing_required_param() {
+        Spi::run(
+            "SELECT zdb.define_saved_query('required', 'title:{{title}}', '{\"title\": null}');",
+        )
+        .expect("SPI failed");
+        Spi::run("SELECT zdb.expand_query('required');").expect("SPI failed");
+    }
+
+    #[pg_test(error = "invalid saved query: placeholder `nope` is not a declared parameter")]
+    fn test_undeclared_placeholder() {
+        Spi::run("SELECT zdb.define_saved_query('undeclared', 'title:{{nope}}', '{}');")
+            .expect("SPI failed");
+    }
+}
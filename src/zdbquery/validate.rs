@@ -0,0 +1,621 @@
+use crate::elasticsearch::Elasticsearch;
+use crate::misc::timestamp_support::is_date_math;
+use crate::zdbquery::ZDBQuery;
+use crate::zql::ast::{Expr, IndexLink, ParserError, QualifiedField, Term};
+use lalrpop_util::ParseError;
+use levenshtein::levenshtein;
+use pgrx::prelude::*;
+use pgrx::PgRelation;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+const NUMERIC_TYPES: &[&str] = &[
+    "long",
+    "integer",
+    "short",
+    "byte",
+    "double",
+    "float",
+    "half_float",
+    "scaled_float",
+    "unsigned_long",
+];
+
+struct Problem {
+    severity: &'static str,
+    message: String,
+    offsets: Option<(i32, i32)>,
+    suggestion: Option<String>,
+}
+
+impl Problem {
+    fn error(message: String, offsets: Option<(i32, i32)>, suggestion: Option<String>) -> Self {
+        Problem {
+            severity: "error",
+            message,
+            offsets,
+            suggestion,
+        }
+    }
+
+    fn warning(message: String, offsets: Option<(i32, i32)>, suggestion: Option<String>) -> Self {
+        Problem {
+            severity: "warning",
+            message,
+            offsets,
+            suggestion,
+        }
+    }
+}
+
+/// Check a query against an index without running it.  ZQL is checked for syntax errors, fields
+/// that aren't in the index's mapping, and values that don't suit their field's type.  If that
+/// finds no errors, the query is then given to Elasticsearch's `_validate/query` API.
+///
+/// Offsets are character positions within the ZQL string the problem was found in, and are NULL
+/// when a problem can't be tied to a specific part of the query
+#[pg_extern(volatile, parallel_safe)]
+fn validate_query(
+    index: PgRelation,
+    query: ZDBQuery,
+) -> TableIterator<
+    'static,
+    (
+        name!(severity, String),
+        name!(message, String),
+        name!(start_offset, Option<i32>),
+        name!(end_offset, Option<i32>),
+        name!(suggestion, Option<String>),
+    ),
+> {
+    let mut validator = Validator::new(&index);
+    let index_links = query
+        .link_options()
+        .unwrap_or_else(|| IndexLink::from_zdb(&index));
+
+    let mut zql = Vec::new();
+    find_zql(
+        &serde_json::to_value(query.query_dsl()).expect("failed to serialize query"),
+        &mut zql,
+    );
+    for input in &zql {
+        let mut used_fields = HashSet::new();
+        match Expr::from_str(
+            &index,
+            "zdb_all",
+            input,
+            &index_links,
+            &None,
+            &mut used_fields,
+        ) {
+            Ok(expr) => validator.check_expr(&Source { input, used_fields }, &expr),
+            Err(e) => validator.problems.push(parse_problem(input, e)),
+        }
+    }
+
+    if !validator
+        .problems
+        .iter()
+        .any(|problem| problem.severity == "error")
+    {
+        validator.check_with_elasticsearch(query);
+    }
+
+    TableIterator::new(validator.problems.into_iter().map(|problem| {
+        (
+            problem.severity.to_string(),
+            problem.message,
+            problem.offsets.map(|(start, _)| start),
+            problem.offsets.map(|(_, end)| end),
+            problem.suggestion,
+        )
+    }))
+}
+
+struct Validator<'a> {
+    index: &'a PgRelation,
+    mappings: HashMap<String, Value>,
+    reported: HashSet<(String, String)>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(index: &'a PgRelation) -> Self {
+        Validator {
+            index,
+            mappings: Default::default(),
+            reported: Default::default(),
+            problems: Default::default(),
+        }
+    }
+
+    fn check_expr(&mut self, source: &Source, expr: &Expr) {
+        match expr {
+            Expr::Null | Expr::Json(_) => {}
+
+            Expr::Subselect(_, e) | Expr::Not(e) | Expr::Linked(_, e) | Expr::Nested(_, e) => {
+                self.check_expr(source, e)
+            }
+            Expr::Expand(_, e, f) => {
+                if let Some(filter) = f {
+                    self.check_expr(source, filter);
+                }
+                self.check_expr(source, e)
+            }
+            Expr::WithList(v) | Expr::AndList(v) | Expr::OrList(v) => {
+                v.iter().for_each(|e| self.check_expr(source, e))
+            }
+
+            Expr::Gt(f, t) | Expr::Lt(f, t) | Expr::Gte(f, t) | Expr::Lte(f, t) => {
+                self.check_term(source, f, t, true)
+            }
+            Expr::Contains(f, t) | Expr::Eq(f, t) | Expr::Ne(f, t) | Expr::DoesNotContain(f, t) => {
+                self.check_term(source, f, t, matches!(t, Term::Range(..)))
+            }
+            Expr::Regex(f, _)
+            | Expr::MoreLikeThis(f, _)
+            | Expr::FuzzyLikeThis(f, _)
+            | Expr::Matches(f, _) => {
+                self.field_type(source, f);
+            }
+        }
+    }
+
+    fn check_term(&mut self, source: &Source, field: &QualifiedField, term: &Term, is_range: bool) {
+        let field_type = match self.field_type(source, field) {
+            Some(field_type) => field_type,
+            None => return,
+        };
+
+        let values = match term {
+            Term::String(s, _) | Term::Phrase(s, _) => vec![*s],
+            Term::Range(s, e, _) => vec![*s, *e],
+            Term::ParsedArray(v, _) => v
+                .iter()
+                .filter_map(|t| match t {
+                    Term::String(s, _) | Term::Phrase(s, _) => Some(*s),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        if is_range && (field_type == "keyword" || field_type == "text") {
+            self.report(
+                &field.field,
+                "range",
+                Problem::warning(
+                    format!(
+                        "range on {} field `{}` compares values as strings",
+                        field_type,
+                        field.field_name()
+                    ),
+                    source.locate_field(&field.field),
+                    None,
+                ),
+            );
+        }
+
+        for value in values.into_iter().filter(|v| *v != "*") {
+            let problem = if NUMERIC_TYPES.contains(&field_type.as_str()) {
+                value.parse::<f64>().is_err().then(|| {
+                    Problem::error(
+                        format!(
+                            "`{}` is not a valid value for {} field `{}`",
+                            value,
+                            field_type,
+                            field.field_name()
+                        ),
+                        source.locate(value),
+                        Some("use a number".into()),
+                    )
+                })
+            } else if field_type == "boolean" {
+                (value != "true" && value != "false").then(|| {
+                    Problem::error(
+                        format!(
+                            "`{}` is not a valid value for boolean field `{}`",
+                            value,
+                            field.field_name()
+                        ),
+                        source.locate(value),
+                        Some("use `true` or `false`".into()),
+                    )
+                })
+            } else if is_range && field_type != "date" && is_date_math(value) {
+                Some(Problem::error(
+                    format!(
+                        "date-math `{}` used with {} field `{}`",
+                        value,
+                        field_type,
+                        field.field_name()
+                    ),
+                    source.locate(value),
+                    Some("date-math can only be used with date fields".into()),
+                ))
+            } else {
+                None
+            };
+
+            if let Some(problem) = problem {
+                self.problems.push(problem);
+            }
+        }
+    }
+
+    /// Look up the mapped type of `field`, reporting it once if it isn't in the mapping
+    fn field_type(&mut self, source: &Source, field: &QualifiedField) -> Option<String> {
+        let field_name = field.field_name();
+        if field_name == "zdb_all" || field_name.starts_with('_') {
+            return None;
+        }
+
+        let properties = self.properties(field.index.as_ref());
+        match lookup_type(&properties, &field_name) {
+            Some(field_type) => Some(field_type),
+            None => {
+                let mut known = Vec::new();
+                flatten_fields(&properties, None, &mut known);
+                let suggestion = known
+                    .into_iter()
+                    .map(|candidate| (levenshtein(&candidate, &field_name), candidate))
+                    .filter(|(distance, _)| *distance <= 2.max(field_name.len() / 3))
+                    .min()
+                    .map(|(_, candidate)| format!("did you mean `{}`?", candidate));
+                self.report(
+                    &field.field,
+                    "unknown",
+                    Problem::error(
+                        format!("field `{}` does not exist in the index", field_name),
+                        source.locate_field(&field.field),
+                        suggestion,
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    fn properties(&mut self, link: Option<&IndexLink>) -> Value {
+        let relation = match link {
+            Some(link) => link
+                .open_index()
+                .unwrap_or_else(|_| panic!("failed to open index for {}", link)),
+            None => self.index.clone(),
+        };
+        let elasticsearch = Elasticsearch::new(&relation);
+        let index_name = elasticsearch.index_name().to_string();
+
+        self.mappings
+            .entry(index_name.clone())
+            .or_insert_with(|| {
+                let mapping = elasticsearch
+                    .get_mapping()
+                    .execute()
+                    .expect("failed to get index mapping");
+                mapping[&index_name]["mappings"]["properties"].clone()
+            })
+            .clone()
+    }
+
+    fn report(&mut self, field: &str, kind: &str, problem: Problem) {
+        if self.reported.insert((field.to_string(), kind.to_string())) {
+            self.problems.push(problem);
+        }
+    }
+
+    fn check_with_elasticsearch(&mut self, query: ZDBQuery) {
+        let (prepared, _) = query.prepare(self.index, None);
+        match Elasticsearch::new(self.index)
+            .validate_query(prepared)
+            .execute()
+        {
+            Ok(response) => {
+                if response["valid"].as_bool().unwrap_or(true) {
+                    return;
+                }
+
+                let mut found = false;
+                if let Some(explanations) = response["explanations"].as_array() {
+                    for explanation in explanations {
+                        if let Some(error) = explanation["error"].as_str() {
+                            found = true;
+                            self.problems
+                                .push(Problem::error(error.to_string(), None, None));
+                        }
+                    }
+                }
+                if !found {
+                    let message = response["error"]
+                        .as_str()
+                        .unwrap_or("Elasticsearch reports the query is invalid");
+                    self.problems
+                        .push(Problem::error(message.to_string(), None, None));
+                }
+            }
+            Err(e) => {
+                // malformed queries can be rejected outright rather than explained
+                let message = serde_json::from_str::<Value>(e.message())
+                    .ok()
+                    .and_then(|body| {
+                        body["error"]["root_cause"][0]["reason"]
+                            .as_str()
+                            .or_else(|| body["error"]["reason"].as_str())
+                            .map(|reason| reason.to_string())
+                    })
+                    .unwrap_or_else(|| e.to_string());
+                self.problems.push(Problem::error(message, None, None));
+            }
+        }
+    }
+}
+
+/// Find the ZQL strings in a query's DSL.  These are the `query_string` clauses that have no
+/// other properties, just like `ZDBQuery::rewrite_zdb_query_clause()` decides
+fn find_zql(dsl: &Value, zql: &mut Vec<String>) {
+    match dsl {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("query_string", Value::Object(query_string)) if query_string.len() == 1 => {
+                        if let Some(Value::String(query)) = query_string.get("query") {
+                            zql.push(query.clone());
+                        }
+                    }
+                    _ => find_zql(value, zql),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| find_zql(value, zql)),
+        _ => {}
+    }
+}
+
+fn parse_problem(input: &str, e: ParserError) -> Problem {
+    match e {
+        ParseError::InvalidToken { location } => {
+            let c = input[location..].chars().next().unwrap_or(' ');
+            Problem::error(
+                format!("unrecognized character `{}`", c),
+                Some(offsets(input, location, location + c.len_utf8())),
+                None,
+            )
+        }
+        ParseError::UnrecognizedEof { location, expected } => Problem::error(
+            "unexpected end of query".into(),
+            Some(offsets(input, location, location)),
+            expected_suggestion(&expected),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected,
+        } => Problem::error(
+            format!("unexpected `{}`", token.1),
+            Some(offsets(input, start, end)),
+            expected_suggestion(&expected),
+        ),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => Problem::error(
+            format!("unexpected `{}`", token.1),
+            Some(offsets(input, start, end)),
+            None,
+        ),
+        ParseError::User { error } => Problem::error(
+            error.message,
+            Some(offsets(input, error.start, error.end)),
+            None,
+        ),
+    }
+}
+
+fn expected_suggestion(expected: &[String]) -> Option<String> {
+    if expected.is_empty() {
+        return None;
+    }
+
+    let expected = expected
+        .iter()
+        .map(|token| {
+            token
+                .trim_start_matches('r')
+                .trim_matches('#')
+                .trim_matches('"')
+        })
+        .collect::<Vec<_>>();
+    Some(format!("expected one of: {}", expected.join(", ")))
+}
+
+/// Convert byte offsets into `input` to character offsets
+fn offsets(input: &str, start: usize, end: usize) -> (i32, i32) {
+    let start_offset = input[..start].chars().count();
+    let end_offset = start_offset + input[start..end].chars().count();
+    (start_offset as i32, end_offset as i32)
+}
+
+/// A ZQL string, and the field names its parse used.  Every field name and value in the parsed
+/// query borrows from `input`, so where one points to is where in the query it came from
+struct Source<'a> {
+    input: &'a str,
+    used_fields: HashSet<&'a str>,
+}
+
+impl Source<'_> {
+    /// Where `s`, a value from the parsed query, is in the query.  `None` if it isn't from the
+    /// query itself, such as a value added when the query was expanded
+    fn locate(&self, s: &str) -> Option<(i32, i32)> {
+        let start = (s.as_ptr() as usize).checked_sub(self.input.as_ptr() as usize)?;
+        (start + s.len() <= self.input.len()).then(|| offsets(self.input, start, start + s.len()))
+    }
+
+    /// Where `field` is first used as a field name in the query
+    fn locate_field(&self, field: &str) -> Option<(i32, i32)> {
+        self.used_fields
+            .get(field)
+            .and_then(|field| self.locate(field))
+    }
+}
+
+fn lookup_type(properties: &Value, field: &str) -> Option<String> {
+    let mut parts = field.split('.');
+    let mut mapping = properties.get(parts.next()?)?;
+    for part in parts {
+        mapping = mapping["properties"]
+            .get(part)
+            .or_else(|| mapping["fields"].get(part))?;
+    }
+    Some(mapping["type"].as_str().unwrap_or("object").to_string())
+}
+
+fn flatten_fields(properties: &Value, prefix: Option<&str>, fields: &mut Vec<String>) {
+    if let Some(properties) = properties.as_object() {
+        for (name, mapping) in properties {
+            let name = match prefix {
+                Some(prefix) => format!("{}.{}", prefix, name),
+                None => name.clone(),
+            };
+            flatten_fields(&mapping["properties"], Some(&name), fields);
+            flatten_fields(&mapping["fields"], Some(&name), fields);
+            fields.push(name);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use pgrx::*;
+
+    fn create_table_and_index() {
+        Spi::run(
+            "CREATE TABLE test_validate (id serial8, title text, price integer, tags varchar[]);
+             CREATE INDEX idxtest_validate ON test_validate USING zombodb ((test_validate.*));",
+        )
+        .expect("SPI failed");
+    }
+
+    fn validate(query: &str) -> Vec<(String, String, Option<i32>, Option<i32>, Option<String>)> {
+        Spi::connect(|client| {
+            let mut rows = Vec::new();
+            for row in client.select(
+                "SELECT * FROM zdb.validate_query('idxtest_validate', $1::zdbquery)",
+                None,
+                &[query.into()],
+            )? {
+                rows.push((
+                    row.get::<String>(1)?.unwrap(),
+                    row.get::<String>(2)?.unwrap(),
+                    row.get::<i32>(3)?,
+                    row.get::<i32>(4)?,
+                    row.get::<String>(5)?,
+                ));
+            }
+            Ok::<_, spi::Error>(rows)
+        })
+        .expect("SPI failed")
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_valid_query() {
+        create_table_and_index();
+        assert!(validate("title:beer AND price:>5").is_empty());
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_parse_error() {
+        create_table_and_index();
+        let rows = validate("title:(beer AND");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, "error");
+        assert_eq!(rows[0].1, "unexpected end of query");
+        assert_eq!((rows[0].2, rows[0].3), (Some(15), Some(15)));
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_directive_errors() {
+        create_table_and_index();
+        assert_eq!(
+            validate("title:beer #limit(-5)"),
+            vec![(
+                "error".to_string(),
+                "#limit() requires a non-negative integer: -5".to_string(),
+                Some(18),
+                Some(20),
+                None
+            )]
+        );
+        assert_eq!(
+            validate("title:beer #sort(title sideways)"),
+            vec![(
+                "error".to_string(),
+                "unrecognized #sort() direction for field title: sideways".to_string(),
+                Some(23),
+                Some(31),
+                None
+            )]
+        );
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_locates_value_not_text() {
+        create_table_and_index();
+        let rows = validate("title:price AND price:price");
+        assert_eq!(
+            rows,
+            vec![(
+                "error".to_string(),
+                "`price` is not a valid value for integer field `price`".to_string(),
+                Some(22),
+                Some(27),
+                Some("use a number".to_string())
+            )]
+        );
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_unknown_field() {
+        create_table_and_index();
+        let rows = validate("titel:beer");
+        assert_eq!(
+            rows,
+            vec![(
+                "error".to_string(),
+                "field `titel` does not exist in the index".to_string(),
+                Some(0),
+                Some(5),
+                Some("did you mean `title`?".to_string())
+            )]
+        );
+    }
+
+    #[pg_test]
+    #[initialize(es = true)]
+    fn test_validate_type_mismatch() {
+        create_table_and_index();
+        let rows = validate("price:cheap OR tags:a /to/ m");
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "error".to_string(),
+                    "`cheap` is not a valid value for integer field `price`".to_string(),
+                    Some(6),
+                    Some(11),
+                    Some("use a number".to_string())
+                ),
+                (
+                    "warning".to_string(),
+                    "range on keyword field `tags` compares values as strings".to_string(),
+                    Some(15),
+                    Some(19),
+                    None
+                ),
+            ]
+        );
+    }
+}
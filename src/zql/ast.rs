@@ -10,6 +10,7 @@ pub use pg_catalog::{ProximityPart, ProximityTerm};
 
 use crate::access_method::options::ZDBIndexOptions;
 use crate::utils::{find_zdb_index, get_null_copy_to_fields};
+use crate::zdbquery::SortDirection;
 use crate::zql::parser::Token;
 use crate::zql::relationship_manager::RelationshipManager;
 use crate::zql::transformations::expand::expand;
@@ -135,6 +136,19 @@ pub enum Term<'input> {
     ProximityChain(Vec<ProximityPart>),
 }
 
+/// Query-wide settings given in ZQL via `#limit()`, `#offset()`, `#sort()`, `#min_score()`,
+/// `#highlight()`, and `#time_zone()`.  These are collected by the parser rather than being
+/// part of the `Expr` tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    Limit(u64),
+    Offset(u64),
+    MinScore(f64),
+    Sort(Vec<(String, SortDirection)>),
+    Highlight(Vec<String>),
+    TimeZone(String),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr<'input> {
     Null,
@@ -221,6 +235,52 @@ impl<'input> Term<'input> {
         }
     }
 
+    /// Recognize a Lucene-style `[start TO end]` date range from the terms of an array.  Either
+    /// end can be `*` to leave that side of the range open.
+    ///
+    /// An array without commas, such as `[a TO b]`, has always been a list of three values, so
+    /// this only applies when the bounds are dates or date-math
+    pub(in crate::zql) fn maybe_range(
+        terms: &[(Term<'input>, Option<&'input str>)],
+    ) -> Option<(&'input str, &'input str)> {
+        match terms {
+            [(start, None), (Term::String(to, None), None), (end, _)]
+                if to.eq_ignore_ascii_case("to") =>
+            {
+                let (start, end) = (start.range_bound()?, end.range_bound()?);
+                if start == "*" && end == "*" {
+                    None
+                } else {
+                    Some((start, end))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn range_bound(&self) -> Option<&'input str> {
+        match self {
+            Term::String(s, None) | Term::Phrase(s, None) if Term::is_date_bound(s) => Some(s),
+            Term::MatchAll => Some("*"),
+            _ => None,
+        }
+    }
+
+    /// Is `s` a date, such as `2020-01-01` or `2020-01-01T12:00:00`, or date-math, such as
+    /// `now-7d` or `2020-01-01||+1M/d`?
+    fn is_date_bound(s: &str) -> bool {
+        let is_date = s.len() >= 10
+            && s.char_indices().take(10).all(|(i, c)| match i {
+                4 | 7 => c == '-',
+                _ => c.is_ascii_digit(),
+            });
+        let is_now = s
+            .strip_prefix("now")
+            .is_some_and(|math| math.is_empty() || math.starts_with(['-', '+', '/']));
+
+        is_date || is_now
+    }
+
     pub fn is_prefix_wildcard(s: &str) -> bool {
         s.ends_with('*')
             && s.chars().filter(|c| *c == '*').count() == 1
@@ -445,7 +505,32 @@ impl ProximityTerm {
     }
 }
 
-pub type ParserError<'input> = ParseError<usize, Token<'input>, &'static str>;
+pub type ParserError<'input> = ParseError<usize, Token<'input>, ZqlError>;
+
+/// A query that's syntactically correct but still can't be used, such as `#limit(-5)`.  `start`
+/// and `end` are the byte offsets of the offending part of the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZqlError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl ZqlError {
+    pub fn new(message: String, start: usize, end: usize) -> Self {
+        ZqlError {
+            message,
+            start,
+            end,
+        }
+    }
+}
+
+impl Display for ZqlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
 
 impl<'input> Expr<'input> {
     pub fn from_str(
@@ -455,6 +540,27 @@ impl<'input> Expr<'input> {
         index_links: &[IndexLink],
         target_link: &Option<IndexLink>,
         used_fields: &mut HashSet<&'input str>,
+    ) -> Result<Expr<'input>, ParserError<'input>> {
+        Expr::from_str_with_directives(
+            index,
+            default_fieldname,
+            input,
+            index_links,
+            target_link,
+            used_fields,
+            &mut Vec::new(),
+        )
+    }
+
+    /// Same as `Expr::from_str()`, but also collects any query directives, such as `#limit()`
+    pub fn from_str_with_directives(
+        index: &PgRelation,
+        default_fieldname: &'input str,
+        input: &'input str,
+        index_links: &[IndexLink],
+        target_link: &Option<IndexLink>,
+        used_fields: &mut HashSet<&'input str>,
+        directives: &mut Vec<Directive>,
     ) -> Result<Expr<'input>, ParserError<'input>> {
         if input.trim().is_empty() {
             // empty strings just match everything
@@ -473,6 +579,7 @@ impl<'input> Expr<'input> {
             index_links,
             target_link,
             zdboptions.field_lists(),
+            directives,
         )
     }
 
@@ -485,6 +592,7 @@ impl<'input> Expr<'input> {
         index_links: &[IndexLink],
         target_link: &Option<IndexLink>,
         mut field_lists: HashMap<String, Vec<QualifiedField>>,
+        directives: &mut Vec<Directive>,
     ) -> Result<Expr<'input>, ParserError<'input>> {
         let input = input;
         let mut operator_stack = vec![ComparisonOpcode::Contains];
@@ -496,6 +604,7 @@ impl<'input> Expr<'input> {
                 used_fields,
                 &mut fieldname_stack,
                 &mut operator_stack,
+                directives,
                 input,
             )
         })?;
@@ -777,6 +886,7 @@ impl IndexLink {
                     &mut HashSet::new(),
                     &mut Vec::new(),
                     &mut Vec::new(),
+                    &mut Vec::new(),
                     input,
                 )
                 .expect("failed to parse IndexLink")
@@ -806,6 +916,7 @@ impl IndexLink {
                                 &mut HashSet::new(),
                                 &mut Vec::new(),
                                 &mut Vec::new(),
+                                &mut Vec::new(),
                                 link.as_str(),
                             )
                             .expect("failed to parse index link")
@@ -833,6 +944,7 @@ impl IndexLink {
                             &mut used_fields,
                             &mut fieldname_stack,
                             &mut operator_stack,
+                            &mut Vec::new(),
                             link.as_str(),
                         )
                         .expect("failed to parse index link")
@@ -7,7 +7,8 @@ use serde_json::json;
 use crate::access_method::options::ZDBIndexOptions;
 use crate::elasticsearch::aggregates::terms::terms_array_agg;
 use crate::gucs::{ZDB_ACCELERATOR, ZDB_IGNORE_VISIBILITY};
-use crate::utils::{is_keyword_field, lookup_es_field_type};
+use crate::misc::timestamp_support::{is_date_math, resolve_date_math};
+use crate::utils::{has_date_subfield, is_date_field, is_keyword_field, lookup_es_field_type};
 use crate::zdbquery::mvcc::build_visibility_clause;
 use crate::zdbquery::ZDBQuery;
 use crate::zql::ast::{
@@ -58,62 +59,78 @@ pub fn expr_to_dsl(
     _root: &IndexLink,
     _index_links: &[IndexLink],
     expr: &Expr,
+) -> serde_json::Value {
+    expr_to_dsl_with_time_zone(_root, _index_links, expr, None)
+}
+
+/// Same as `expr_to_dsl()`, but ranges against date fields use the specified `time_zone`
+pub fn expr_to_dsl_with_time_zone(
+    _root: &IndexLink,
+    _index_links: &[IndexLink],
+    expr: &Expr,
+    time_zone: Option<&str>,
 ) -> serde_json::Value {
     match expr {
         Expr::Null => unreachable!(),
 
-        Expr::Subselect(link, e) => expr_to_dsl(link, _index_links, e),
-        Expr::Expand(link, e, _) => {
-            expr_to_dsl(link, _index_links, &Expr::Linked(link.clone(), e.clone()))
-        }
+        Expr::Subselect(link, e) => expr_to_dsl_with_time_zone(link, _index_links, e, time_zone),
+        Expr::Expand(link, e, _) => expr_to_dsl_with_time_zone(
+            link,
+            _index_links,
+            &Expr::Linked(link.clone(), e.clone()),
+            time_zone,
+        ),
 
         // AND and WITH output the same query DSL, but we want to maintain their differences in the AST
         Expr::AndList(v) | Expr::WithList(v) => {
             let dsl: Vec<serde_json::Value> = v
                 .iter()
-                .map(|v| expr_to_dsl(_root, _index_links, v))
+                .map(|v| expr_to_dsl_with_time_zone(_root, _index_links, v, time_zone))
                 .collect();
             json! { { "bool": { "must": dsl } } }
         }
         Expr::OrList(v) => {
             if v.len() == 1 {
-                expr_to_dsl(_root, _index_links, v.first().unwrap())
+                expr_to_dsl_with_time_zone(_root, _index_links, v.first().unwrap(), time_zone)
             } else {
                 let dsl: Vec<serde_json::Value> = v
                     .iter()
-                    .map(|v| expr_to_dsl(_root, _index_links, v))
+                    .map(|v| expr_to_dsl_with_time_zone(_root, _index_links, v, time_zone))
                     .collect();
                 json! { { "bool": { "should": dsl } } }
             }
         }
         Expr::Not(r) => {
-            let r = expr_to_dsl(_root, _index_links, r.as_ref());
+            let r = expr_to_dsl_with_time_zone(_root, _index_links, r.as_ref(), time_zone);
             json! { { "bool": { "must_not": [r] } } }
         }
-        Expr::Contains(f, t) | Expr::Eq(f, t) => term_to_dsl(f, t, ComparisonOpcode::Contains),
+        Expr::Contains(f, t) | Expr::Eq(f, t) => {
+            term_to_dsl(f, t, ComparisonOpcode::Contains, time_zone)
+        }
         Expr::DoesNotContain(f, t) | Expr::Ne(f, t) => {
-            term_to_dsl(f, t, ComparisonOpcode::DoesNotContain)
+            term_to_dsl(f, t, ComparisonOpcode::DoesNotContain, time_zone)
         }
-        Expr::Regex(f, t) => term_to_dsl(f, t, ComparisonOpcode::Regex),
+        Expr::Regex(f, t) => term_to_dsl(f, t, ComparisonOpcode::Regex, time_zone),
         Expr::MoreLikeThis(_, _) => unimplemented!("more like this is not implemented yet"),
         Expr::FuzzyLikeThis(_, _) => unimplemented!("fuzzy like this is not implemented yet"),
 
-        Expr::Matches(f, t) => term_to_dsl(f, t, ComparisonOpcode::Matches),
+        Expr::Matches(f, t) => term_to_dsl(f, t, ComparisonOpcode::Matches, time_zone),
 
-        Expr::Gt(f, t) => term_to_dsl(f, t, ComparisonOpcode::Gt),
-        Expr::Gte(f, t) => term_to_dsl(f, t, ComparisonOpcode::Gte),
-        Expr::Lt(f, t) => term_to_dsl(f, t, ComparisonOpcode::Lt),
-        Expr::Lte(f, t) => term_to_dsl(f, t, ComparisonOpcode::Lte),
+        Expr::Gt(f, t) => term_to_dsl(f, t, ComparisonOpcode::Gt, time_zone),
+        Expr::Gte(f, t) => term_to_dsl(f, t, ComparisonOpcode::Gte, time_zone),
+        Expr::Lt(f, t) => term_to_dsl(f, t, ComparisonOpcode::Lt, time_zone),
+        Expr::Lte(f, t) => term_to_dsl(f, t, ComparisonOpcode::Lte, time_zone),
 
         Expr::Json(json) => serde_json::from_str(json).expect("failed to parse json expression"),
 
         Expr::Nested(p, e) => {
-            let dsl = expr_to_dsl(_root, _index_links, e.as_ref());
+            let dsl = expr_to_dsl_with_time_zone(_root, _index_links, e.as_ref(), time_zone);
             json! { { "nested": { "path": p, "query": dsl, "score_mode": "avg", "ignore_unmapped": false } } }
         }
 
         Expr::Linked(link, e) => {
-            let mut query_dsl = expr_to_dsl(_root, _index_links, e.as_ref());
+            let mut query_dsl =
+                expr_to_dsl_with_time_zone(_root, _index_links, e.as_ref(), time_zone);
             if link.left_field.is_none() {
                 query_dsl
             } else {
@@ -189,31 +206,36 @@ pub fn term_to_dsl(
     field: &QualifiedField,
     term: &Term,
     opcode: ComparisonOpcode,
+    time_zone: Option<&str>,
 ) -> serde_json::Value {
     match opcode {
-        ComparisonOpcode::Contains | ComparisonOpcode::Eq => eq(field, term, false),
+        ComparisonOpcode::Contains | ComparisonOpcode::Eq => match term {
+            Term::Range(s, e, b) => range_to_dsl(field, &[("gte", *s), ("lte", *e)], b, time_zone),
+            _ => eq(field, term, false),
+        },
 
         ComparisonOpcode::DoesNotContain | ComparisonOpcode::Ne => {
-            json! { { "bool": { "must_not": [ eq(field, term, false) ] } } }
+            let clause = term_to_dsl(field, term, ComparisonOpcode::Contains, time_zone);
+            json! { { "bool": { "must_not": [ clause ] } } }
         }
 
         ComparisonOpcode::Regex => regex(field, term),
 
         ComparisonOpcode::Gt => {
             let (v, b) = range(term);
-            json! { { "range": { field.field_name(): { "gt": v, "boost": b.unwrap_or(1.0) }} } }
+            range_to_dsl(field, &[("gt", v)], b, time_zone)
         }
         ComparisonOpcode::Lt => {
             let (v, b) = range(term);
-            json! { { "range": { field.field_name(): { "lt": v, "boost": b.unwrap_or(1.0) }} } }
+            range_to_dsl(field, &[("lt", v)], b, time_zone)
         }
         ComparisonOpcode::Gte => {
             let (v, b) = range(term);
-            json! { { "range": { field.field_name(): { "gte": v, "boost": b.unwrap_or(1.0) }} } }
+            range_to_dsl(field, &[("gte", v)], b, time_zone)
         }
         ComparisonOpcode::Lte => {
             let (v, b) = range(term);
-            json! { { "range": { field.field_name(): { "lte": v, "boost": b.unwrap_or(1.0) }} } }
+            range_to_dsl(field, &[("lte", v)], b, time_zone)
         }
         ComparisonOpcode::Matches => match term {
             Term::String(s, b)
@@ -341,9 +363,7 @@ fn eq(field: &QualifiedField, term: &Term, is_span: bool) -> serde_json::Value {
             json! { { "regexp": { field.field_name(): { "value": r, "boost": b.unwrap_or(1.0) } } } }
         }
 
-        Term::Range(s, e, b) => {
-            json! { { "range": { field.field_name(): { "gte": s, "lte": e, "boost": b.unwrap_or(1.0) }} } }
-        }
+        Term::Range(s, e, b) => range_to_dsl(field, &[("gte", *s), ("lte", *e)], b, None),
         Term::ParsedArray(v, _b) => {
             let mut clauses = Vec::new();
 
@@ -438,6 +458,50 @@ fn proximity_chain(field: &QualifiedField, parts: &[ProximityPart]) -> serde_jso
     span_near.expect("did not generate a span_near clause")
 }
 
+/// Build a `range` query with the given `(operator, value)` bounds.  For date fields, any
+/// date-math in the bounds has its `now` resolved to the start of the current transaction, and
+/// the query uses `time_zone`.  A bound of `*` leaves that side of the range open
+fn range_to_dsl(
+    field: &QualifiedField,
+    bounds: &[(&str, &str)],
+    boost: &Option<f32>,
+    time_zone: Option<&str>,
+) -> serde_json::Value {
+    let mut field_name = field.field_name();
+    let mut is_date = false;
+
+    if time_zone.is_some() || bounds.iter().any(|(_, v)| is_date_math(v)) {
+        if let Some(index) = field.index.as_ref() {
+            let indexrel = index.open_index().unwrap();
+            if is_date_field(&indexrel, &field_name) {
+                is_date = true;
+            } else if has_date_subfield(&indexrel, &field_name) {
+                field_name = format!("{}.date", field_name);
+                is_date = true;
+            }
+        }
+    }
+
+    let mut range = serde_json::Map::new();
+    for (op, value) in bounds {
+        if *value == "*" {
+            continue;
+        } else if is_date {
+            range.insert(op.to_string(), json!(resolve_date_math(value)));
+        } else {
+            range.insert(op.to_string(), json!(value));
+        }
+    }
+    if is_date {
+        if let Some(time_zone) = time_zone {
+            range.insert("time_zone".into(), json!(time_zone));
+        }
+    }
+    range.insert("boost".into(), json!(boost.unwrap_or(1.0)));
+
+    json! { { "range": { field_name: range } } }
+}
+
 fn range<'a>(term: &'a Term) -> (&'a str, &'a Option<f32>) {
     match term {
         Term::String(s, b) | Term::Phrase(s, b) => (s, b),
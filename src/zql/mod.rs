@@ -36,6 +36,7 @@ pub(crate) fn parse_field_lists(input: &str) -> HashMap<String, Vec<QualifiedFie
                 &mut used_fields,
                 &mut fieldname_stack,
                 &mut operator_stack,
+                &mut Vec::new(),
                 input,
             )
             .expect("failed to parse field lists")
@@ -398,9 +399,19 @@ mod tests {
 
     use pgrx::*;
 
-    use crate::zql::ast::{Expr, IndexLink, ParserError, QualifiedIndex};
+    use crate::zdbquery::SortDirection;
+    use crate::zql::ast::{
+        Directive, Expr, IndexLink, ParserError, QualifiedField, QualifiedIndex, Term,
+    };
 
     pub(super) fn parse(input: &str) -> Result<Expr, ParserError> {
+        parse_with_directives(input, &mut Vec::new())
+    }
+
+    fn parse_with_directives<'input>(
+        input: &'input str,
+        directives: &mut Vec<Directive>,
+    ) -> Result<Expr<'input>, ParserError<'input>> {
         let mut used_fields = HashSet::new();
         Expr::from_str_disconnected(
             None,
@@ -420,6 +431,7 @@ mod tests {
             &Vec::new(),
             &None,
             HashMap::new(),
+            directives,
         )
     }
 
@@ -931,4 +943,105 @@ mod tests {
             ),
         )
     }
+
+    #[pg_test]
+    fn test_directives() {
+        let mut directives = Vec::new();
+        let expr = parse_with_directives(
+            "#limit(20) foo #offset(10) #sort(date desc, `weird:field`, title ASC) #min_score(0.5) #highlight(title, body)",
+            &mut directives,
+        )
+        .expect("failed to parse");
+
+        assert_eq!(expr, String!(Contains, "_", "foo"));
+        assert_eq!(
+            directives,
+            vec![
+                Directive::Limit(20),
+                Directive::Offset(10),
+                Directive::Sort(vec![
+                    ("date".into(), SortDirection::desc),
+                    ("weird:field".into(), SortDirection::asc),
+                    ("title".into(), SortDirection::asc),
+                ]),
+                Directive::MinScore(0.5),
+                Directive::Highlight(vec!["title".into(), "body".into()]),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_directives_only() {
+        let mut directives = Vec::new();
+        let expr = parse_with_directives("#limit(5)", &mut directives).expect("failed to parse");
+
+        assert_eq!(expr, Expr::Json(r#"{"match_all":{}}"#.to_string()));
+        assert_eq!(directives, vec![Directive::Limit(5)]);
+    }
+
+    #[pg_test]
+    fn test_directives_not_nested() {
+        assert!(parse("foo AND (bar #limit(5))").is_err());
+    }
+
+    #[pg_test]
+    fn test_date_math() {
+        assert_expr("created:>now-7d", String!(Gt, "created", "now-7d"));
+        assert_expr(
+            "created:<=2020-01-01||+1M/d",
+            String!(Lte, "created", "2020-01-01||+1M/d"),
+        );
+    }
+
+    #[pg_test]
+    fn test_bracketed_range() {
+        assert_expr(
+            "due:[now/d TO now+1M]",
+            Expr::Contains(
+                QualifiedField {
+                    index: Some(IndexLink::default()),
+                    field: "due".into(),
+                },
+                Term::Range("now/d", "now+1M", None),
+            ),
+        );
+        assert_expr(
+            "due:[2020-01-01 to *]",
+            Expr::Contains(
+                QualifiedField {
+                    index: Some(IndexLink::default()),
+                    field: "due".into(),
+                },
+                Term::Range("2020-01-01", "*", None),
+            ),
+        );
+    }
+
+    #[pg_test]
+    fn test_bracketed_non_date_range_is_array() {
+        // arrays without commas are still arrays, unless their bounds are dates
+        assert_expr(
+            "field:[a TO b]",
+            ParsedArray!(Contains, "field", String!("a"), String!("TO"), String!("b")),
+        );
+        assert_expr(
+            "field:[a to *]",
+            ParsedArray!(
+                Contains,
+                "field",
+                String!("a"),
+                String!("to"),
+                Term::MatchAll
+            ),
+        );
+    }
+
+    #[pg_test]
+    fn test_time_zone_directive() {
+        let mut directives = Vec::new();
+        parse_with_directives("created:>=now/d #time_zone(\"-05:00\")", &mut directives)
+            .expect("failed to parse");
+
+        assert_eq!(directives, vec![Directive::TimeZone("-05:00".into())]);
+    }
 }
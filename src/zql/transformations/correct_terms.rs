@@ -0,0 +1,131 @@
+use crate::zql::ast::{Expr, QualifiedField, Term};
+use std::collections::HashMap;
+
+/// Collect the field and value of every `Term::String` the query compares a field to, in the
+/// order they appear
+pub fn find_string_terms<'input>(
+    expr: &Expr<'input>,
+    terms: &mut Vec<(QualifiedField, &'input str)>,
+) {
+    match expr {
+        Expr::Null => unreachable!(),
+
+        Expr::Subselect(_, e) | Expr::Linked(_, e) | Expr::Nested(_, e) | Expr::Not(e) => {
+            find_string_terms(e, terms)
+        }
+        Expr::Expand(_, e, f) => {
+            find_string_terms(e, terms);
+            if let Some(filter) = f {
+                find_string_terms(filter, terms);
+            }
+        }
+        Expr::WithList(v) | Expr::AndList(v) | Expr::OrList(v) => {
+            v.iter().for_each(|e| find_string_terms(e, terms))
+        }
+
+        Expr::Contains(f, t) | Expr::Eq(f, t) | Expr::Ne(f, t) | Expr::DoesNotContain(f, t) => {
+            match t {
+                Term::String(s, _) => terms.push((f.clone(), *s)),
+                Term::ParsedArray(elements, _) => elements.iter().for_each(|t| {
+                    if let Term::String(s, _) = t {
+                        terms.push((f.clone(), *s))
+                    }
+                }),
+                _ => {}
+            }
+        }
+
+        _ => {}
+    }
+}
+
+/// Replace the value of each `Term::String` found by [`find_string_terms`] with its correction
+/// for that field, if there is one
+pub fn correct_string_terms<'a>(
+    expr: &mut Expr<'a>,
+    corrections: &'a HashMap<(QualifiedField, String), String>,
+) {
+    match expr {
+        Expr::Null => unreachable!(),
+
+        Expr::Subselect(_, e) | Expr::Linked(_, e) | Expr::Nested(_, e) | Expr::Not(e) => {
+            correct_string_terms(e, corrections)
+        }
+        Expr::Expand(_, e, f) => {
+            correct_string_terms(e, corrections);
+            if let Some(filter) = f {
+                correct_string_terms(filter, corrections);
+            }
+        }
+        Expr::WithList(v) | Expr::AndList(v) | Expr::OrList(v) => v
+            .iter_mut()
+            .for_each(|e| correct_string_terms(e, corrections)),
+
+        Expr::Contains(f, t) | Expr::Eq(f, t) | Expr::Ne(f, t) | Expr::DoesNotContain(f, t) => {
+            match t {
+                Term::ParsedArray(elements, _) => elements
+                    .iter_mut()
+                    .for_each(|t| correct_term(f, t, corrections)),
+                t => correct_term(f, t, corrections),
+            }
+        }
+
+        _ => {}
+    }
+}
+
+fn correct_term<'a>(
+    field: &QualifiedField,
+    term: &mut Term<'a>,
+    corrections: &'a HashMap<(QualifiedField, String), String>,
+) {
+    if let Term::String(s, _) = term {
+        if let Some(correction) = corrections.get(&(field.clone(), s.to_string())) {
+            *s = correction.as_str();
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use crate::zql::tests::parse;
+    use crate::zql::transformations::correct_terms::{correct_string_terms, find_string_terms};
+    use pgrx::*;
+    use std::collections::HashMap;
+
+    #[pg_test]
+    fn test_correct_string_terms() {
+        let expr =
+            parse("title:quikc AND body:[brwn, fox, \"slow turtle\"]").expect("failed to parse");
+
+        let mut terms = Vec::new();
+        find_string_terms(&expr, &mut terms);
+        assert_eq!(
+            terms
+                .iter()
+                .map(|(field, term)| (field.field.as_str(), *term))
+                .collect::<Vec<_>>(),
+            vec![("title", "quikc"), ("body", "brwn"), ("body", "fox")]
+        );
+
+        let corrections = terms
+            .into_iter()
+            .filter_map(|(field, term)| {
+                let correction = match term {
+                    "quikc" => "quick",
+                    "brwn" => "brown",
+                    _ => return None,
+                };
+                Some(((field, term.to_string()), correction.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut expr = expr;
+        correct_string_terms(&mut expr, &corrections);
+        assert_eq!(
+            format!("{}", expr),
+            r#"(title:"quick" AND body:["brown","fox","slow turtle"])"#
+        );
+    }
+}
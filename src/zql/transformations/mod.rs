@@ -1,3 +1,4 @@
+pub(crate) mod correct_terms;
 pub(crate) mod expand;
 pub(crate) mod expand_index_links;
 pub(crate) mod field_finder;
@@ -1,3 +1,16 @@
 CREATE TABLE foo AS SELECT * FROM events LIMIT 10;
 CREATE INDEX CONCURRENTLY idxfoo ON foo USING zombodb ((foo.*));
-DROP TABLE foo CASCADE;
\ No newline at end of file
+SELECT assert(count(*), 10, 'create index concurrently') FROM foo WHERE foo ==> dsl.match_all();
+INSERT INTO foo SELECT * FROM events LIMIT 5;
+REINDEX INDEX CONCURRENTLY idxfoo;
+SELECT assert(count(*), 15, 'reindex concurrently') FROM foo WHERE foo ==> dsl.match_all();
+DROP TABLE foo CASCADE;
+CREATE TABLE bar AS SELECT * FROM events ORDER BY id LIMIT 10;
+BEGIN;
+UPDATE bar SET event_type = 'rolled back' WHERE id IN (SELECT id FROM bar ORDER BY id LIMIT 3);
+DELETE FROM bar WHERE id IN (SELECT id FROM bar ORDER BY id DESC LIMIT 2);
+ROLLBACK;
+CREATE INDEX CONCURRENTLY idxbar ON bar USING zombodb ((bar.*));
+SELECT assert(count(*), 10, 'rolled back changes before create index concurrently') FROM bar WHERE bar ==> dsl.match_all();
+SELECT assert(count(*), 0, 'rolled back update not indexed') FROM bar WHERE bar ==> 'event_type:"rolled back"';
+DROP TABLE bar CASCADE;
@@ -0,0 +1,24 @@
+CREATE TYPE test_hot_idx AS (id bigint, title text);
+CREATE TABLE test_hot (id bigint, title text, hits bigint) WITH (fillfactor = 50);
+INSERT INTO test_hot VALUES (1, 'cats and dogs', 0), (2, 'dogs', 0);
+CREATE INDEX idxtest_hot ON test_hot USING zombodb ((ROW(id, title)::test_hot_idx));
+
+-- these only change an unindexed column, so they're HOT updates
+UPDATE test_hot SET hits = hits + 1 WHERE id = 1;
+UPDATE test_hot SET hits = hits + 1;
+
+SELECT id, ctid, hits, zdb.score(ctid) > 0 AS scored, zdb.highlight(ctid, 'title')
+  FROM test_hot
+ WHERE test_hot ==> 'title:cats'
+ ORDER BY id;
+SELECT id, ctid, hits, zdb.score(ctid) > 0 AS scored FROM test_hot WHERE test_hot ==> 'title:dogs' ORDER BY id;
+
+-- a HOT update leaves nothing for the next INSERT in the same transaction to pick up
+BEGIN;
+UPDATE test_hot SET hits = hits + 1 WHERE id = 2;
+INSERT INTO test_hot VALUES (3, 'cats and birds', 0);
+COMMIT;
+SELECT id, hits FROM test_hot WHERE test_hot ==> 'title:(cats OR dogs)' ORDER BY id;
+
+DROP TABLE test_hot;
+DROP TYPE test_hot_idx;
@@ -0,0 +1,12 @@
+CREATE TABLE multi AS SELECT * FROM events ORDER BY id LIMIT 10;
+CREATE INDEX idxmulti_one ON multi USING zombodb ((multi.*));
+CREATE INDEX idxmulti_two ON multi USING zombodb ((multi.*)) WITH (exclude_columns='id');
+INSERT INTO multi SELECT * FROM events ORDER BY id OFFSET 10 LIMIT 5;
+SELECT assert(count(*), 15, 'first index') FROM multi WHERE multi ==> dsl.field_exists('id');
+SELECT assert(count(*), 15, 'second index') FROM multi WHERE multi ==> dsl.index('idxmulti_two', dsl.match_all());
+SELECT assert(count(*), 0, 'second index excludes id') FROM multi WHERE multi ==> dsl.index('idxmulti_two', dsl.field_exists('id'));
+SELECT assert(zdb.count('idxmulti_two', dsl.match_all()), 15, 'aggregate on second index');
+DROP INDEX idxmulti_one;
+DELETE FROM multi WHERE id IN (SELECT id FROM multi ORDER BY id LIMIT 3);
+SELECT assert(zdb.count('idxmulti_two', dsl.match_all()), 12, 'triggers moved to second index');
+DROP TABLE multi CASCADE;
@@ -0,0 +1,55 @@
+CREATE TABLE test_prepared (id bigint, title text);
+CREATE INDEX idxtest_prepared ON test_prepared USING zombodb ((test_prepared.*));
+
+-- a prepared transaction's rows only become visible once another session commits it
+BEGIN;
+INSERT INTO test_prepared VALUES (1, 'committed');
+PREPARE TRANSACTION 'zdb_test_commit';
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:committed';
+\c
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:committed';
+COMMIT PREPARED 'zdb_test_commit';
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:committed';
+SELECT count(*) FROM zdb.prepared_transactions;
+SELECT aborted_xids FROM zdb.index_stats WHERE pg_index_name = 'idxtest_prepared'::regclass;
+
+-- a rolled back one leaves its xid aborted
+BEGIN;
+INSERT INTO test_prepared VALUES (2, 'rolled back');
+PREPARE TRANSACTION 'zdb_test_rollback';
+\c
+ROLLBACK PREPARED 'zdb_test_rollback';
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:rolled';
+SELECT count(*) FROM zdb.prepared_transactions;
+SELECT aborted_xids FROM zdb.index_stats WHERE pg_index_name = 'idxtest_prepared'::regclass;
+
+-- committed by a session that hasn't loaded ZomboDB, so it has to be finished by hand
+BEGIN;
+INSERT INTO test_prepared VALUES (3, 'recovered');
+PREPARE TRANSACTION 'zdb_test_recover';
+\c
+COMMIT PREPARED 'zdb_test_recover';
+SELECT count(*) FROM zdb.prepared_transactions;
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:recovered';
+SELECT zdb.finish_prepared_transactions();
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:recovered';
+SELECT count(*) FROM zdb.prepared_transactions;
+
+-- only ZomboDB changes zdb.prepared_transactions, even for a role that prepares transactions
+DROP ROLE IF EXISTS zdb_test_prepared;
+CREATE ROLE zdb_test_prepared;
+GRANT ALL ON test_prepared TO zdb_test_prepared;
+SET ROLE zdb_test_prepared;
+DELETE FROM zdb.prepared_transactions;
+BEGIN;
+INSERT INTO test_prepared VALUES (4, 'unprivileged');
+PREPARE TRANSACTION 'zdb_test_unprivileged';
+\c
+SET ROLE zdb_test_prepared;
+COMMIT PREPARED 'zdb_test_unprivileged';
+SELECT count(*) FROM test_prepared WHERE test_prepared ==> 'title:unprivileged';
+RESET ROLE;
+SELECT count(*) FROM zdb.prepared_transactions;
+
+DROP TABLE test_prepared;
+DROP ROLE zdb_test_prepared;
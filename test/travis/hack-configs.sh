@@ -9,5 +9,6 @@ fsync=off
 zdb.default_elasticsearch_url = 'http://localhost:9200/'
 zdb.log_level = LOG
 zdb.default_replicas = 0
+max_prepared_transactions = 10
 DONE
 